@@ -1,15 +1,30 @@
 use anyhow::Error as AnyError;
-use http::Uri;
+use http::{StatusCode, Uri};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::convert::From;
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::prelude::*;
-use std::path::Path;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use thiserror::Error;
-use toml;
+use tracer_client::auth::{Auth, AwsSigV4};
+use tracer_client::compression::{
+    BodyRepresentation, BodyRepresentationParseError, CompressionOptions, ContentEncoding,
+    ContentEncodingParseError,
+};
+use tracer_client::cookies::CookieJar;
+use tracer_client::error::ErrorKindParseError;
+use tracer_client::proxy::{NoProxy, Proxy, ProxyAuth, ProxyError, ProxyOptions};
+use tracer_client::redirect::{MethodRewrite, MethodRewriteError, RedirectOptions};
+use tracer_client::retry::RetryOptions;
+use tracer_client::socket::SocketOptions;
+use tracer_client::timeouts::Timeouts;
+use tracer_client::tls::{self, TlsConfigError, TlsOptions, TlsVersion};
+use tracer_client::{ClientOptions, ErrorKind, HttpVersion};
 
 #[derive(Debug, Deserialize, Clone)]
 #[serde(untagged)]
@@ -44,6 +59,581 @@ pub struct CaptureHeaderFileConfig {
     list: Option<Vec<String>>,
 }
 
+/// Timeouts in milliseconds
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeoutFileConfig {
+    dns: Option<u64>,
+    connect: Option<u64>,
+    tls: Option<u64>,
+    headers: Option<u64>,
+    body_read: Option<u64>,
+    total: Option<u64>,
+}
+
+/// Certificate verification settings.  Paths are relative to the config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TlsFileConfig {
+    /// PEM file of CA certificates to trust
+    pub cacert: Option<String>,
+    /// Directory of PEM files with CA certificates to trust
+    pub capath: Option<String>,
+    /// Trust the operating system's CA certificates
+    pub ca_native: Option<bool>,
+    /// Skip certificate verification entirely
+    pub insecure: Option<bool>,
+    /// Name to send via SNI and verify the certificate against, instead of the URL host
+    pub server_name: Option<String>,
+    /// Resume TLS sessions from earlier repetitions
+    pub resume: Option<bool>,
+    /// Send requests on resumed HTTP/3 connections as 0-RTT early data
+    pub early_data: Option<bool>,
+    /// Only offer these TLS versions, e.g. `["1.3"]`
+    pub versions: Option<Vec<String>>,
+    /// Only offer these cipher suites
+    pub cipher_suites: Option<Vec<String>>,
+    /// Client certificate chain to present, PEM unless `cert_type` says otherwise
+    pub cert: Option<String>,
+    /// PEM private key for `cert`, if it isn't in the same file
+    pub key: Option<String>,
+    pub cert_type: Option<CertTypeFileConfig>,
+    /// Password of a PKCS#12 `cert`
+    pub cert_password: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum CertTypeFileConfig {
+    #[serde(rename = "pem")]
+    Pem,
+    #[serde(rename = "p12")]
+    P12,
+}
+
+impl TlsFileConfig {
+    /// Fill in keys this section leaves out from `defaults`.  The client certificate keys are
+    /// taken together, from whichever section sets `cert`.
+    fn or(self, defaults: &TlsFileConfig) -> TlsFileConfig {
+        let client = if self.cert.is_some() { &self } else { defaults };
+        TlsFileConfig {
+            cacert: self.cacert.clone().or_else(|| defaults.cacert.clone()),
+            capath: self.capath.clone().or_else(|| defaults.capath.clone()),
+            ca_native: self.ca_native.or(defaults.ca_native),
+            insecure: self.insecure.or(defaults.insecure),
+            server_name: self
+                .server_name
+                .clone()
+                .or_else(|| defaults.server_name.clone()),
+            resume: self.resume.or(defaults.resume),
+            early_data: self.early_data.or(defaults.early_data),
+            versions: self.versions.clone().or_else(|| defaults.versions.clone()),
+            cipher_suites: self
+                .cipher_suites
+                .clone()
+                .or_else(|| defaults.cipher_suites.clone()),
+            cert: client.cert.clone(),
+            key: client.key.clone(),
+            cert_type: client.cert_type,
+            cert_password: client.cert_password.clone(),
+        }
+    }
+
+    /// Load the configured CA certificates.  Without any, the bundled Mozilla roots are trusted.
+    pub fn load(&self, parent: &Path) -> Result<TlsOptions, TlsConfigError> {
+        let mut roots = Vec::new();
+        if let Some(ref f) = self.cacert {
+            roots.extend(tls::load_pem_file(parent.join(f))?);
+        }
+        if let Some(ref d) = self.capath {
+            roots.extend(tls::load_pem_dir(parent.join(d))?);
+        }
+        if self.ca_native.unwrap_or(false) {
+            roots.extend(tls::load_native_roots()?);
+        }
+        let client_cert = match (&self.cert, self.cert_type) {
+            (Some(cert), Some(CertTypeFileConfig::P12)) => {
+                let password = self.cert_password.as_deref().unwrap_or("");
+                Some(tls::load_pkcs12(parent.join(cert), password)?)
+            }
+            (Some(cert), _) => {
+                let key = self.key.as_ref().unwrap_or(cert);
+                Some(tls::load_client_cert(parent.join(cert), parent.join(key))?)
+            }
+            (None, _) => None,
+        };
+        let versions = match self.versions {
+            Some(ref versions) => Some(
+                versions
+                    .iter()
+                    .map(|v| v.parse())
+                    .collect::<Result<Vec<TlsVersion>, _>>()?,
+            ),
+            None => None,
+        };
+        let cipher_suites = match self.cipher_suites {
+            Some(ref names) => Some(
+                names
+                    .iter()
+                    .map(|n| tls::find_cipher_suite(n).map(|s| s.name))
+                    .collect::<Result<Vec<String>, _>>()?,
+            ),
+            None => None,
+        };
+        Ok(TlsOptions {
+            roots: if roots.is_empty() { None } else { Some(roots) },
+            insecure: self.insecure.unwrap_or(false),
+            client_cert,
+            resume: self.resume.unwrap_or(false),
+            early_data: self.early_data.unwrap_or(false),
+            server_name: self.server_name.clone(),
+            versions,
+            cipher_suites,
+        })
+    }
+}
+
+/// Proxy settings.  Proxies from the environment are used for whatever this leaves out.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ProxyFileConfig {
+    /// Proxy for all URLs, instead of those from `http_proxy`/`https_proxy`/`all_proxy`
+    pub url: Option<String>,
+    /// Proxy credentials as `user:password`
+    pub user: Option<String>,
+    /// Comma separated hosts to connect to directly, instead of `no_proxy`
+    pub no_proxy: Option<String>,
+}
+
+impl ProxyFileConfig {
+    /// Fill in keys this section leaves out from `defaults`
+    fn or(self, defaults: &ProxyFileConfig) -> ProxyFileConfig {
+        ProxyFileConfig {
+            url: self.url.or_else(|| defaults.url.clone()),
+            user: self.user.or_else(|| defaults.user.clone()),
+            no_proxy: self.no_proxy.or_else(|| defaults.no_proxy.clone()),
+        }
+    }
+
+    pub fn load(&self) -> Result<ProxyOptions, ProxyError> {
+        let mut options = ProxyOptions::from_env()?;
+        if let Some(ref url) = self.url {
+            options = ProxyOptions {
+                no_proxy: options.no_proxy,
+                ..ProxyOptions::all(Proxy::parse(url)?)
+            };
+        }
+        if let Some(ref user) = self.user {
+            let auth: ProxyAuth = user.parse()?;
+            for proxy in options.http.iter_mut().chain(options.https.iter_mut()) {
+                proxy.auth = Some(auth.clone());
+            }
+        }
+        if let Some(ref no_proxy) = self.no_proxy {
+            options.no_proxy = NoProxy::parse(no_proxy);
+        }
+        Ok(options)
+    }
+}
+
+/// Source address and TCP options
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SocketFileConfig {
+    pub local_address: Option<String>,
+    pub local_port: Option<u16>,
+    /// Network interface name, Linux only
+    pub interface: Option<String>,
+    /// Disable Nagle's algorithm, on by default
+    pub nodelay: Option<bool>,
+    /// milliseconds of idle time before keepalive probes
+    pub keepalive: Option<u64>,
+    pub send_buffer: Option<usize>,
+    pub recv_buffer: Option<usize>,
+    pub ttl: Option<u32>,
+    pub dscp: Option<u8>,
+}
+
+impl SocketFileConfig {
+    /// Fill in keys this section leaves out from `defaults`
+    fn or(self, defaults: &SocketFileConfig) -> SocketFileConfig {
+        SocketFileConfig {
+            local_address: self
+                .local_address
+                .or_else(|| defaults.local_address.clone()),
+            local_port: self.local_port.or(defaults.local_port),
+            interface: self.interface.or_else(|| defaults.interface.clone()),
+            nodelay: self.nodelay.or(defaults.nodelay),
+            keepalive: self.keepalive.or(defaults.keepalive),
+            send_buffer: self.send_buffer.or(defaults.send_buffer),
+            recv_buffer: self.recv_buffer.or(defaults.recv_buffer),
+            ttl: self.ttl.or(defaults.ttl),
+            dscp: self.dscp.or(defaults.dscp),
+        }
+    }
+
+    pub fn load(&self) -> Result<SocketOptions, ConfigError> {
+        let local_address = match self.local_address {
+            Some(ref addr) => Some(
+                addr.parse::<IpAddr>()
+                    .map_err(|_| ConfigError::InvalidLocalAddress(addr.clone()))?,
+            ),
+            None => None,
+        };
+        if let Some(dscp) = self.dscp.filter(|d| *d > 63) {
+            return Err(ConfigError::InvalidDscp(dscp));
+        }
+        Ok(SocketOptions {
+            local_address,
+            local_port: self.local_port,
+            interface: self.interface.clone(),
+            nodelay: self.nodelay.unwrap_or(true),
+            keepalive: self.keepalive.map(Duration::from_millis),
+            send_buffer_size: self.send_buffer,
+            recv_buffer_size: self.recv_buffer,
+            ttl: self.ttl,
+            dscp: self.dscp,
+        })
+    }
+}
+
+/// Redirect following, off unless `follow` is set
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RedirectFileConfig {
+    pub follow: Option<bool>,
+    /// Most redirects to follow, 10 by default
+    pub max: Option<usize>,
+    /// Send the body again on 307 and 308, on by default
+    pub resend_body: Option<bool>,
+    /// Keep credentials when redirected to another host
+    pub trusted: Option<bool>,
+    /// browser, strict or keep
+    pub method: Option<String>,
+}
+
+impl RedirectFileConfig {
+    /// Fill in keys this section leaves out from `defaults`
+    fn or(self, defaults: &RedirectFileConfig) -> RedirectFileConfig {
+        RedirectFileConfig {
+            follow: self.follow.or(defaults.follow),
+            max: self.max.or(defaults.max),
+            resend_body: self.resend_body.or(defaults.resend_body),
+            trusted: self.trusted.or(defaults.trusted),
+            method: self.method.or_else(|| defaults.method.clone()),
+        }
+    }
+
+    pub fn load(&self) -> Result<RedirectOptions, ConfigError> {
+        let max_redirects = if self.follow.unwrap_or(false) {
+            self.max.unwrap_or(10)
+        } else {
+            0
+        };
+        let method = match self.method {
+            Some(ref method) => method.parse::<MethodRewrite>()?,
+            None => MethodRewrite::default(),
+        };
+        Ok(RedirectOptions {
+            max_redirects,
+            resend_body: self.resend_body.unwrap_or(true),
+            trusted: self.trusted.unwrap_or(false),
+            method,
+        })
+    }
+}
+
+/// Retry policy, off unless `retries` is set
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RetryFileConfig {
+    /// Most times to send a request again
+    pub retries: Option<usize>,
+    /// Failure classes to retry, like "connection-refused" or "timeout"
+    pub errors: Option<Vec<String>>,
+    /// Response statuses to retry
+    pub statuses: Option<Vec<u16>>,
+    /// milliseconds before the first retry, doubled for each after it
+    pub backoff: Option<u64>,
+    /// milliseconds
+    pub max_backoff: Option<u64>,
+    pub jitter: Option<bool>,
+}
+
+impl RetryFileConfig {
+    /// Fill in keys this section leaves out from `defaults`
+    fn or(self, defaults: &RetryFileConfig) -> RetryFileConfig {
+        RetryFileConfig {
+            retries: self.retries.or(defaults.retries),
+            errors: self.errors.or_else(|| defaults.errors.clone()),
+            statuses: self.statuses.or_else(|| defaults.statuses.clone()),
+            backoff: self.backoff.or(defaults.backoff),
+            max_backoff: self.max_backoff.or(defaults.max_backoff),
+            jitter: self.jitter.or(defaults.jitter),
+        }
+    }
+
+    pub fn load(&self) -> Result<RetryOptions, ConfigError> {
+        let mut options = RetryOptions::default();
+        if let Some(retries) = self.retries {
+            options.max_attempts = retries + 1;
+        }
+        if let Some(ref errors) = self.errors {
+            options.errors = errors
+                .iter()
+                .map(|e| e.parse::<ErrorKind>())
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(ref statuses) = self.statuses {
+            options.statuses = statuses
+                .iter()
+                .map(|s| StatusCode::from_u16(*s).map_err(|_| ConfigError::InvalidStatus(*s)))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(backoff) = self.backoff {
+            options.backoff = Duration::from_millis(backoff);
+        }
+        if let Some(max_backoff) = self.max_backoff {
+            options.max_backoff = Duration::from_millis(max_backoff);
+        }
+        if let Some(jitter) = self.jitter {
+            options.jitter = jitter;
+        }
+        Ok(options)
+    }
+}
+
+/// Response decompression, off unless `encodings` are set
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CompressionFileConfig {
+    /// gzip, deflate, br or zstd
+    pub encodings: Option<Vec<String>>,
+    /// Hash the body as "decoded" or "encoded"
+    pub hash: Option<String>,
+}
+
+impl CompressionFileConfig {
+    /// Fill in keys this section leaves out from `defaults`
+    fn or(self, defaults: &CompressionFileConfig) -> CompressionFileConfig {
+        CompressionFileConfig {
+            encodings: self.encodings.or_else(|| defaults.encodings.clone()),
+            hash: self.hash.or_else(|| defaults.hash.clone()),
+        }
+    }
+
+    pub fn load(&self) -> Result<CompressionOptions, ConfigError> {
+        let encodings = match self.encodings {
+            Some(ref encodings) => encodings
+                .iter()
+                .map(|e| e.parse::<ContentEncoding>())
+                .collect::<Result<_, _>>()?,
+            None => Vec::new(),
+        };
+        let body = match self.hash {
+            Some(ref hash) => hash.parse::<BodyRepresentation>()?,
+            None => BodyRepresentation::default(),
+        };
+        Ok(CompressionOptions { encodings, body })
+    }
+}
+
+/// Credentials to authenticate requests with
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthFileConfig {
+    #[serde(rename = "type")]
+    pub kind: Option<AuthTypeFileConfig>,
+    /// For basic and digest, or the AWS access key
+    pub user: Option<String>,
+    /// For basic and digest, or the AWS secret key
+    pub password: Option<String>,
+    /// Bearer token
+    pub token: Option<String>,
+    /// Environment variable to read the bearer token from
+    pub token_env: Option<String>,
+    /// File to read the bearer token from, relative to the config file
+    pub token_file: Option<String>,
+    /// For temporary AWS credentials
+    pub session_token: Option<String>,
+    pub region: Option<String>,
+    /// AWS service, `execute-api` when left out
+    pub service: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum AuthTypeFileConfig {
+    #[serde(rename = "basic")]
+    Basic,
+    #[serde(rename = "bearer")]
+    Bearer,
+    #[serde(rename = "digest")]
+    Digest,
+    #[serde(rename = "aws-sigv4")]
+    AwsSigV4,
+}
+
+impl AuthFileConfig {
+    /// The keys are taken together, from whichever section sets `type`
+    fn or(self, defaults: &AuthFileConfig) -> AuthFileConfig {
+        if self.kind.is_some() {
+            self
+        } else {
+            defaults.clone()
+        }
+    }
+
+    /// `None` unless `type` is set.  AWS credentials left out are taken from the environment,
+    /// as the AWS CLI does.
+    pub fn load(&self, parent: &Path) -> Result<Option<Auth>, ConfigError> {
+        let kind = match self.kind {
+            Some(kind) => kind,
+            None => return Ok(None),
+        };
+        let required = |value: &Option<String>, key: &'static str| {
+            value.clone().ok_or(ConfigError::MissingAuthSetting(key))
+        };
+        let auth = match kind {
+            AuthTypeFileConfig::Basic => Auth::Basic {
+                user: required(&self.user, "user")?,
+                password: self.password.clone().unwrap_or_default(),
+            },
+            AuthTypeFileConfig::Digest => Auth::Digest {
+                user: required(&self.user, "user")?,
+                password: self.password.clone().unwrap_or_default(),
+            },
+            AuthTypeFileConfig::Bearer => {
+                let token = if let Some(ref var) = self.token_env {
+                    env::var(var).map_err(|_| ConfigError::MissingEnvVar(var.clone()))?
+                } else if let Some(ref file) = self.token_file {
+                    let file = parent.join(file);
+                    fs::read_to_string(&file)
+                        .map_err(|e| ConfigError::TokenFile(file.display().to_string(), e))?
+                        .trim()
+                        .to_string()
+                } else {
+                    required(&self.token, "token")?
+                };
+                Auth::Bearer(token)
+            }
+            AuthTypeFileConfig::AwsSigV4 => {
+                let from_env = |value: &Option<String>, vars: &[&str], key: &'static str| {
+                    value
+                        .clone()
+                        .or_else(|| vars.iter().find_map(|v| env::var(v).ok()))
+                        .ok_or(ConfigError::MissingAuthSetting(key))
+                };
+                Auth::AwsSigV4(AwsSigV4 {
+                    access_key: from_env(&self.user, &["AWS_ACCESS_KEY_ID"], "user")?,
+                    secret_key: from_env(&self.password, &["AWS_SECRET_ACCESS_KEY"], "password")?,
+                    session_token: self
+                        .session_token
+                        .clone()
+                        .or_else(|| env::var("AWS_SESSION_TOKEN").ok()),
+                    region: from_env(
+                        &self.region,
+                        &["AWS_REGION", "AWS_DEFAULT_REGION"],
+                        "region",
+                    )?,
+                    service: self
+                        .service
+                        .clone()
+                        .unwrap_or_else(|| "execute-api".to_string()),
+                })
+            }
+        };
+        Ok(Some(auth))
+    }
+}
+
+/// Cookie handling, off unless `enabled`, `jar`, `load` or `save` are set
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CookieFileConfig {
+    pub enabled: Option<bool>,
+    /// Name of a jar shared with the other tests that use it, which then run one after another
+    pub jar: Option<String>,
+    /// Netscape format cookie file to start with, relative to the config file
+    pub load: Option<String>,
+    /// Where to write the cookies once the tests are done, relative to the config file
+    pub save: Option<String>,
+}
+
+/// Where a test keeps its cookies
+#[derive(Debug, Clone, Default)]
+pub struct CookieSettings {
+    pub jar: Option<CookieJar>,
+    /// Name of the jar, when shared with other tests
+    pub shared: Option<String>,
+    pub save: Option<PathBuf>,
+}
+
+impl CookieFileConfig {
+    /// Fill in keys this section leaves out from `defaults`
+    fn or(self, defaults: &CookieFileConfig) -> CookieFileConfig {
+        CookieFileConfig {
+            enabled: self.enabled.or(defaults.enabled),
+            jar: self.jar.or_else(|| defaults.jar.clone()),
+            load: self.load.or_else(|| defaults.load.clone()),
+            save: self.save.or_else(|| defaults.save.clone()),
+        }
+    }
+
+    /// The jar for a test, taken from `shared` when it has a name, with any file loaded into it
+    pub fn load(
+        &self,
+        path: &Path,
+        shared: &mut HashMap<String, CookieJar>,
+    ) -> Result<CookieSettings, ConfigError> {
+        let enabled = self.enabled.unwrap_or(false)
+            || self.jar.is_some()
+            || self.load.is_some()
+            || self.save.is_some();
+        if !enabled {
+            return Ok(CookieSettings::default());
+        }
+        let jar = match self.jar {
+            Some(ref name) => shared.entry(name.clone()).or_default().clone(),
+            None => CookieJar::new(),
+        };
+        if let Some(ref file) = self.load {
+            let file = path.join(file);
+            jar.load(&file)
+                .map_err(|e| ConfigError::CookieFile(file.display().to_string(), e))?;
+        }
+        Ok(CookieSettings {
+            jar: Some(jar),
+            shared: self.jar.clone(),
+            save: self.save.as_ref().map(|f| path.join(f)),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub enum HttpVersionFileConfig {
+    #[serde(rename = "http1.1")]
+    Http1,
+    #[serde(rename = "http2")]
+    Http2,
+    #[serde(rename = "http2-prior-knowledge")]
+    Http2PriorKnowledge,
+    #[serde(rename = "http3")]
+    Http3,
+}
+
+impl From<HttpVersionFileConfig> for HttpVersion {
+    fn from(fc: HttpVersionFileConfig) -> HttpVersion {
+        match fc {
+            HttpVersionFileConfig::Http1 => HttpVersion::Http1,
+            HttpVersionFileConfig::Http2 => HttpVersion::Http2,
+            HttpVersionFileConfig::Http2PriorKnowledge => HttpVersion::Http2PriorKnowledge,
+            HttpVersionFileConfig::Http3 => HttpVersion::Http3,
+        }
+    }
+}
+
+/// What happens to response bodies once they have been hashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum BodyMode {
+    /// Hash each chunk as it arrives and drop it, so bodies of any size can be measured
+    #[default]
+    #[serde(rename = "discard")]
+    Discard,
+    /// Keep the whole body in memory until it has arrived, as a client using it would
+    #[serde(rename = "buffer")]
+    Buffer,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct FileTestConfig {
     pub name: String,
@@ -52,6 +642,29 @@ pub struct FileTestConfig {
     pub headers: Option<HashMap<String, String>>,
     pub payload: Option<PayloadConfig>,
     pub capture_headers: Option<CaptureHeaderFileConfig>,
+    /// Print the negotiated TLS parameters and server certificate with each result
+    pub tls_info: Option<bool>,
+    /// Print kernel TCP statistics with each result, Linux only
+    pub tcp_info: Option<bool>,
+    /// Print the bytes sent and received on the connection with each result
+    pub wire_bytes: Option<bool>,
+    pub response_body: Option<BodyMode>,
+    pub timeouts: Option<TimeoutFileConfig>,
+    pub tls: Option<TlsFileConfig>,
+    pub proxy: Option<ProxyFileConfig>,
+    pub socket: Option<SocketFileConfig>,
+    pub redirects: Option<RedirectFileConfig>,
+    pub retry: Option<RetryFileConfig>,
+    pub compression: Option<CompressionFileConfig>,
+    pub cookies: Option<CookieFileConfig>,
+    pub auth: Option<AuthFileConfig>,
+    /// Unix domain socket to connect to instead of the URL's host, relative to the config file
+    pub unix_socket: Option<String>,
+    pub http_version: Option<HttpVersionFileConfig>,
+    pub keep_alive: Option<bool>,
+    pub pool_max_idle: Option<usize>,
+    /// milliseconds
+    pub pool_idle_timeout: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -60,6 +673,29 @@ pub struct DefaultsConfig {
     pub method: Option<String>,
     pub headers: Option<HashMap<String, String>>,
     pub capture_headers: Option<CaptureHeaderFileConfig>,
+    /// Print the negotiated TLS parameters and server certificate with each result
+    pub tls_info: Option<bool>,
+    /// Print kernel TCP statistics with each result, Linux only
+    pub tcp_info: Option<bool>,
+    /// Print the bytes sent and received on the connection with each result
+    pub wire_bytes: Option<bool>,
+    pub response_body: Option<BodyMode>,
+    pub timeouts: Option<TimeoutFileConfig>,
+    pub tls: Option<TlsFileConfig>,
+    pub proxy: Option<ProxyFileConfig>,
+    pub socket: Option<SocketFileConfig>,
+    pub redirects: Option<RedirectFileConfig>,
+    pub retry: Option<RetryFileConfig>,
+    pub compression: Option<CompressionFileConfig>,
+    pub cookies: Option<CookieFileConfig>,
+    pub auth: Option<AuthFileConfig>,
+    /// Unix domain socket to connect to instead of the URL's host, relative to the config file
+    pub unix_socket: Option<String>,
+    pub http_version: Option<HttpVersionFileConfig>,
+    pub keep_alive: Option<bool>,
+    pub pool_max_idle: Option<usize>,
+    /// milliseconds
+    pub pool_idle_timeout: Option<u64>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -82,6 +718,19 @@ pub struct TestConfig {
     pub headers: HashMap<String, String>,
     pub payload: Option<PayloadConfig>,
     pub capture_headers: CaptureHeaderConfig,
+    /// Print the negotiated TLS parameters and server certificate with each result
+    pub tls_info: bool,
+    /// Print kernel TCP statistics with each result, Linux only
+    pub tcp_info: bool,
+    /// Print the bytes sent and received on the connection with each result
+    pub wire_bytes: bool,
+    pub response_body: BodyMode,
+    /// Name of the cookie jar shared with other tests, which run one after another
+    pub cookie_jar: Option<String>,
+    /// Where to write the test's cookies once it's done
+    pub save_cookies: Option<PathBuf>,
+    /// Connection-level settings for the test's `Client`
+    pub client: ClientOptions,
 }
 
 #[derive(Debug, Clone)]
@@ -121,36 +770,57 @@ impl From<CaptureHeaderFileConfig> for CaptureHeaderConfig {
             CaptureHeaderConfig::List(
                 fc.list
                     .map(|v| v.into_iter().map(|val| val.to_lowercase()).collect())
-                    .unwrap_or_else(HashSet::new),
+                    .unwrap_or_default(),
             )
         }
     }
 }
 
+impl From<TimeoutFileConfig> for Timeouts {
+    fn from(fc: TimeoutFileConfig) -> Timeouts {
+        Timeouts {
+            dns: fc.dns.map(Duration::from_millis),
+            connect: fc.connect.map(Duration::from_millis),
+            tls: fc.tls.map(Duration::from_millis),
+            headers: fc.headers.map(Duration::from_millis),
+            body_read: fc.body_read.map(Duration::from_millis),
+            total: fc.total.map(Duration::from_millis),
+        }
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum ConfigError {
     #[error("Missing url for test '{0}' and no default_url set.")]
     MissingUrl(String),
+    #[error("Invalid local address '{0}'")]
+    InvalidLocalAddress(String),
+    #[error("DSCP must be between 0 and 63, got {0}")]
+    InvalidDscp(u8),
+    #[error(transparent)]
+    InvalidRedirectMethod(#[from] MethodRewriteError),
+    #[error(transparent)]
+    InvalidRetryError(#[from] ErrorKindParseError),
+    #[error("Invalid HTTP status {0}")]
+    InvalidStatus(u16),
+    #[error(transparent)]
+    InvalidEncoding(#[from] ContentEncodingParseError),
+    #[error(transparent)]
+    InvalidHashRepresentation(#[from] BodyRepresentationParseError),
+    #[error("Couldn't read cookie file {0}: {1}")]
+    CookieFile(String, #[source] std::io::Error),
+    #[error("Missing auth setting {0}")]
+    MissingAuthSetting(&'static str),
+    #[error("Environment variable {0} isn't set")]
+    MissingEnvVar(String),
+    #[error("Couldn't read token file {0}: {1}")]
+    TokenFile(String, #[source] std::io::Error),
 }
 
 impl Config {
-    pub fn single(
-        url: Uri,
-        method: String,
-        headers: HashMap<String, String>,
-        payload: Option<PayloadConfig>,
-        capture_headers: CaptureHeaderConfig,
-    ) -> Config {
-        let t = TestConfig {
-            name: url.to_string(),
-            url,
-            method,
-            headers,
-            payload,
-            capture_headers,
-        };
-
-        Config { tests: vec![t] }
+    /// A config with only `test`, for a URL given on the command line
+    pub fn single(test: TestConfig) -> Config {
+        Config { tests: vec![test] }
     }
 
     fn fill_defaults(unresolved: FileConfig, path: &Path) -> Result<Config, AnyError> {
@@ -163,14 +833,86 @@ impl Config {
             .defaults
             .as_ref()
             .and_then(|d| d.headers.clone())
-            .unwrap_or_else(HashMap::new);
+            .unwrap_or_default();
 
         let default_capture_headers = unresolved
             .defaults
             .as_ref()
             .and_then(|d| d.capture_headers.clone())
             .map(CaptureHeaderConfig::from)
-            .unwrap_or_else(CaptureHeaderConfig::default);
+            .unwrap_or_default();
+
+        let default_tls_info = unresolved.defaults.as_ref().and_then(|d| d.tls_info);
+        let default_tcp_info = unresolved.defaults.as_ref().and_then(|d| d.tcp_info);
+        let default_wire_bytes = unresolved.defaults.as_ref().and_then(|d| d.wire_bytes);
+        let default_response_body = unresolved.defaults.as_ref().and_then(|d| d.response_body);
+
+        let default_timeouts = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.timeouts.clone())
+            .map(Timeouts::from)
+            .unwrap_or_default();
+
+        let default_tls = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.tls.clone())
+            .unwrap_or_default();
+
+        let default_proxy = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.proxy.clone())
+            .unwrap_or_default();
+
+        let default_socket = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.socket.clone())
+            .unwrap_or_default();
+
+        let default_redirects = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.redirects.clone())
+            .unwrap_or_default();
+
+        let default_retry = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.retry.clone())
+            .unwrap_or_default();
+
+        let default_compression = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.compression.clone())
+            .unwrap_or_default();
+
+        let default_cookies = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.cookies.clone())
+            .unwrap_or_default();
+        let mut shared_jars = HashMap::new();
+        let default_auth = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.auth.clone())
+            .unwrap_or_default();
+
+        let default_unix_socket = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.unix_socket.clone());
+        let default_http_version = unresolved.defaults.as_ref().and_then(|d| d.http_version);
+        let default_keep_alive = unresolved.defaults.as_ref().and_then(|d| d.keep_alive);
+        let default_pool_max_idle = unresolved.defaults.as_ref().and_then(|d| d.pool_max_idle);
+        let default_pool_idle_timeout = unresolved
+            .defaults
+            .as_ref()
+            .and_then(|d| d.pool_idle_timeout);
 
         let default_url = match unresolved
             .defaults
@@ -178,7 +920,7 @@ impl Config {
             .and_then(|d| d.url.clone())
             .as_ref()
         {
-            Some(ref url_res) => Some(url_res.parse::<Uri>()?),
+            Some(url_res) => Some(url_res.parse::<Uri>()?),
             None => {
                 for test in unresolved.tests.iter() {
                     if test.url.is_none() {
@@ -194,9 +936,14 @@ impl Config {
             .into_iter()
             .map(move |t| {
                 let url = match t.url.as_ref() {
-                    Some(ref url) => url.parse::<Uri>(),
+                    Some(url) => url.parse::<Uri>(),
                     None => Ok(default_url.clone().unwrap()),
                 };
+                let cookies = t
+                    .cookies
+                    .unwrap_or_default()
+                    .or(&default_cookies)
+                    .load(path, &mut shared_jars)?;
 
                 Ok(TestConfig {
                     name: t.name,
@@ -207,7 +954,53 @@ impl Config {
                         .capture_headers
                         .map(CaptureHeaderConfig::from)
                         .unwrap_or_else(|| default_capture_headers.clone()),
-                    payload: t.payload.map(|p| p.make_absolute(&path)),
+                    tls_info: t.tls_info.or(default_tls_info).unwrap_or(false),
+                    tcp_info: t.tcp_info.or(default_tcp_info).unwrap_or(false),
+                    wire_bytes: t.wire_bytes.or(default_wire_bytes).unwrap_or(false),
+                    response_body: t
+                        .response_body
+                        .or(default_response_body)
+                        .unwrap_or_default(),
+                    cookie_jar: cookies.shared,
+                    save_cookies: cookies.save,
+                    client: ClientOptions {
+                        timeouts: t
+                            .timeouts
+                            .map(Timeouts::from)
+                            .unwrap_or_else(|| default_timeouts.clone()),
+                        tls: t.tls.unwrap_or_default().or(&default_tls).load(path)?,
+                        proxy: t.proxy.unwrap_or_default().or(&default_proxy).load()?,
+                        socket: t.socket.unwrap_or_default().or(&default_socket).load()?,
+                        redirects: t
+                            .redirects
+                            .unwrap_or_default()
+                            .or(&default_redirects)
+                            .load()?,
+                        retry: t.retry.unwrap_or_default().or(&default_retry).load()?,
+                        compression: t
+                            .compression
+                            .unwrap_or_default()
+                            .or(&default_compression)
+                            .load()?,
+                        cookies: cookies.jar,
+                        auth: t.auth.unwrap_or_default().or(&default_auth).load(path)?,
+                        unix_socket: t
+                            .unix_socket
+                            .or_else(|| default_unix_socket.clone())
+                            .map(|p| path.join(p)),
+                        http_version: t
+                            .http_version
+                            .or(default_http_version)
+                            .map(HttpVersion::from)
+                            .unwrap_or_default(),
+                        keep_alive: t.keep_alive.or(default_keep_alive).unwrap_or(false),
+                        pool_max_idle: t.pool_max_idle.or(default_pool_max_idle),
+                        pool_idle_timeout: t
+                            .pool_idle_timeout
+                            .or(default_pool_idle_timeout)
+                            .map(Duration::from_millis),
+                    },
+                    payload: t.payload.map(|p| p.make_absolute(path)),
                 })
             })
             .collect();
@@ -221,6 +1014,175 @@ impl Config {
         f.read_to_string(&mut contents)?;
         let config: FileConfig = toml::from_str(&contents)?;
         let p = path.as_ref().parent().unwrap_or_else(|| Path::new("/"));
-        Config::fill_defaults(config, &p)
+        Config::fill_defaults(config, p)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use http::header::{HeaderValue, SET_COOKIE};
+    use http::HeaderMap;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("tracer-config-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn file_config(config: &str) -> FileConfig {
+        toml::from_str(config).unwrap()
+    }
+
+    fn auth(section: &str) -> AuthFileConfig {
+        toml::from_str(section).unwrap()
+    }
+
+    fn load_auth(section: &AuthFileConfig, parent: &Path) -> Result<Option<Auth>, String> {
+        section.load(parent).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn auth_or_test() {
+        let defaults = auth("type = \"basic\"\nuser = \"a\"\npassword = \"b\"");
+        let basic = Auth::Basic {
+            user: "a".into(),
+            password: "b".into(),
+        };
+        let cases = [
+            ("", Some(basic.clone())),
+            // Keys without `type` don't change the defaults
+            ("user = \"c\"", Some(basic)),
+            // With `type`, nothing is taken from the defaults
+            (
+                "type = \"digest\"\nuser = \"c\"",
+                Some(Auth::Digest {
+                    user: "c".into(),
+                    password: "".into(),
+                }),
+            ),
+            (
+                "type = \"bearer\"\ntoken = \"t\"",
+                Some(Auth::Bearer("t".into())),
+            ),
+        ];
+        for (section, expected) in cases.iter() {
+            let merged = auth(section).or(&defaults);
+            assert_eq!(
+                load_auth(&merged, Path::new("/")),
+                Ok(expected.clone()),
+                "{}",
+                section
+            );
+        }
+        let merged = auth("user = \"c\"").or(&AuthFileConfig::default());
+        assert_eq!(load_auth(&merged, Path::new("/")), Ok(None));
+    }
+
+    #[test]
+    fn bearer_token_test() {
+        let dir = temp_dir("bearer");
+        fs::write(dir.join("token"), "from-file\n").unwrap();
+        env::set_var("TRACER_CONFIG_TEST_TOKEN", "from-env");
+        let cases = [
+            (
+                "token_env = \"TRACER_CONFIG_TEST_TOKEN\"\ntoken_file = \"token\"\ntoken = \"inline\"",
+                Ok("from-env"),
+            ),
+            ("token_file = \"token\"\ntoken = \"inline\"", Ok("from-file")),
+            ("token = \"inline\"", Ok("inline")),
+            (
+                "token_env = \"TRACER_CONFIG_TEST_UNSET\"\ntoken = \"inline\"",
+                Err("Environment variable TRACER_CONFIG_TEST_UNSET isn't set"),
+            ),
+            ("", Err("Missing auth setting token")),
+        ];
+        for (keys, expected) in cases.iter() {
+            let section = auth(&format!("type = \"bearer\"\n{}", keys));
+            let expected = expected
+                .map(|t| Some(Auth::Bearer(t.into())))
+                .map_err(String::from);
+            assert_eq!(load_auth(&section, &dir), expected, "{}", keys);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn aws_env_test() {
+        env::set_var("AWS_ACCESS_KEY_ID", "env-key");
+        env::set_var("AWS_SECRET_ACCESS_KEY", "env-secret");
+        env::remove_var("AWS_SESSION_TOKEN");
+        env::remove_var("AWS_REGION");
+        env::set_var("AWS_DEFAULT_REGION", "eu-west-1");
+        let aws = |access_key: &str, secret_key: &str, region: &str| {
+            Ok(Some(Auth::AwsSigV4(AwsSigV4 {
+                access_key: access_key.into(),
+                secret_key: secret_key.into(),
+                session_token: None,
+                region: region.into(),
+                service: "execute-api".into(),
+            })))
+        };
+
+        let section = auth("type = \"aws-sigv4\"");
+        let explicit = auth(
+            "type = \"aws-sigv4\"\nuser = \"key\"\npassword = \"secret\"\nregion = \"us-east-1\"",
+        );
+        assert_eq!(
+            load_auth(&section, Path::new("/")),
+            aws("env-key", "env-secret", "eu-west-1")
+        );
+        env::set_var("AWS_REGION", "eu-central-1");
+        assert_eq!(
+            load_auth(&section, Path::new("/")),
+            aws("env-key", "env-secret", "eu-central-1")
+        );
+        assert_eq!(
+            load_auth(&explicit, Path::new("/")),
+            aws("key", "secret", "us-east-1")
+        );
+
+        env::remove_var("AWS_ACCESS_KEY_ID");
+        assert_eq!(
+            load_auth(&section, Path::new("/")),
+            Err("Missing auth setting user".to_string())
+        );
+    }
+
+    #[test]
+    fn shared_cookie_jar_test() {
+        let config = file_config(
+            r#"
+            [defaults]
+            url = "http://example.com/"
+
+            [[test]]
+            name = "login"
+            cookies = { jar = "session" }
+
+            [[test]]
+            name = "alone"
+            cookies = { enabled = true }
+
+            [[test]]
+            name = "profile"
+            cookies = { jar = "session" }
+            "#,
+        );
+        let config = Config::fill_defaults(config, Path::new("/")).unwrap();
+        let jar = |i: usize| config.tests[i].client.cookies.clone().unwrap();
+        assert_eq!(config.tests[0].cookie_jar.as_deref(), Some("session"));
+        assert_eq!(config.tests[1].cookie_jar, None);
+        assert_eq!(config.tests[2].cookie_jar.as_deref(), Some("session"));
+
+        let url: Uri = "http://example.com/".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(SET_COOKIE, HeaderValue::from_static("id=1"));
+        jar(0).store(&url, &headers);
+        assert_eq!(
+            jar(2).header_for(&url),
+            Some(HeaderValue::from_static("id=1"))
+        );
+        assert_eq!(jar(1).header_for(&url), None);
     }
 }
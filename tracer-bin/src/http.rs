@@ -1,20 +1,23 @@
-use crate::config::{Config, PayloadConfig, TestConfig};
+use crate::config::{BodyMode, Config, PayloadConfig, TestConfig};
 use crate::interrupt::Interrupted;
-use crate::reporting::TestReport;
+use crate::reporting::{FailureReport, TestReport};
 use anyhow::Error;
 use futures::future;
-use futures::TryFutureExt;
 use http::header::HeaderValue;
 use http::HeaderMap;
 use http::Request;
-use hyper::{body::Bytes, Body, Error as HyperError};
+use hyper::Body;
 use sha2::{Digest, Sha256};
-use slog;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
+use tracer_client::body::BodyStats;
 use tracer_client::client::Metric;
-use tracer_client::Client;
+use tracer_client::connectors::TracingHttpsConnector;
+use tracer_client::context::RequestMetrics;
+use tracer_client::{Client, ClientError};
+use tracer_metrics::data::SampleValue;
 use tracer_metrics::Collector;
 
 pub struct TestExecutor {
@@ -39,21 +42,40 @@ impl TestExecutor {
         self,
         repetitions: R,
         interrupted: Interrupted,
-    ) -> Vec<Result<(TestConfig, Collector<Metric>), ()>> {
+    ) -> Vec<(TestConfig, Collector<Metric>)> {
         let logger = self.logger.clone();
         let repetitions = repetitions.into();
-        let chain = self
-            .tests_and_collectors()
-            .map(|(t, c)| async {
-                let interrupted = interrupted.clone();
+        // Tests sharing a cookie jar run one after another, in the order they're configured, so
+        // cookies set by one are there for the next
+        let groups = group_by_jar(self.tests_and_collectors(), |(test, _)| {
+            test.cookie_jar.clone()
+        });
+        let chain = groups.into_iter().map(|group| {
+            let logger = logger.clone();
+            let interrupted = interrupted.clone();
+            async move {
+                // One client per test, so keep-alive connections survive between repetitions
+                let clients: Vec<_> = group
+                    .iter()
+                    .map(|(_, (test, collector))| {
+                        Client::new_with_options(collector.handle(), test.client.clone())
+                    })
+                    .collect();
                 let mut iterations = 0;
-                let mut test = t;
-                let mut collector = c;
-                while !interrupted.interrupted() {
-                    let (report, c) = execute_test(test, collector).await?;
-                    println!("{}", report);
-                    test = report.take_config();
-                    collector = c;
+                'repeat: loop {
+                    for ((_, (test, collector)), client) in group.iter().zip(&clients) {
+                        if interrupted.interrupted() {
+                            break 'repeat;
+                        }
+                        match execute_test(test.clone(), client, collector).await {
+                            Ok(report) => println!("{}", report),
+                            // Failures are counted by the client, keep going so error rates can be measured
+                            Err(e) => {
+                                slog::debug!(logger, "{} failed: {:?}", test.name, e);
+                                println!("{}", FailureReport::new(test, &e));
+                            }
+                        }
+                    }
                     iterations += 1;
                     if let Some(n) = repetitions {
                         if iterations >= n {
@@ -61,26 +83,71 @@ impl TestExecutor {
                         }
                     }
                 }
-                Ok::<_, HyperError>((test, collector))
-            })
-            .map(|f| f.map_err(|e| slog::error!(logger, "{}", e)));
-        future::join_all(chain).await
+                group
+            }
+        });
+        let results = in_config_order(future::join_all(chain).await);
+        save_cookies(&logger, &results);
+        results
+    }
+}
+
+/// Tests grouped by the cookie jar `jar` gives for them, in the order they come, alongside
+/// their position.  Tests without a jar are on their own.
+fn group_by_jar<T, F>(tests: impl IntoIterator<Item = T>, jar: F) -> Vec<Vec<(usize, T)>>
+where
+    F: Fn(&T) -> Option<String>,
+{
+    let mut groups: Vec<Vec<(usize, T)>> = Vec::new();
+    let mut shared_jars: HashMap<String, usize> = HashMap::new();
+    for (i, test) in tests.into_iter().enumerate() {
+        match jar(&test) {
+            Some(jar) => {
+                let group = *shared_jars.entry(jar).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[group].push((i, test));
+            }
+            None => groups.push(vec![(i, test)]),
+        }
+    }
+    groups
+}
+
+/// The tests from `groups` back in the order they came before grouping
+fn in_config_order<T>(groups: impl IntoIterator<Item = Vec<(usize, T)>>) -> Vec<T> {
+    let mut tests: Vec<_> = groups.into_iter().flatten().collect();
+    tests.sort_by_key(|(i, _)| *i);
+    tests.into_iter().map(|(_, test)| test).collect()
+}
+
+/// Write out the cookie jars of tests that ask for it, once per file
+fn save_cookies(logger: &slog::Logger, results: &[(TestConfig, Collector<Metric>)]) {
+    let mut saved = HashSet::new();
+    for (test, _) in results {
+        if let (Some(path), Some(jar)) = (&test.save_cookies, &test.client.cookies) {
+            if !saved.insert(path) {
+                continue;
+            }
+            if let Err(e) = jar.save(path) {
+                slog::warn!(logger, "Couldn't save cookies to {}: {}", path.display(), e);
+            }
+        }
     }
 }
 
 fn calculate_header_size(h: &HeaderMap<HeaderValue>) -> usize {
-    // Assume header is in the canonical form of <HEADER-NAME><COLON><SPACE><HEADER-VALUE>\r\n
-    h.keys()
-        .map::<usize, _>(|k| h.len() + h.get_all(k).iter().map(|v| v.len()).sum::<usize>() + 4)
-        .sum::<usize>() as usize
+    // Assume each value is on its own line in the canonical form of
+    // <HEADER-NAME><COLON><SPACE><HEADER-VALUE>\r\n
+    h.iter().map(|(k, v)| k.as_str().len() + v.len() + 4).sum()
 }
 
 pub async fn execute_test(
     config: TestConfig,
-    collector: Collector<Metric>,
-) -> Result<(TestReport, Collector<Metric>), HyperError> {
-    let client = Client::new_with_collector_handle(collector.handle());
-
+    client: &Client<TracingHttpsConnector>,
+    collector: &Collector<Metric>,
+) -> Result<TestReport, ClientError> {
     let mut builder = Request::builder()
         .uri(config.url.clone())
         .method(&*config.method);
@@ -91,31 +158,39 @@ pub async fn execute_test(
         None => builder.body(Body::empty()).unwrap(),
         Some(ref p) => match p {
             PayloadConfig::File { file: f } => {
-                builder.body(load_payload_body(&f).unwrap().into()).unwrap()
+                builder.body(load_payload_body(f).unwrap().into()).unwrap()
             }
             PayloadConfig::Value { value: v } => builder.body(v.clone().into()).unwrap(),
         },
     };
-    let (res, body) = client.request_fully(req).await?;
-    let body_size = body.len();
-    let header_size = calculate_header_size(&res.headers);
+    let mut hasher = Sha256::default();
+    let res = match config.response_body {
+        BodyMode::Discard => {
+            client
+                .request_streaming(req, |chunk| hasher.input(chunk))
+                .await
+        }
+        BodyMode::Buffer => client.request_fully(req).await.map(|(parts, body)| {
+            hasher.input(&body);
+            parts
+        }),
+    };
+    collector.process_outstanding();
+    let mut res = res?;
+    let body_size = res.extensions.get::<BodyStats>().map_or(0, |s| s.len);
+    let header_size = calculate_header_size(&res.headers) as u64;
     let handle = collector.handle();
-    handle.send_value(Metric::BodyLen, body_size as u64);
-    handle.send_value(Metric::HeaderLen, header_size as u64);
+    handle.send_value(Metric::BodyLen, body_size);
+    handle.send_value(Metric::HeaderLen, header_size);
     collector.process_outstanding();
-    let tr = TestReport::new(
-        config,
-        Metric::get_all_metrics(&collector),
-        res,
-        hash_body(body),
-    );
-    Ok((tr, collector))
-}
-
-fn hash_body(c: Bytes) -> String {
-    let mut h = Sha256::default();
-    h.input(c);
-    format!("{:x}", h.result())
+    let mut metrics = res
+        .extensions
+        .remove::<RequestMetrics>()
+        .unwrap_or_default();
+    metrics.push(Metric::BodyLen, SampleValue::Value(body_size));
+    metrics.push(Metric::HeaderLen, SampleValue::Value(header_size));
+    let tr = TestReport::new(config, metrics, res, format!("{:x}", hasher.result()));
+    Ok(tr)
 }
 
 fn load_payload_body<P: AsRef<Path>>(path: P) -> Result<String, Error> {
@@ -124,3 +199,36 @@ fn load_payload_body<P: AsRef<Path>>(path: P) -> Result<String, Error> {
     f.read_to_string(&mut contents)?;
     Ok(contents)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn group_by_jar_test() {
+        let tests = vec![
+            ("a", Some("session")),
+            ("b", None),
+            ("c", Some("session")),
+            ("d", Some("other")),
+            ("e", None),
+            ("f", Some("other")),
+        ];
+        let groups = group_by_jar(tests.clone(), |(_, jar)| jar.map(String::from));
+        let names: Vec<Vec<_>> = groups
+            .iter()
+            .map(|g| g.iter().map(|(i, (name, _))| (*i, *name)).collect())
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                vec![(0, "a"), (2, "c")],
+                vec![(1, "b")],
+                vec![(3, "d"), (5, "f")],
+                vec![(4, "e")],
+            ]
+        );
+        // Groups interleave, so the results need putting back in order
+        assert_eq!(in_config_order(groups), tests);
+    }
+}
@@ -2,16 +2,30 @@ mod config;
 mod http;
 mod interrupt;
 mod reporting;
+mod scan;
 
-use crate::config::{CaptureHeaderConfig, Config, PayloadConfig};
+use crate::config::{
+    AuthFileConfig, AuthTypeFileConfig, BodyMode, CaptureHeaderConfig, CertTypeFileConfig,
+    CompressionFileConfig, Config, CookieFileConfig, CookieSettings, PayloadConfig,
+    ProxyFileConfig, RedirectFileConfig, RetryFileConfig, SocketFileConfig, TestConfig,
+    TlsFileConfig,
+};
 use crate::http::TestExecutor;
 use ::http::Uri;
 use anyhow::Error;
-use clap::{value_t, App, Arg, SubCommand};
+use clap::{value_t, values_t, App, Arg, ArgMatches, SubCommand};
 use slog::{o, Drain, Level};
 use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 use tokio::runtime;
 use tracer_client::client::Metric;
+use tracer_client::compression::ContentEncoding;
+use tracer_client::timeouts::Timeouts;
+use tracer_client::{ClientOptions, HttpVersion};
 
 fn root_logger(level: Level) -> slog::Logger {
     let decorator = slog_term::TermDecorator::new().stderr().build();
@@ -36,19 +50,39 @@ fn run_tests(
     rt.block_on(async move {
         let results = t.execute_repeated_tests(repeat, interrupted).await;
         if stats_summary {
-            let results = results
-                .into_iter()
-                .filter(|r| r.is_ok())
-                .map(|r| r.unwrap());
             for (config, collector) in results {
-                println!("{} stats:", config.name);
+                if config.client.tls.insecure {
+                    println!(
+                        "{} stats (INSECURE, certificates not verified):",
+                        config.name
+                    );
+                } else {
+                    println!("{} stats:", config.name);
+                }
                 let snapshots = Metric::get_all_metrics(&collector);
                 snapshots
                     .iter()
                     .filter(|s| s.latency_histogram().is_some())
                     .for_each(|s| {
-                        println!("  {}: {}", s.key(), reporting::format_snapshot_stats(&s));
+                        println!("  {}: {}", s.key(), reporting::format_snapshot_stats(s));
                     });
+                let counts = &[
+                    Metric::ReusedConnection,
+                    Metric::ZeroRtt,
+                    Metric::Redirects,
+                    Metric::Retries,
+                ];
+                let reused = Metric::get_metrics(counts, &collector);
+                let failures = Metric::get_failure_metrics(&collector);
+                let timeouts = Metric::get_timeout_metrics(&collector);
+                for s in reused.iter().chain(&failures).chain(&timeouts) {
+                    println!("  {}: {}", s.key(), reporting::format_snapshot_stats(s));
+                }
+                let requests = collector.snapshot(&Metric::Requests);
+                println!(
+                    "  Errors: {}",
+                    reporting::format_error_rate(&requests, &failures)
+                );
             }
         }
     });
@@ -56,6 +90,218 @@ fn run_tests(
     Ok(())
 }
 
+fn run_scan(
+    logger: slog::Logger,
+    url: Uri,
+    options: ClientOptions,
+    handshakes: usize,
+    interrupted: interrupt::Interrupted,
+) -> Result<(), Error> {
+    let mut rt = runtime::Builder::new()
+        .threaded_scheduler()
+        .enable_all()
+        .build()?;
+    rt.block_on(scan::scan(url, options, handshakes, interrupted, logger));
+    Ok(())
+}
+
+fn duration_value(matches: &ArgMatches, name: &str) -> Option<Duration> {
+    if matches.is_present(name) {
+        let ms = value_t!(matches, name, u64).unwrap_or_else(|e| e.exit());
+        Some(Duration::from_millis(ms))
+    } else {
+        None
+    }
+}
+
+fn parsed_value<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<T> {
+    if matches.is_present(name) {
+        Some(value_t!(matches, name, T).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    }
+}
+
+fn parsed_values<T: FromStr>(matches: &ArgMatches, name: &str) -> Option<Vec<T>> {
+    if matches.is_present(name) {
+        Some(values_t!(matches, name, T).unwrap_or_else(|e| e.exit()))
+    } else {
+        None
+    }
+}
+
+/// Settings loaded from the command line, or exit after saying why they're invalid
+fn or_exit<T, E: fmt::Display>(what: &str, settings: Result<T, E>) -> T {
+    settings.unwrap_or_else(|e| {
+        eprintln!("Invalid {} settings: {}", what, e);
+        std::process::exit(1)
+    })
+}
+
+/// Client settings from the command line, for requests to a single URL
+fn client_options(matches: &ArgMatches) -> ClientOptions {
+    let timeouts = Timeouts {
+        dns: duration_value(matches, "dns-timeout"),
+        connect: duration_value(matches, "connect-timeout"),
+        tls: duration_value(matches, "tls-timeout"),
+        headers: duration_value(matches, "headers-timeout"),
+        body_read: duration_value(matches, "read-timeout"),
+        total: duration_value(matches, "timeout"),
+    };
+    let http_version = if matches.is_present("http3") {
+        HttpVersion::Http3
+    } else if matches.is_present("http2-prior-knowledge") {
+        HttpVersion::Http2PriorKnowledge
+    } else if matches.is_present("http2") {
+        HttpVersion::Http2
+    } else {
+        HttpVersion::Http1
+    };
+    let tls = TlsFileConfig {
+        cacert: matches.value_of("cacert").map(String::from),
+        capath: matches.value_of("capath").map(String::from),
+        ca_native: Some(matches.is_present("ca-native")),
+        insecure: Some(matches.is_present("insecure")),
+        resume: Some(matches.is_present("tls-resume")),
+        early_data: Some(matches.is_present("early-data")),
+        server_name: matches.value_of("tls-server-name").map(String::from),
+        cert: matches.value_of("cert").map(String::from),
+        key: matches.value_of("key").map(String::from),
+        cert_type: match matches.value_of("cert-type") {
+            Some("p12") => Some(CertTypeFileConfig::P12),
+            Some(_) => Some(CertTypeFileConfig::Pem),
+            None => None,
+        },
+        cert_password: matches.value_of("cert-password").map(String::from),
+        versions: matches
+            .values_of("tls-version")
+            .map(|v| v.map(String::from).collect()),
+        cipher_suites: matches
+            .values_of("ciphers")
+            .map(|v| v.map(String::from).collect()),
+    };
+    let curdir = env::current_dir().expect("Couldn't get current working directory");
+    let tls = or_exit("TLS", tls.load(&curdir));
+    let proxy = ProxyFileConfig {
+        url: matches.value_of("proxy").map(String::from),
+        user: matches.value_of("proxy-user").map(String::from),
+        no_proxy: matches.value_of("noproxy").map(String::from),
+    };
+    let proxy = or_exit("proxy", proxy.load());
+    let socket = SocketFileConfig {
+        local_address: matches.value_of("local-address").map(String::from),
+        local_port: parsed_value(matches, "local-port"),
+        interface: matches.value_of("interface").map(String::from),
+        nodelay: Some(!matches.is_present("nagle")),
+        keepalive: parsed_value(matches, "tcp-keepalive"),
+        send_buffer: parsed_value(matches, "send-buffer"),
+        recv_buffer: parsed_value(matches, "recv-buffer"),
+        ttl: parsed_value(matches, "ttl"),
+        dscp: parsed_value(matches, "dscp"),
+    };
+    let socket = or_exit("socket", socket.load());
+    let redirects = RedirectFileConfig {
+        follow: Some(matches.is_present("location")),
+        max: parsed_value(matches, "max-redirects"),
+        resend_body: Some(!matches.is_present("no-resend-body")),
+        trusted: Some(matches.is_present("location-trusted")),
+        method: matches.value_of("redirect-method").map(String::from),
+    };
+    let redirects = or_exit("redirect", redirects.load());
+    let retry = RetryFileConfig {
+        retries: parsed_value(matches, "retry"),
+        errors: matches
+            .values_of("retry-errors")
+            .map(|v| v.map(String::from).collect()),
+        statuses: parsed_values(matches, "retry-statuses"),
+        backoff: parsed_value(matches, "retry-backoff"),
+        max_backoff: parsed_value(matches, "retry-max-backoff"),
+        jitter: Some(!matches.is_present("no-retry-jitter")),
+    };
+    let retry = or_exit("retry", retry.load());
+    let encodings = match matches.values_of("encoding") {
+        Some(encodings) => Some(encodings.map(String::from).collect()),
+        None if matches.is_present("compressed") => Some(
+            ContentEncoding::all()
+                .iter()
+                .map(|e| e.name().to_string())
+                .collect(),
+        ),
+        None => None,
+    };
+    let compression = CompressionFileConfig {
+        encodings,
+        hash: matches.value_of("hash").map(String::from),
+    };
+    let compression = or_exit("compression", compression.load());
+    let (user, password) = match matches.value_of("user") {
+        Some(user) => {
+            let (user, password) = user.split_once(':').unwrap_or((user, ""));
+            (Some(user.to_string()), Some(password.to_string()))
+        }
+        None => (None, None),
+    };
+    let aws = matches
+        .value_of("aws-sigv4")
+        .map(|s| s.split_once(':').unwrap_or((s, "")));
+    let kind = if aws.is_some() {
+        Some(AuthTypeFileConfig::AwsSigV4)
+    } else if matches.is_present("digest") {
+        Some(AuthTypeFileConfig::Digest)
+    } else if user.is_some() {
+        Some(AuthTypeFileConfig::Basic)
+    } else if ["bearer", "bearer-env", "bearer-file"]
+        .iter()
+        .any(|a| matches.is_present(a))
+    {
+        Some(AuthTypeFileConfig::Bearer)
+    } else {
+        None
+    };
+    let not_empty = |s: &str| Some(s.to_string()).filter(|s| !s.is_empty());
+    let auth = AuthFileConfig {
+        kind,
+        user,
+        password,
+        token: matches.value_of("bearer").map(String::from),
+        token_env: matches.value_of("bearer-env").map(String::from),
+        token_file: matches.value_of("bearer-file").map(String::from),
+        session_token: None,
+        region: aws.and_then(|(region, _)| not_empty(region)),
+        service: aws.and_then(|(_, service)| not_empty(service)),
+    };
+    let auth = or_exit("auth", auth.load(&curdir));
+    ClientOptions {
+        timeouts,
+        tls,
+        proxy,
+        socket,
+        redirects,
+        retry,
+        compression,
+        cookies: None,
+        auth,
+        unix_socket: matches.value_of("unix-socket").map(PathBuf::from),
+        http_version,
+        keep_alive: matches.is_present("keep-alive"),
+        pool_max_idle: parsed_value(matches, "pool-max-idle"),
+        pool_idle_timeout: duration_value(matches, "pool-idle-timeout"),
+    }
+}
+
+/// Cookie handling for a test run from the command line, with files relative to the current
+/// directory
+fn cookie_settings(matches: &ArgMatches) -> CookieSettings {
+    let cookies = CookieFileConfig {
+        enabled: Some(matches.is_present("cookies")),
+        jar: None,
+        load: matches.value_of("cookie").map(String::from),
+        save: matches.value_of("cookie-jar").map(String::from),
+    };
+    let curdir = env::current_dir().expect("Couldn't get current working directory");
+    or_exit("cookie", cookies.load(&curdir, &mut HashMap::new()))
+}
+
 fn main() {
     let cli = App::new("Tracer")
         .version("0.1.0")
@@ -99,7 +345,7 @@ fn main() {
                 .long("header")
                 .help("Header to include in request, in HEADER=VALUE format.  Can be specified multiple times. Case insensitive")
                 .validator(|v| {
-                    if (&v).splitn(2, '=').count() == 2 {
+                    if v.splitn(2, '=').count() == 2 {
                         Ok(())
                     } else {
                         Err(format!("No '=' found in header definition {}", v))
@@ -147,6 +393,555 @@ fn main() {
                 .required(false)
                 .takes_value(true)
         )
+        .arg(
+            Arg::with_name("dns-timeout")
+                .value_name("MS")
+                .long("dns-timeout")
+                .help("Maximum time for DNS resolution, in milliseconds")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("connect-timeout")
+                .value_name("MS")
+                .long("connect-timeout")
+                .help("Maximum time to establish the TCP connection, in milliseconds")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("tls-timeout")
+                .value_name("MS")
+                .long("tls-timeout")
+                .help("Maximum time for the TLS handshake, in milliseconds")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("headers-timeout")
+                .value_name("MS")
+                .long("headers-timeout")
+                .help("Maximum time from the start of the request until the response headers arrive, including any connection setup, in milliseconds")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("read-timeout")
+                .value_name("MS")
+                .long("read-timeout")
+                .help("Maximum time to wait between chunks of the response body, in milliseconds")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .value_name("MS")
+                .long("timeout")
+                .help("Maximum time for the whole request, in milliseconds")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("cacert")
+                .long("cacert")
+                .value_name("FILE")
+                .help("Trust the CA certificates in this PEM file instead of the bundled roots")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("capath")
+                .long("capath")
+                .value_name("DIR")
+                .help("Trust the CA certificates in the PEM files in this directory instead of the bundled roots")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("ca-native")
+                .long("ca-native")
+                .help("Trust the operating system's CA certificates instead of the bundled roots")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("insecure")
+                .short("k")
+                .long("insecure")
+                .help("Don't verify server certificates")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("tls-server-name")
+                .long("tls-server-name")
+                .value_name("NAME")
+                .help("Send this name via SNI and verify the server certificate against it instead of the URL host")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("tls-resume")
+                .long("tls-resume")
+                .help("Resume TLS sessions from earlier repetitions instead of doing a full handshake every time")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("early-data")
+                .long("early-data")
+                .help("With --http3 and --tls-resume, send idempotent requests on resumed connections as 0-RTT early data")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("tls-version")
+                .long("tls-version")
+                .value_name("VERSION")
+                .help("Only offer this TLS version.  Can be specified multiple times")
+                .possible_values(&["1.2", "1.3"])
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("ciphers")
+                .long("ciphers")
+                .value_name("SUITES")
+                .help("Only offer these comma-separated cipher suites, e.g. TLS13_AES_128_GCM_SHA256")
+                .takes_value(true)
+                .use_delimiter(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("tls-info")
+                .long("tls-info")
+                .help("Print the negotiated TLS version, cipher suite and server certificate with each result")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("tcp-info")
+                .long("tcp-info")
+                .help("Print kernel TCP statistics (RTT, retransmits, congestion window) with each result (Linux only)")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("wire-bytes")
+                .long("wire-bytes")
+                .help("Print the bytes sent and received for the TLS handshake, request and response with each result")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("response-body")
+                .long("response-body")
+                .value_name("MODE")
+                .help("Hash response bodies as they arrive and drop them, or buffer each one whole in memory first")
+                .takes_value(true)
+                .possible_values(&["discard", "buffer"])
+                .default_value("discard")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("cert")
+                .short("E")
+                .long("cert")
+                .value_name("FILE")
+                .help("Client certificate chain to present to servers that ask for one, PEM unless --cert-type is p12")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("key")
+                .long("key")
+                .value_name("FILE")
+                .help("PEM private key (PKCS#8 or RSA) for --cert, if it isn't in the same file")
+                .takes_value(true)
+                .requires("cert")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("cert-type")
+                .long("cert-type")
+                .value_name("TYPE")
+                .help("Format of --cert")
+                .possible_values(&["pem", "p12"])
+                .takes_value(true)
+                .requires("cert")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("cert-password")
+                .long("cert-password")
+                .value_name("PASSWORD")
+                .help("Password of a PKCS#12 --cert")
+                .takes_value(true)
+                .requires("cert")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("proxy")
+                .short("x")
+                .long("proxy")
+                .value_name("URL")
+                .help("Connect through this proxy, http://, socks5:// or socks5h:// (remote DNS), instead of those from http_proxy, https_proxy and all_proxy")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("proxy-user")
+                .short("U")
+                .long("proxy-user")
+                .value_name("USER:PASSWORD")
+                .help("Credentials for the proxy")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("noproxy")
+                .long("noproxy")
+                .value_name("HOSTS")
+                .help("Comma separated hosts, domains and CIDR ranges to connect to directly, instead of no_proxy.  '*' disables proxies")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("unix-socket")
+                .long("unix-socket")
+                .value_name("PATH")
+                .help("Connect to this Unix domain socket instead of the URL's host, which is still sent in requests")
+                .takes_value(true)
+                .conflicts_with("http3")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("local-address")
+                .long("local-address")
+                .value_name("IP")
+                .help("Source IP address to connect from")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("local-port")
+                .long("local-port")
+                .value_name("PORT")
+                .help("Source port to connect from")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("interface")
+                .long("interface")
+                .value_name("NAME")
+                .help("Network interface to connect through (Linux only)")
+                .takes_value(true)
+                .conflicts_with("unix-socket")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("nagle")
+                .long("nagle")
+                .help("Leave Nagle's algorithm on (TCP_NODELAY is set by default)")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("tcp-keepalive")
+                .long("tcp-keepalive")
+                .value_name("MS")
+                .help("Send TCP keepalive probes after this much idle time")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("send-buffer")
+                .long("send-buffer")
+                .value_name("BYTES")
+                .help("Socket send buffer size")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("recv-buffer")
+                .long("recv-buffer")
+                .value_name("BYTES")
+                .help("Socket receive buffer size")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("ttl")
+                .long("ttl")
+                .value_name("HOPS")
+                .help("IP time to live, or hop limit for IPv6")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("dscp")
+                .long("dscp")
+                .value_name("DSCP")
+                .help("Differentiated services code point to mark packets with (0-63)")
+                .takes_value(true)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("http1.1")
+                .long("http1.1")
+                .help("Only use HTTP/1.1 (default)")
+                .conflicts_with_all(&["http2", "http2-prior-knowledge", "http3"])
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("http2")
+                .long("http2")
+                .help("Offer HTTP/2 during the TLS handshake, falling back to HTTP/1.1")
+                .conflicts_with_all(&["http1.1", "http2-prior-knowledge", "http3"])
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("http2-prior-knowledge")
+                .long("http2-prior-knowledge")
+                .help("Use HTTP/2 without negotiating it, including for plain http:// URLs")
+                .conflicts_with_all(&["http1.1", "http2", "http3"])
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("http3")
+                .long("http3")
+                .help("Use HTTP/3 over QUIC (https:// URLs only)")
+                .conflicts_with_all(&["http1.1", "http2", "http2-prior-knowledge"])
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("keep-alive")
+                .long("keep-alive")
+                .help("Reuse connections between repetitions and report warm and cold latencies separately")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("pool-max-idle")
+                .value_name("COUNT")
+                .long("pool-max-idle")
+                .help("Maximum idle connections to keep per host in keep-alive mode")
+                .requires("keep-alive")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("pool-idle-timeout")
+                .value_name("MS")
+                .long("pool-idle-timeout")
+                .help("How long idle connections are kept in keep-alive mode, in milliseconds")
+                .requires("keep-alive")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("location")
+                .short("L")
+                .long("location")
+                .help("Follow redirects and report timings for each hop")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("max-redirects")
+                .value_name("COUNT")
+                .long("max-redirects")
+                .help("Most redirects to follow, 10 by default")
+                .requires("location")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("location-trusted")
+                .long("location-trusted")
+                .help("Keep sending Authorization and Cookie headers when redirected to another host")
+                .requires("location")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("no-resend-body")
+                .long("no-resend-body")
+                .help("Don't follow 307 and 308 redirects that would send the request body again")
+                .requires("location")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("redirect-method")
+                .value_name("RULE")
+                .long("redirect-method")
+                .help("When redirects change the method to GET")
+                .possible_values(&["browser", "strict", "keep"])
+                .requires("location")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("retry")
+                .value_name("COUNT")
+                .long("retry")
+                .help("Send failed requests again up to this many times, with exponential backoff")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("retry-errors")
+                .value_name("CLASSES")
+                .long("retry-errors")
+                .help("Comma separated failure classes to retry, instead of connection-refused, connection-reset and timeout")
+                .possible_values(&[
+                    "dns",
+                    "connection-refused",
+                    "connection-reset",
+                    "tls-handshake",
+                    "tls-verification",
+                    "tls-client-auth",
+                    "proxy",
+                    "protocol",
+                    "timeout",
+                    "body-truncated",
+                    "decompression",
+                    "other",
+                ])
+                .use_delimiter(true)
+                .requires("retry")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("retry-statuses")
+                .value_name("CODES")
+                .long("retry-statuses")
+                .help("Comma separated response statuses to retry, instead of 502, 503 and 504")
+                .use_delimiter(true)
+                .requires("retry")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("retry-backoff")
+                .value_name("MS")
+                .long("retry-backoff")
+                .help("Wait before the first retry in milliseconds, doubled for each retry after it (Default 100)")
+                .requires("retry")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("retry-max-backoff")
+                .value_name("MS")
+                .long("retry-max-backoff")
+                .help("Longest wait before a retry in milliseconds (Default 10000)")
+                .requires("retry")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("no-retry-jitter")
+                .long("no-retry-jitter")
+                .help("Wait the full backoff before each retry, rather than a random time between half and all of it")
+                .requires("retry")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("compressed")
+                .long("compressed")
+                .help("Ask for a compressed response with gzip, deflate, br or zstd, and decode it")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("encoding")
+                .value_name("ENCODING")
+                .long("encoding")
+                .help("Ask for and decode only this encoding.  Can be specified multiple times")
+                .possible_values(&["gzip", "deflate", "br", "zstd"])
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("hash")
+                .value_name("BODY")
+                .long("hash")
+                .help("Hash the response body as decoded (default) or as encoded on the wire")
+                .possible_values(&["decoded", "encoded"])
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("user")
+                .value_name("USER:PASSWORD")
+                .short("u")
+                .long("user")
+                .help("Authenticate with HTTP basic authentication, or digest with --digest.  The access and secret key with --aws-sigv4")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("digest")
+                .long("digest")
+                .help("Use HTTP digest authentication with --user, answering the server's challenge and reusing it for later requests")
+                .requires("user")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("bearer")
+                .value_name("TOKEN")
+                .long("bearer")
+                .help("Authenticate with a bearer token")
+                .conflicts_with_all(&["user", "bearer-env", "bearer-file", "aws-sigv4"])
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("bearer-env")
+                .value_name("VAR")
+                .long("bearer-env")
+                .help("Authenticate with the bearer token in this environment variable")
+                .conflicts_with_all(&["user", "bearer-file", "aws-sigv4"])
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("bearer-file")
+                .value_name("FILE")
+                .long("bearer-file")
+                .help("Authenticate with the bearer token in this file")
+                .conflicts_with_all(&["user", "aws-sigv4"])
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("aws-sigv4")
+                .value_name("REGION:SERVICE")
+                .long("aws-sigv4")
+                .help("Sign requests with AWS Signature Version 4 for this region and service (execute-api if left out).  Credentials come from --user or AWS_ACCESS_KEY_ID, AWS_SECRET_ACCESS_KEY and AWS_SESSION_TOKEN, and the region from AWS_REGION if left out")
+                .conflicts_with("digest")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("cookies")
+                .long("cookies")
+                .help("Keep cookies set by responses and send them with later requests and redirects")
+                .required(false)
+        )
+        .arg(
+            Arg::with_name("cookie")
+                .value_name("FILE")
+                .short("b")
+                .long("cookie")
+                .help("Start with the cookies in this Netscape format cookie file.  Implies --cookies")
+                .required(false)
+                .takes_value(true)
+        )
+        .arg(
+            Arg::with_name("cookie-jar")
+                .value_name("FILE")
+                .short("c")
+                .long("cookie-jar")
+                .help("Write the cookies to this Netscape format cookie file when done.  Implies --cookies")
+                .required(false)
+                .takes_value(true)
+        )
         .arg(
             Arg::with_name("URL")
                 .takes_value(true)
@@ -164,11 +959,63 @@ fn main() {
                      .required(true)
                      .help("Config file that specifies the test(s) to run")
                 )
+        )
+        .subcommand(
+            SubCommand::with_name("tls-scan")
+                .about("Try each TLS version and cipher suite against a server, -n handshakes each")
+                .arg(Arg::with_name("URL")
+                     .index(1)
+                     .takes_value(true)
+                     .required(true)
+                     .help("https URL to scan")
+                )
         );
     let matches = cli.get_matches();
+    let repeat = if matches.is_present("C") {
+        None
+    } else if matches.is_present("n") {
+        let count = value_t!(matches, "n", usize).unwrap_or_else(|e| e.exit());
+        Some(count)
+    } else {
+        Some(1)
+    };
+    let level = match matches.occurrences_of("v") {
+        0 => Level::Warning,
+        1 => Level::Info,
+        2 => Level::Debug,
+        3 => Level::Trace,
+        _ => {
+            eprintln!("WARNING: more than -vvv is ignored");
+            Level::Trace
+        }
+    };
+    let stats = matches.is_present("stats");
+
+    let logger = root_logger(level);
+    let interrupted = interrupt::register().expect("Could not register interrupt handler");
+
+    if let Some(m) = matches.subcommand_matches("tls-scan") {
+        let url = m
+            .value_of("URL")
+            .expect("No URL value")
+            .parse::<Uri>()
+            .expect("Invalid URL");
+        let handshakes = repeat.unwrap_or(1);
+        if let Err(e) = run_scan(
+            logger,
+            url,
+            client_options(&matches),
+            handshakes,
+            interrupted,
+        ) {
+            eprintln!("Error running scan: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     let config = if let Some(m) = matches.subcommand_matches("test") {
         let config_path = m.value_of("config").unwrap();
-        match Config::load(&config_path) {
+        match Config::load(config_path) {
             Ok(conf) => conf,
             Err(e) => {
                 eprintln!("Could not load config: {}", e);
@@ -210,31 +1057,29 @@ fn main() {
         } else {
             CaptureHeaderConfig::empty()
         };
-        Config::single(url, method, headers, payload, capture_headers)
+        let mut client = client_options(&matches);
+        let cookies = cookie_settings(&matches);
+        client.cookies = cookies.jar;
+        Config::single(TestConfig {
+            name: url.to_string(),
+            url,
+            method,
+            headers,
+            payload,
+            capture_headers,
+            tls_info: matches.is_present("tls-info"),
+            tcp_info: matches.is_present("tcp-info"),
+            wire_bytes: matches.is_present("wire-bytes"),
+            response_body: match matches.value_of("response-body") {
+                Some("buffer") => BodyMode::Buffer,
+                _ => BodyMode::Discard,
+            },
+            cookie_jar: cookies.shared,
+            save_cookies: cookies.save,
+            client,
+        })
     };
 
-    let repeat = if matches.is_present("C") {
-        None
-    } else if matches.is_present("n") {
-        let count = value_t!(matches, "n", usize).unwrap_or_else(|e| e.exit());
-        Some(count)
-    } else {
-        Some(1)
-    };
-    let level = match matches.occurrences_of("v") {
-        0 => Level::Warning,
-        1 => Level::Info,
-        2 => Level::Debug,
-        3 => Level::Trace,
-        _ => {
-            eprintln!("WARNING: more than -vvv is ignored");
-            Level::Trace
-        }
-    };
-    let stats = matches.is_present("stats");
-
-    let logger = root_logger(level);
-    let interrupted = interrupt::register().expect("Could not register interrupt handler");
     if let Err(e) = run_tests(logger.clone(), config, repeat, stats, interrupted) {
         eprintln!("Error running tests: {}", e);
         std::process::exit(1);
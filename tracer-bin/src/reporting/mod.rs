@@ -1,3 +1,5 @@
+mod scanreport;
 mod testreport;
 
-pub use self::testreport::{format_snapshot_stats, TestReport};
+pub use self::scanreport::ScanReport;
+pub use self::testreport::{format_error_rate, format_snapshot_stats, FailureReport, TestReport};
@@ -0,0 +1,61 @@
+use super::testreport::{format_snapshot_stats, INSECURE_MARKER};
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use tracer_client::client::Metric;
+use tracer_client::tls::TlsVersion;
+use tracer_client::{ClientError, ErrorKind};
+use tracer_metrics::data::Snapshot;
+
+/// Report line for one TLS version and cipher suite combination tried by `tls-scan`
+pub struct ScanReport {
+    version: TlsVersion,
+    suite: String,
+    insecure: bool,
+    outcome: Outcome,
+}
+
+enum Outcome {
+    /// At least one handshake completed, with these timings
+    Accepted(Snapshot<Metric>),
+    /// The server refused the handshake
+    Rejected(String),
+    /// Something other than the handshake went wrong, so the result is unknown
+    Failed(ErrorKind, String),
+}
+
+impl ScanReport {
+    pub fn new(
+        version: TlsVersion,
+        suite: String,
+        insecure: bool,
+        handshakes: Snapshot<Metric>,
+        error: Option<ClientError>,
+    ) -> ScanReport {
+        let outcome = match error {
+            _ if handshakes.count().unwrap_or(0) > 0 => Outcome::Accepted(handshakes),
+            Some(e) if e.kind() == ErrorKind::TlsHandshake => Outcome::Rejected(e.to_string()),
+            Some(e) => Outcome::Failed(e.kind(), e.to_string()),
+            None => Outcome::Failed(ErrorKind::Other, "no handshakes attempted".into()),
+        };
+        ScanReport {
+            version,
+            suite,
+            insecure,
+            outcome,
+        }
+    }
+}
+
+impl Display for ScanReport {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "* ")?;
+        if self.insecure {
+            write!(f, "{}", INSECURE_MARKER)?;
+        }
+        write!(f, "{} {}: ", self.version, self.suite)?;
+        match self.outcome {
+            Outcome::Accepted(ref s) => write!(f, "accepted, TLS: {}", format_snapshot_stats(s)),
+            Outcome::Rejected(ref msg) => write!(f, "rejected ({})", msg),
+            Outcome::Failed(kind, ref msg) => write!(f, "unknown ({}) {}", kind, msg),
+        }
+    }
+}
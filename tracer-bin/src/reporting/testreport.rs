@@ -4,36 +4,104 @@ use http::response::Parts;
 use http::HeaderMap;
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::net::SocketAddr;
 use std::time::Duration;
 use tracer_client::client::Metric;
-use tracer_metrics::data::Snapshot;
+use tracer_client::compression::Decompression;
+use tracer_client::connectors::ConnectionInfo;
+use tracer_client::context::RequestMetrics;
+use tracer_client::redirect::Redirects;
+use tracer_client::retry::Retries;
+use tracer_client::socket::TcpInfo;
+use tracer_client::tls::TlsInfo;
+use tracer_client::{ClientError, ErrorKind, HttpVersion};
+use tracer_metrics::data::{SampleValue, Snapshot};
 
 pub struct TestReport {
     config: TestConfig,
-    snapshots: Vec<Snapshot<Metric>>,
+    metrics: RequestMetrics,
     res: Parts,
     body_hash: String,
     captured_headers: HashMap<String, String>,
+    reused: bool,
+    zero_rtt: bool,
+    tls: Option<TlsInfo>,
+    alpn: Option<String>,
+    local_addr: Option<SocketAddr>,
+    connect_tcp: Option<TcpInfo>,
+    response_tcp: Option<TcpInfo>,
+    redirects: Option<Redirects>,
+    retries: Option<Retries>,
+    decompression: Option<Decompression>,
 }
 
 impl TestReport {
     pub fn new(
         config: TestConfig,
-        snapshots: Vec<Snapshot<Metric>>,
+        metrics: RequestMetrics,
         res: Parts,
         body_hash: String,
     ) -> TestReport {
         let captured_headers = extract_configured_headers(&config.capture_headers, &res.headers);
+        let info = res.extensions.get::<ConnectionInfo>();
+        let reused = info.map(|i| i.is_reused()).unwrap_or(false);
+        let zero_rtt = info.map(|i| i.is_zero_rtt()).unwrap_or(false);
+        let tls = info.and_then(|i| i.tls()).cloned();
+        let alpn = info
+            .and_then(|i| i.alpn_protocol())
+            .map(|p| String::from_utf8_lossy(p).into_owned());
+        let local_addr = info.and_then(|i| i.local_addr());
+        let connect_tcp = info.and_then(|i| i.connect_tcp_info()).cloned();
+        let response_tcp = info.and_then(|i| i.tcp_info()).cloned();
+        let redirects = res.extensions.get::<Redirects>().cloned();
+        let retries = res.extensions.get::<Retries>().cloned();
+        let decompression = res.extensions.get::<Decompression>().cloned();
         TestReport {
             config,
-            snapshots,
+            metrics,
             res,
             body_hash,
             captured_headers,
+            reused,
+            zero_rtt,
+            tls,
+            alpn,
+            local_addr,
+            connect_tcp,
+            response_tcp,
+            redirects,
+            retries,
+            decompression,
         }
     }
-    pub fn take_config(self) -> TestConfig {
-        self.config
+}
+
+/// Report line for a test iteration that didn't produce a response
+pub struct FailureReport {
+    name: String,
+    insecure: bool,
+    kind: ErrorKind,
+    message: String,
+}
+
+impl FailureReport {
+    pub fn new(config: &TestConfig, err: &ClientError) -> FailureReport {
+        FailureReport {
+            name: config.name.clone(),
+            insecure: config.client.tls.insecure,
+            kind: err.kind(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl Display for FailureReport {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "* ")?;
+        if self.insecure {
+            write!(f, "{}", INSECURE_MARKER)?;
+        }
+        write!(f, "{} ({}) {}", self.name, self.kind, self.message)
     }
 }
 
@@ -83,6 +151,18 @@ fn extract_configured_headers(
         .collect()
 }
 
+/// Summarize how many of the requests counted in `requests` failed
+pub fn format_error_rate(requests: &Snapshot<Metric>, failures: &[Snapshot<Metric>]) -> String {
+    let total = requests.count().unwrap_or(0);
+    let failed: u64 = failures.iter().filter_map(|s| s.count()).sum();
+    let rate = if total > 0 {
+        failed as f64 * 100.0 / total as f64
+    } else {
+        0.0
+    };
+    format!("{}/{} failed ({:.1}%)", failed, total, rate)
+}
+
 pub fn format_snapshot_stats(s: &Snapshot<Metric>) -> String {
     if let Some(h) = s.latency_histogram() {
         format!(
@@ -93,46 +173,266 @@ pub fn format_snapshot_stats(s: &Snapshot<Metric>) -> String {
             fmt_duration(&h.max()),
             fmt_duration(&h.stdev())
         )
+    } else if let Some(c) = s.count() {
+        format!("count {}", c)
     } else {
         String::new()
     }
 }
 
-fn abbrev_metric(m: Metric) -> &'static str {
+/// Flags reports from tests that skip certificate verification, so they aren't mistaken for real results
+pub(super) const INSECURE_MARKER: &str = "[INSECURE] ";
+
+/// Short name for metrics shown on each report line.  Aggregates like counters and
+/// the warm/cold split belong in the stats summary instead.
+fn abbrev_metric(m: Metric) -> Option<&'static str> {
     use tracer_client::client::Metric::*;
     match m {
-        Dns => "DNS",
-        Connection => "Conn",
-        Tls => "TLS",
-        Headers => "Hdrs",
-        FullResponse => "Resp",
-        BodyLen => "BodyLen",
-        HeaderLen => "HdrLen",
+        Dns => Some("DNS"),
+        Connection => Some("Conn"),
+        ProxyConnect => Some("ProxyConn"),
+        ProxyHandshake => Some("Tunnel"),
+        Tls => Some("TLS"),
+        Http2Settings => Some("H2Settings"),
+        QuicHandshake => Some("QUIC"),
+        RequestWrite => Some("Sent"),
+        FirstByte => Some("TTFB"),
+        Headers => Some("Hdrs"),
+        FullResponse => Some("Resp"),
+        BodyLen => Some("BodyLen"),
+        DecodedBodyLen => Some("Decoded"),
+        DecompressTime => Some("Decomp"),
+        Throughput => Some("Rate"),
+        PeakThroughput => Some("Peak"),
+        HeaderLen => Some("HdrLen"),
+        Redirects => Some("Redirects"),
+        RedirectTotal => Some("Total"),
+        Retries => Some("Retries"),
+        FirstAttempt => Some("First"),
+        EventualSuccess => Some("Eventual"),
+        AuthChallenge => Some("Auth"),
+        _ => None,
     }
 }
 
-fn format_snapshot(s: &Snapshot<Metric>, f: &mut Formatter) -> FmtResult {
-    let display = if Metric::latency_metrics().contains(&s.key()) {
-        fmt_duration(&s.gauge_as_duration().unwrap())
-    } else if Metric::size_metrics().contains(&s.key()) {
-        fmt_size(s.gauge().unwrap())
+fn format_metric(m: Metric, metrics: &RequestMetrics, f: &mut Formatter) -> FmtResult {
+    let abbrev = match abbrev_metric(m) {
+        Some(a) => a,
+        None => return Ok(()),
+    };
+    // Steps this request skipped, like connecting on a reused connection, have no value
+    let display = if Metric::latency_metrics().contains(&m) {
+        metrics.elapsed(m).map(|d| fmt_duration(&d))
+    } else if Metric::size_metrics().contains(&m) {
+        metrics.value(m).map(fmt_size)
+    } else if Metric::throughput_metrics().contains(&m) {
+        metrics.value(m).map(|r| format!("{}/s", fmt_size(r)))
+    } else if let Some(SampleValue::Count(c)) = metrics.get(m) {
+        Some(c.to_string())
     } else {
-        "".into()
+        None
+    };
+    match display {
+        Some(display) => write!(f, "{}: {} ", abbrev, display),
+        None => Ok(()),
+    }
+}
+
+/// Kernel RTTs are in microseconds, and often well under a millisecond
+fn fmt_rtt(d: &Duration) -> String {
+    format!("{:.3}ms", d.as_micros() as f64 / 1000.0)
+}
+
+fn format_tcp_info(connect: Option<&TcpInfo>, response: &TcpInfo, f: &mut Formatter) -> FmtResult {
+    write!(f, "\n    TCP:")?;
+    if let Some(connect) = connect {
+        write!(
+            f,
+            " connect RTT {}/var {},",
+            fmt_rtt(&connect.rtt),
+            fmt_rtt(&connect.rtt_var)
+        )?;
+    }
+    write!(
+        f,
+        " response RTT {}/var {} retransmits {} cwnd {}",
+        fmt_rtt(&response.rtt),
+        fmt_rtt(&response.rtt_var),
+        response.retransmits,
+        response.cwnd
+    )?;
+    if let Some(acked) = response.bytes_acked {
+        write!(f, " acked {}", fmt_size(acked))?;
+    }
+    Ok(())
+}
+
+fn format_wire_bytes(metrics: &RequestMetrics, f: &mut Formatter) -> FmtResult {
+    let sent_received = |sent: Metric, received: Metric| {
+        Some(format!(
+            "{} sent/{} received",
+            fmt_size(metrics.value(sent)?),
+            fmt_size(metrics.value(received)?)
+        ))
     };
-    write!(f, "{}: {} ", abbrev_metric(s.key()), display)
+    write!(f, "\n    Bytes:")?;
+    let handshake = sent_received(
+        Metric::TlsHandshakeBytesSent,
+        Metric::TlsHandshakeBytesReceived,
+    );
+    if let Some(handshake) = handshake {
+        write!(f, " TLS handshake {},", handshake)?;
+    }
+    if let Some(request) = metrics.value(Metric::RequestBytes) {
+        write!(f, " request {},", fmt_size(request))?;
+    }
+    if let (Some(head), Some(body)) = (
+        metrics.value(Metric::ResponseHeaderBytes),
+        metrics.value(Metric::ResponseBodyBytes),
+    ) {
+        write!(
+            f,
+            " response headers {} body {},",
+            fmt_size(head),
+            fmt_size(body)
+        )?;
+    }
+    match sent_received(Metric::WireBytesSent, Metric::WireBytesReceived) {
+        Some(wire) => write!(f, " on the wire {}", wire),
+        None => write!(f, " not counted"),
+    }
+}
+
+/// A line for each redirect followed, with the timings of the request that got it
+fn format_redirects(redirects: &Redirects, f: &mut Formatter) -> FmtResult {
+    for hop in redirects.hops.iter() {
+        write!(f, "\n    -> {} {} ", hop.status.as_u16(), hop.url)?;
+        for m in Metric::all_metrics() {
+            format_metric(m, &hop.metrics, f)?;
+        }
+    }
+    write!(f, "\n    => {}", redirects.url)
+}
+
+/// A line for each attempt that was retried, with why and its timings
+fn format_retries(retries: &Retries, f: &mut Formatter) -> FmtResult {
+    for attempt in retries.attempts.iter() {
+        write!(f, "\n    x {} ", attempt.reason)?;
+        for m in Metric::all_metrics() {
+            format_metric(m, &attempt.metrics, f)?;
+        }
+        write!(f, "Backoff: {}", fmt_duration(&attempt.backoff))?;
+    }
+    Ok(())
+}
+
+fn format_tls_info(tls: &TlsInfo, alpn: Option<&str>, f: &mut Formatter) -> FmtResult {
+    write!(
+        f,
+        "\n    TLS: {}",
+        tls.version.as_deref().unwrap_or("unknown version")
+    )?;
+    if let Some(ref suite) = tls.cipher_suite {
+        write!(f, " {}", suite)?;
+    }
+    if let Some(alpn) = alpn {
+        write!(f, " ALPN: {}", alpn)?;
+    }
+    if let Some(ref sni) = tls.sni {
+        write!(f, " SNI: {}", sni)?;
+    }
+    if tls.resumed {
+        write!(f, " (resumed session, no certificate sent)")?;
+    }
+    if let Some(ref leaf) = tls.leaf {
+        write!(
+            f,
+            "\n    Certificate: {} issued by {}, chain of {}, ",
+            leaf.subject, leaf.issuer, tls.chain_len
+        )?;
+        let days = leaf.days_until_expiry();
+        if days >= 0 {
+            write!(f, "expires in {} days", days)?;
+        } else {
+            write!(f, "EXPIRED {} days ago", -days)?;
+        }
+        if !leaf.sans.is_empty() {
+            write!(f, "\n    SANs: {}", leaf.sans.join(", "))?;
+        }
+    }
+    Ok(())
 }
 
 impl Display for TestReport {
-    fn fmt(&self, mut f: &mut Formatter) -> FmtResult {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "* ")?;
+        if self.config.client.tls.insecure {
+            write!(f, "{}", INSECURE_MARKER)?;
+        }
         write!(
             f,
-            "* {} ({}) Hash: {} ",
+            "{} ({}) Hash: {} ",
             self.config.name,
             self.res.status,
             &self.body_hash[0..8]
         )?;
-        for s in &self.snapshots {
-            format_snapshot(&s, &mut f)?;
+        if self.config.client.http_version != HttpVersion::Http1 {
+            write!(f, "{:?} ", self.res.version)?;
+        }
+        if self.config.client.keep_alive {
+            write!(f, "{} ", if self.reused { "Reused" } else { "New" })?;
+        }
+        if self.config.client.http_version == HttpVersion::Http3 && !self.reused {
+            write!(f, "{} ", if self.zero_rtt { "0-RTT" } else { "1-RTT" })?;
+        }
+        if self.config.client.tls.resume && !self.reused {
+            if let Some(ref tls) = self.tls {
+                write!(
+                    f,
+                    "{} ",
+                    if tls.resumed {
+                        "TLS-Resumed"
+                    } else {
+                        "TLS-Full"
+                    }
+                )?;
+            }
+        }
+        let socket = &self.config.client.socket;
+        if socket.local_address.is_some()
+            || socket.local_port.is_some()
+            || socket.interface.is_some()
+        {
+            if let Some(addr) = self.local_addr {
+                write!(f, "Local: {} ", addr)?;
+            }
+        }
+        if let Some(ref decompression) = self.decompression {
+            write!(f, "Enc: {} ", decompression.encoding)?;
+        }
+        for m in Metric::all_metrics() {
+            format_metric(m, &self.metrics, f)?;
+        }
+        if let Some(ref retries) = self.retries {
+            format_retries(retries, f)?;
+        }
+        if let Some(ref redirects) = self.redirects {
+            format_redirects(redirects, f)?;
+        }
+        if self.config.tls_info {
+            if let Some(ref tls) = self.tls {
+                format_tls_info(tls, self.alpn.as_deref(), f)?;
+            }
+        }
+        if self.config.tcp_info {
+            if let Some(ref tcp) = self.response_tcp {
+                // Figures from when the connection was set up belong to an earlier request
+                let connect = self.connect_tcp.as_ref().filter(|_| !self.reused);
+                format_tcp_info(connect, tcp, f)?;
+            }
+        }
+        if self.config.wire_bytes {
+            format_wire_bytes(&self.metrics, f)?;
         }
         if !self.captured_headers.is_empty() {
             for (k, v) in self.captured_headers.iter() {
@@ -0,0 +1,89 @@
+use crate::interrupt::Interrupted;
+use crate::reporting::ScanReport;
+use http::{Request, Uri};
+use hyper::Body;
+use tracer_client::client::Metric;
+use tracer_client::tls::{self, CipherSuite, TlsVersion};
+use tracer_client::{Client, ClientOptions, HttpVersion};
+use tracer_metrics::Collector;
+
+/// Try every TLS version and cipher suite combination allowed by `options` against `url`,
+/// printing which ones the server accepts and how long their handshakes take
+pub async fn scan(
+    url: Uri,
+    options: ClientOptions,
+    handshakes: usize,
+    interrupted: Interrupted,
+    logger: slog::Logger,
+) {
+    for (version, suite) in combinations(&options) {
+        if interrupted.interrupted() {
+            break;
+        }
+        let report = scan_one(&url, &options, version, suite, handshakes, &logger).await;
+        println!("{}", report);
+    }
+}
+
+fn combinations(options: &ClientOptions) -> Vec<(TlsVersion, CipherSuite)> {
+    let versions = options
+        .tls
+        .versions
+        .clone()
+        .unwrap_or_else(|| TlsVersion::all().to_vec());
+    let suites: Vec<CipherSuite> = tls::cipher_suites()
+        .into_iter()
+        .filter(|s| match options.tls.cipher_suites {
+            Some(ref names) => names.contains(&s.name),
+            None => true,
+        })
+        .collect();
+    TlsVersion::all()
+        .iter()
+        .filter(|v| versions.contains(v))
+        .flat_map(|v| {
+            suites
+                .iter()
+                .filter(move |s| s.version == *v)
+                .map(move |s| (*v, s.clone()))
+        })
+        .collect()
+}
+
+async fn scan_one(
+    url: &Uri,
+    options: &ClientOptions,
+    version: TlsVersion,
+    suite: CipherSuite,
+    handshakes: usize,
+    logger: &slog::Logger,
+) -> ScanReport {
+    let mut collector = Collector::new();
+    Client::configure_collector_defaults(&mut collector);
+    // A new connection and full handshake for every request, over TCP
+    let mut options = options.clone();
+    options.http_version = HttpVersion::Http1;
+    options.keep_alive = false;
+    options.tls.resume = false;
+    options.tls.versions = Some(vec![version]);
+    options.tls.cipher_suites = Some(vec![suite.name.clone()]);
+    let insecure = options.tls.insecure;
+    let client = Client::new_with_options(collector.handle(), options);
+
+    let mut error = None;
+    for _ in 0..handshakes {
+        let req = Request::get(url.clone()).body(Body::empty()).unwrap();
+        if let Err(e) = client.request_fully(req).await {
+            slog::debug!(logger, "{} {} failed: {:?}", version, suite.name, e);
+            error = Some(e);
+        }
+    }
+    collector.process_outstanding();
+    ScanReport::new(
+        version,
+        suite.name,
+        insecure,
+        collector.snapshot(&Metric::Tls),
+        error,
+    )
+}
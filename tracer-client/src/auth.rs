@@ -0,0 +1,716 @@
+use crate::date::DateTime;
+use crate::redirect::default_port;
+use hmac::{Hmac, Mac};
+use hyper::header::{HeaderMap, HeaderValue, AUTHORIZATION, CONTENT_TYPE, HOST, WWW_AUTHENTICATE};
+use hyper::http::Method;
+use hyper::Uri;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+/// Credentials `Client` authenticates requests with, unless they set `Authorization`
+/// themselves
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Auth {
+    Basic {
+        user: String,
+        password: String,
+    },
+    Bearer(String),
+    /// HTTP digest authentication, sent in answer to a `401` challenge.  Later requests to the
+    /// same origin answer the last challenge up front, until the server challenges them again.
+    Digest {
+        user: String,
+        password: String,
+    },
+    /// Requests are signed with AWS Signature Version 4
+    AwsSigV4(AwsSigV4),
+}
+
+impl Auth {
+    /// `Authorization` for schemes that don't depend on the request
+    pub(crate) fn header(&self) -> Option<HeaderValue> {
+        let value = match self {
+            Auth::Basic { user, password } => {
+                format!(
+                    "Basic {}",
+                    base64::encode(&format!("{}:{}", user, password))
+                )
+            }
+            Auth::Bearer(token) => format!("Bearer {}", token),
+            Auth::Digest { .. } | Auth::AwsSigV4(_) => return None,
+        };
+        HeaderValue::from_str(&value).ok()
+    }
+}
+
+/// Headers added by `AwsSigV4::sign`, besides `Authorization`, which only go with a signature
+pub(crate) const AWS_SIGNING_HEADERS: [&str; 3] =
+    ["x-amz-date", "x-amz-security-token", "x-amz-content-sha256"];
+
+/// AWS credentials and the endpoint they're for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AwsSigV4 {
+    pub access_key: String,
+    pub secret_key: String,
+    /// For temporary credentials
+    pub session_token: Option<String>,
+    pub region: String,
+    /// Such as `execute-api` for API Gateway
+    pub service: String,
+}
+
+impl AwsSigV4 {
+    /// Sign a request as sent at `now`, adding `X-Amz-Date` and `Authorization` headers
+    pub(crate) fn sign(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &mut HeaderMap,
+        body: &[u8],
+        now: SystemTime,
+    ) {
+        let time = amz_date(now);
+        let date = &time[..8];
+        headers.insert("x-amz-date", HeaderValue::from_str(&time).unwrap());
+        if let Some(token) = self
+            .session_token
+            .as_ref()
+            .and_then(|t| HeaderValue::from_str(t).ok())
+        {
+            headers.insert("x-amz-security-token", token);
+        }
+        let payload = hex(&Sha256::digest(body));
+        if self.service == "s3" {
+            let value = HeaderValue::from_str(&payload).unwrap();
+            headers.insert("x-amz-content-sha256", value);
+        }
+
+        // Host as hyper sends it, unless the request sets it, which leaves out the scheme's
+        // default port
+        let host = match headers.get(HOST).and_then(|h| h.to_str().ok()) {
+            Some(host) => host.to_string(),
+            None => {
+                let host = uri.host().unwrap_or_default();
+                match uri.port_u16().filter(|p| Some(*p) != default_port(uri)) {
+                    Some(port) => format!("{}:{}", host, port),
+                    None => host.to_string(),
+                }
+            }
+        };
+        let mut signed: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        signed.insert("host".to_string(), vec![host]);
+        for (name, value) in headers.iter() {
+            if name == CONTENT_TYPE || name.as_str().starts_with("x-amz-") {
+                if let Ok(value) = value.to_str() {
+                    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+                    signed.entry(name.to_string()).or_default().push(value);
+                }
+            }
+        }
+        let canonical_headers: String = signed
+            .iter()
+            .map(|(name, values)| format!("{}:{}\n", name, values.join(",")))
+            .collect();
+        let signed_headers = signed.keys().cloned().collect::<Vec<_>>().join(";");
+
+        // S3 paths are signed as they are, other services encode them once more
+        let path = match uri.path() {
+            "" => "/",
+            path => path,
+        };
+        let path = if self.service == "s3" {
+            path.to_string()
+        } else {
+            aws_encode(path.as_bytes(), false)
+        };
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method,
+            path,
+            canonical_query(uri.query().unwrap_or("")),
+            canonical_headers,
+            signed_headers,
+            payload
+        );
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            time,
+            scope,
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let key = format!("AWS4{}", self.secret_key);
+        let key = hmac(key.as_bytes(), date);
+        let key = hmac(&key, &self.region);
+        let key = hmac(&key, &self.service);
+        let key = hmac(&key, "aws4_request");
+        let signature = hex(&hmac(&key, &string_to_sign));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, signed_headers, signature
+        );
+        if let Ok(value) = HeaderValue::from_str(&authorization) {
+            headers.insert(AUTHORIZATION, value);
+        }
+    }
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).expect("HMAC takes keys of any length");
+    mac.input(data.as_bytes());
+    mac.result().code().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Percent-encode all but the characters AWS leaves alone, and `/` unless `slash`
+fn aws_encode(data: &[u8], slash: bool) -> String {
+    let mut encoded = String::with_capacity(data.len());
+    for &b in data {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(b as char)
+            }
+            b'/' if !slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", b)),
+        }
+    }
+    encoded
+}
+
+fn percent_decode(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = s.get(i + 1..i + 3).filter(|_| bytes[i] == b'%');
+        match hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// The query with its parameters encoded the same way and sorted
+fn canonical_query(query: &str) -> String {
+    let mut params: Vec<(String, String)> = query
+        .split('&')
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            let (name, value) = p.split_once('=').unwrap_or((p, ""));
+            (
+                aws_encode(&percent_decode(name), true),
+                aws_encode(&percent_decode(value), true),
+            )
+        })
+        .collect();
+    params.sort();
+    let params: Vec<String> = params
+        .into_iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect();
+    params.join("&")
+}
+
+/// `YYYYMMDDTHHMMSSZ`, in UTC
+fn amz_date(t: SystemTime) -> String {
+    let date = DateTime::from_system_time(t);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        date.year, date.month, date.day, date.hour, date.minute, date.second
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum DigestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    fn hash(self, data: &[u8]) -> String {
+        match self {
+            DigestAlgorithm::Md5 => hex(&Md5::digest(data)),
+            DigestAlgorithm::Sha256 => hex(&Sha256::digest(data)),
+        }
+    }
+}
+
+/// A `Digest` challenge from a `WWW-Authenticate` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: DigestAlgorithm,
+    /// Name the algorithm was given, as it's echoed back
+    algorithm_name: String,
+    /// `-sess` variant of the algorithm
+    session: bool,
+    /// Quality of protection, `None` for the original RFC 2069 digest
+    qop: Option<&'static str>,
+    /// Times the nonce has been answered, this answer included
+    nc: u32,
+}
+
+impl DigestChallenge {
+    /// The strongest `Digest` challenge in a response's headers that can be answered
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<DigestChallenge> {
+        headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .flat_map(challenges)
+            .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("digest"))
+            .filter_map(|(_, params)| DigestChallenge::from_params(params))
+            .max_by_key(|c| c.algorithm)
+    }
+
+    fn from_params(params: Vec<(String, String)>) -> Option<DigestChallenge> {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, v)| v.clone())
+        };
+        let algorithm_name = param("algorithm").unwrap_or_else(|| "MD5".to_string());
+        let (algorithm, session) = match algorithm_name.to_ascii_uppercase().as_str() {
+            "MD5" => (DigestAlgorithm::Md5, false),
+            "MD5-SESS" => (DigestAlgorithm::Md5, true),
+            "SHA-256" => (DigestAlgorithm::Sha256, false),
+            "SHA-256-SESS" => (DigestAlgorithm::Sha256, true),
+            _ => return None,
+        };
+        let qop = match param("qop") {
+            None => None,
+            Some(qop) => {
+                let offered: Vec<&str> = qop.split(',').map(str::trim).collect();
+                if offered.contains(&"auth") {
+                    Some("auth")
+                } else if offered.contains(&"auth-int") {
+                    Some("auth-int")
+                } else {
+                    return None;
+                }
+            }
+        };
+        Some(DigestChallenge {
+            realm: param("realm")?,
+            nonce: param("nonce")?,
+            opaque: param("opaque"),
+            algorithm,
+            algorithm_name,
+            session,
+            qop,
+            nc: 1,
+        })
+    }
+
+    /// `Authorization` answering the challenge for a request
+    pub(crate) fn authorization(
+        &self,
+        user: &str,
+        password: &str,
+        method: &Method,
+        uri: &Uri,
+        body: &[u8],
+        cnonce: &str,
+    ) -> String {
+        let h = |data: String| self.algorithm.hash(data.as_bytes());
+        let uri = uri.path_and_query().map_or("/", |p| p.as_str());
+        let nc = format!("{:08x}", self.nc);
+        let mut ha1 = h(format!("{}:{}:{}", user, self.realm, password));
+        if self.session {
+            ha1 = h(format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = match self.qop {
+            Some("auth-int") => h(format!("{}:{}:{}", method, uri, self.algorithm.hash(body))),
+            _ => h(format!("{}:{}", method, uri)),
+        };
+        let response = match self.qop {
+            Some(qop) => h(format!(
+                "{}:{}:{}:{}:{}:{}",
+                ha1, self.nonce, nc, cnonce, qop, ha2
+            )),
+            None => h(format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        };
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm={}, response=\"{}\"",
+            quote(user),
+            quote(&self.realm),
+            quote(&self.nonce),
+            uri,
+            self.algorithm_name,
+            response
+        );
+        if let Some(qop) = self.qop {
+            header.push_str(&format!(", qop={}, nc={}, cnonce=\"{}\"", qop, nc, cnonce));
+        }
+        if let Some(ref opaque) = self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        header
+    }
+}
+
+/// The digest challenges answered so far, by origin, so later requests can answer them without
+/// waiting for a `401`
+#[derive(Debug, Default)]
+pub(crate) struct DigestNonces {
+    challenges: Mutex<HashMap<String, DigestChallenge>>,
+}
+
+impl DigestNonces {
+    /// Keep a challenge received for `uri`, to be answered now.  Its nonce count carries on
+    /// from the last answer when the nonce is the same.
+    pub(crate) fn insert(&self, uri: &Uri, mut challenge: DigestChallenge) -> DigestChallenge {
+        let mut challenges = self.challenges.lock().unwrap();
+        if let Some(last) = challenges.get(&origin(uri)) {
+            if last.nonce == challenge.nonce {
+                challenge.nc = last.nc + 1;
+            }
+        }
+        challenges.insert(origin(uri), challenge.clone());
+        challenge
+    }
+
+    /// The challenge last received from the origin of `uri`, counted on to answer it again
+    pub(crate) fn next(&self, uri: &Uri) -> Option<DigestChallenge> {
+        let mut challenges = self.challenges.lock().unwrap();
+        let challenge = challenges.get_mut(&origin(uri))?;
+        challenge.nc += 1;
+        Some(challenge.clone())
+    }
+}
+
+fn origin(uri: &Uri) -> String {
+    format!(
+        "{}://{}:{}",
+        uri.scheme_str().unwrap_or_default(),
+        uri.host().unwrap_or_default(),
+        uri.port_u16().or_else(|| default_port(uri)).unwrap_or(0)
+    )
+}
+
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The challenges in a `WWW-Authenticate` header, as schemes and their lowercased parameters
+fn challenges(header: &str) -> Vec<(String, Vec<(String, String)>)> {
+    let mut challenges: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    let mut rest = header.trim_start();
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| c == ',' || c == '=' || c.is_whitespace())
+            .unwrap_or(rest.len());
+        let token = &rest[..end];
+        rest = rest[end..].trim_start();
+        if let Some(value) = rest.strip_prefix('=') {
+            let value = value.trim_start();
+            let (value, remaining) = match value.strip_prefix('"') {
+                Some(quoted) => unquote(quoted),
+                None => {
+                    let end = value.find(',').unwrap_or(value.len());
+                    (value[..end].trim().to_string(), &value[end..])
+                }
+            };
+            rest = remaining;
+            if let Some((_, params)) = challenges.last_mut() {
+                params.push((token.to_ascii_lowercase(), value));
+            }
+        } else if !token.is_empty() {
+            challenges.push((token.to_string(), Vec::new()));
+        }
+        rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+    }
+    challenges
+}
+
+/// A quoted string's value and what follows it, from just after the opening quote
+fn unquote(s: &str) -> (String, &str) {
+    let mut value = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, &s[i + 1..]),
+            '\\' => {
+                if let Some((_, c)) = chars.next() {
+                    value.push(c);
+                }
+            }
+            c => value.push(c),
+        }
+    }
+    (value, "")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn digest_challenge(header: &str) -> Option<DigestChallenge> {
+        let mut headers = HeaderMap::new();
+        headers.insert(WWW_AUTHENTICATE, HeaderValue::from_str(header).unwrap());
+        DigestChallenge::from_headers(&headers)
+    }
+
+    #[test]
+    fn header_test() {
+        let basic = Auth::Basic {
+            user: "user".to_string(),
+            password: "pass".to_string(),
+        };
+        assert_eq!("Basic dXNlcjpwYXNz", basic.header().unwrap());
+        let bearer = Auth::Bearer("token".to_string());
+        assert_eq!("Bearer token", bearer.header().unwrap());
+        let digest = Auth::Digest {
+            user: "user".to_string(),
+            password: "pass".to_string(),
+        };
+        assert_eq!(None, digest.header());
+    }
+
+    #[test]
+    fn challenges_test() {
+        let param = |name: &str, value: &str| (name.to_string(), value.to_string());
+        // RFC 7235 section 4.1
+        assert_eq!(
+            vec![
+                (
+                    "Newauth".to_string(),
+                    vec![
+                        param("realm", "apps"),
+                        param("type", "1"),
+                        param("title", "Login to \"apps\""),
+                    ]
+                ),
+                ("Basic".to_string(), vec![param("realm", "simple")]),
+            ],
+            challenges(
+                "Newauth realm=\"apps\", type=1, title=\"Login to \\\"apps\\\"\", \
+                 Basic realm=\"simple\""
+            )
+        );
+        // Commas within quotes, lowercased names and loose whitespace
+        assert_eq!(
+            vec![(
+                "Digest".to_string(),
+                vec![param("realm", "a, b"), param("qop", "auth,auth-int")]
+            )],
+            challenges("  Digest Realm = \"a, b\" ,QOP=\"auth,auth-int\",")
+        );
+        assert_eq!(
+            vec![("Negotiate".to_string(), vec![])],
+            challenges("Negotiate")
+        );
+        assert!(challenges("").is_empty());
+        // An unterminated quoted string runs to the end
+        assert_eq!(
+            vec![("Basic".to_string(), vec![param("realm", "open")])],
+            challenges("Basic realm=\"open")
+        );
+    }
+
+    #[test]
+    fn digest_challenge_test() {
+        // RFC 2617 section 3.5
+        let challenge = digest_challenge(
+            "Basic realm=\"testrealm@host.com\", Digest realm=\"testrealm@host.com\", \
+             qop=\"auth,auth-int\", nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+             opaque=\"5ccc069c403ebaf9f0171e9517f40e41\"",
+        )
+        .unwrap();
+        assert_eq!(Some("auth"), challenge.qop);
+        let uri: Uri = "http://host.com/dir/index.html".parse().unwrap();
+        let authorization = challenge.authorization(
+            "Mufasa",
+            "Circle Of Life",
+            &Method::GET,
+            &uri,
+            b"",
+            "0a4f113b",
+        );
+        assert!(authorization.contains("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(authorization.contains("qop=auth, nc=00000001, cnonce=\"0a4f113b\""));
+        assert!(authorization.contains("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+
+        // RFC 7616 section 3.9.1, where SHA-256 is preferred to MD5
+        let mut headers = HeaderMap::new();
+        for algorithm in &["MD5", "SHA-256"] {
+            let value = format!(
+                "Digest realm=\"http-auth@example.org\", qop=\"auth, auth-int\", \
+                 algorithm={}, nonce=\"7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v\", \
+                 opaque=\"FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS\"",
+                algorithm
+            );
+            headers.append(WWW_AUTHENTICATE, HeaderValue::from_str(&value).unwrap());
+        }
+        let challenge = DigestChallenge::from_headers(&headers).unwrap();
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        let authorization =
+            challenge.authorization("Mufasa", "Circle of Life", &Method::GET, &uri, b"", cnonce);
+        assert!(authorization.contains("algorithm=SHA-256"));
+        assert!(authorization.contains(
+            "response=\"753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1\""
+        ));
+
+        // The original RFC 2069 digest, without qop
+        let challenge = digest_challenge("Digest realm=\"r\", nonce=\"n\"").unwrap();
+        let uri: Uri = "http://host/".parse().unwrap();
+        let authorization = challenge.authorization("u", "p", &Method::GET, &uri, b"", "c");
+        let md5 = |s: &str| hex(&Md5::digest(s.as_bytes()));
+        let response = md5(&format!("{}:n:{}", md5("u:r:p"), md5("GET:/")));
+        assert!(authorization.contains(&format!("response=\"{}\"", response)));
+        assert!(!authorization.contains("qop=") && !authorization.contains("nc="));
+
+        let challenge = digest_challenge(
+            "Digest realm=\"r\", nonce=\"n\", qop=\"auth-int\", algorithm=MD5-sess",
+        )
+        .unwrap();
+        assert_eq!(Some("auth-int"), challenge.qop);
+        assert!(challenge.session);
+        let authorization = challenge.authorization("u", "p", &Method::GET, &uri, b"", "c");
+        assert!(authorization.contains("algorithm=MD5-sess"));
+        // Challenges that can't be answered
+        for header in &[
+            "Digest realm=\"r\", nonce=\"n\", qop=\"auth-conf\"",
+            "Digest realm=\"r\", nonce=\"n\", algorithm=SHA-512-256",
+            "Digest realm=\"r\"",
+            "Digest nonce=\"n\"",
+            "Basic realm=\"r\"",
+        ] {
+            assert_eq!(None, digest_challenge(header), "{}", header);
+        }
+    }
+
+    #[test]
+    fn digest_nonces_test() {
+        let nonces = DigestNonces::default();
+        let uri: Uri = "http://host/a".parse().unwrap();
+        let same_origin: Uri = "http://host:80/b".parse().unwrap();
+        let other: Uri = "https://host/a".parse().unwrap();
+        let challenge = |nonce: &str| {
+            digest_challenge(&format!(
+                "Digest realm=\"r\", nonce=\"{}\", qop=\"auth\"",
+                nonce
+            ))
+            .unwrap()
+        };
+        assert_eq!(None, nonces.next(&uri));
+        assert_eq!(1, nonces.insert(&uri, challenge("a")).nc);
+        assert_eq!(2, nonces.next(&uri).unwrap().nc);
+        assert_eq!(3, nonces.next(&same_origin).unwrap().nc);
+        assert_eq!(None, nonces.next(&other));
+        // Challenged again with the same nonce, or a new one
+        assert_eq!(4, nonces.insert(&uri, challenge("a")).nc);
+        assert_eq!(1, nonces.insert(&uri, challenge("b")).nc);
+        let next = nonces.next(&uri).unwrap();
+        let authorization = next.authorization("u", "p", &Method::GET, &uri, b"", "c");
+        assert!(authorization.contains("nonce=\"b\"") && authorization.contains("nc=00000002"));
+    }
+
+    #[test]
+    fn sigv4_test() {
+        // The get-vanilla and post-vanilla cases of the AWS SigV4 test suite
+        let aws = AwsSigV4 {
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+            region: "us-east-1".to_string(),
+            service: "service".to_string(),
+        };
+        let now = UNIX_EPOCH + Duration::from_secs(1_440_938_160);
+        // The Host signed leaves out the default port, as hyper does when sending it
+        for (uri, method, signature) in &[
+            (
+                "https://example.amazonaws.com/",
+                Method::GET,
+                "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+            ),
+            (
+                "https://example.amazonaws.com:443/",
+                Method::GET,
+                "5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31",
+            ),
+            (
+                "https://example.amazonaws.com/",
+                Method::POST,
+                "5da7c1a2acd57cee7505fc6676e4e544621c30862966e37dddb68e92efbe5d6b",
+            ),
+        ] {
+            let uri: Uri = uri.parse().unwrap();
+            let mut headers = HeaderMap::new();
+            aws.sign(method, &uri, &mut headers, b"", now);
+            assert_eq!("20150830T123600Z", headers["x-amz-date"]);
+            let expected = format!(
+                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+                 SignedHeaders=host;x-amz-date, Signature={}",
+                signature
+            );
+            assert_eq!(expected, headers[AUTHORIZATION]);
+        }
+        let signature = |uri: &str| {
+            let mut headers = HeaderMap::new();
+            aws.sign(&Method::GET, &uri.parse().unwrap(), &mut headers, b"", now);
+            headers[AUTHORIZATION].to_str().unwrap().to_string()
+        };
+        assert_ne!(
+            signature("https://example.amazonaws.com/"),
+            signature("https://example.amazonaws.com:8443/")
+        );
+
+        // S3 signs the payload hash, and temporary credentials their token
+        let s3 = AwsSigV4 {
+            session_token: Some("token".to_string()),
+            service: "s3".to_string(),
+            ..aws
+        };
+        let mut headers = HeaderMap::new();
+        let uri: Uri = "https://bucket.s3.amazonaws.com/key".parse().unwrap();
+        s3.sign(&Method::PUT, &uri, &mut headers, b"", now);
+        assert_eq!("token", headers["x-amz-security-token"]);
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            headers["x-amz-content-sha256"]
+        );
+        assert!(headers[AUTHORIZATION]
+            .to_str()
+            .unwrap()
+            .contains("SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token, "));
+    }
+
+    #[test]
+    fn canonical_query_test() {
+        let cases = [
+            ("", ""),
+            ("Param2=value2&Param1=value1", "Param1=value1&Param2=value2"),
+            ("b=2&a=1&a=0", "a=0&a=1&b=2"),
+            ("p=a%20b", "p=a%20b"),
+            ("p=a+b", "p=a%2Bb"),
+            ("p", "p="),
+            ("k=-_.~/", "k=-_.~%2F"),
+            ("%e1%88%b4=x", "%E1%88%B4=x"),
+            ("a=%zz", "a=%25zz"),
+            ("&&a=1&", "a=1"),
+        ];
+        for (query, expected) in cases.iter() {
+            assert_eq!(canonical_query(query), *expected, "{}", query);
+        }
+        assert_eq!("/a%20b/%C3%BC", aws_encode("/a b/ü".as_bytes(), false));
+        assert_eq!("%2Fa", aws_encode(b"/a", true));
+    }
+}
@@ -0,0 +1,93 @@
+use std::time::{Duration, Instant};
+
+/// Span over which the transfer rate is sampled for `BodyStats::peak_throughput`.  Bodies that
+/// arrive quicker than this are too short to measure a rate for.
+const RATE_WINDOW: Duration = Duration::from_millis(100);
+
+/// How the response body arrived, attached to the responses `Client` reads fully
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BodyStats {
+    pub len: u64,
+    /// Number of data chunks the body arrived in
+    pub chunks: u64,
+    /// Time from the start of the request until the first byte of the body was read, `None`
+    /// for empty bodies
+    pub first_byte: Option<Duration>,
+    /// Time from the headers until the last of the body was read
+    pub transfer: Duration,
+    /// Longest wait between two chunks
+    pub max_gap: Option<Duration>,
+    /// Bytes per second over the whole transfer
+    pub throughput: Option<u64>,
+    /// Highest bytes per second over any `RATE_WINDOW` of the transfer
+    pub peak_throughput: Option<u64>,
+}
+
+/// Measures a body as its chunks are read
+pub(crate) struct BodyMeter {
+    started: Instant,
+    headers: Instant,
+    last: Option<Instant>,
+    window_start: Instant,
+    window_len: u64,
+    stats: BodyStats,
+}
+
+impl BodyMeter {
+    /// Start measuring a body whose request started at `started`, and whose headers have
+    /// just arrived
+    pub(crate) fn new(started: Instant) -> BodyMeter {
+        let now = Instant::now();
+        BodyMeter {
+            started,
+            headers: now,
+            last: None,
+            window_start: now,
+            window_len: 0,
+            stats: BodyStats::default(),
+        }
+    }
+
+    /// Count a chunk of `len` bytes read just now, returning the gap since the previous chunk
+    pub(crate) fn chunk(&mut self, len: usize) -> Option<Duration> {
+        let now = Instant::now();
+        let stats = &mut self.stats;
+        stats.len += len as u64;
+        stats.chunks += 1;
+        if stats.first_byte.is_none() {
+            stats.first_byte = Some(now - self.started);
+        }
+        let gap = self.last.map(|last| now - last);
+        if let Some(gap) = gap {
+            stats.max_gap = stats.max_gap.max(Some(gap));
+        }
+        self.last = Some(now);
+
+        self.window_len += len as u64;
+        let window = now - self.window_start;
+        if window >= RATE_WINDOW {
+            stats.peak_throughput = stats.peak_throughput.max(rate(self.window_len, window));
+            self.window_start = now;
+            self.window_len = 0;
+        }
+        gap
+    }
+
+    pub(crate) fn finish(mut self) -> BodyStats {
+        let transfer = self.last.unwrap_or(self.headers) - self.headers;
+        self.stats.transfer = transfer;
+        if transfer >= RATE_WINDOW {
+            self.stats.throughput = rate(self.stats.len, transfer);
+        }
+        self.stats
+    }
+}
+
+fn rate(len: u64, d: Duration) -> Option<u64> {
+    let secs = d.as_secs_f64();
+    if secs > 0.0 {
+        Some((len as f64 / secs) as u64)
+    } else {
+        None
+    }
+}
@@ -1,50 +1,281 @@
-use crate::connectors::TracingHttpsConnector;
-use hyper::body::Bytes;
+use crate::auth::{Auth, DigestChallenge, DigestNonces};
+use crate::body::BodyMeter;
+use crate::compression::{BodyRepresentation, CompressionOptions, Decoder};
+use crate::connectors::{ConnectionInfo, TracingHttpsConnector, TracingQuicConnector};
+use crate::context::{self, RequestContext, RequestTrace};
+use crate::cookies::CookieJar;
+use crate::error::{ClientError, ErrorKind};
+use crate::events::{self, Event};
+use crate::proxy::{ProxyError, ProxyOptions};
+use crate::redirect::{self, Hop, RedirectHop, RedirectOptions, Redirects};
+use crate::retry::{Retries, RetryAttempt, RetryOptions, RetryReason};
+use crate::socket::SocketOptions;
+use crate::timeouts::{timeout, Phase, Timeouts};
+use crate::tls::TlsOptions;
+use hyper::body::{Bytes, HttpBody};
 use hyper::client::Client as HyperClient;
+use hyper::header::{
+    HeaderValue, ACCEPT_ENCODING, AUTHORIZATION, CONTENT_ENCODING, COOKIE, PROXY_AUTHORIZATION,
+};
 use hyper::http::response::Parts;
-use hyper::http::{Request, Response};
-use hyper::Body;
-use hyper::Error as HyperError;
+use hyper::http::{Request, Response, StatusCode, Version};
+use hyper::{Body, Uri};
 use std::fmt;
-use tracer_metrics::data::Snapshot;
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime};
+use tracer_metrics::data::{Sample, SampleValue, Snapshot};
 use tracer_metrics::{Collector, CollectorHandle, Interest, Stopwatch};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Metric {
     Dns,
     Connection,
+    ProxyConnect,
+    ProxyHandshake,
     Tls,
+    TlsFull,
+    TlsResumed,
+    Http2Settings,
+    QuicHandshake,
+    RequestWrite,
+    FirstByte,
+    Upload,
     Headers,
+    FirstBodyByte,
+    ChunkGap,
     FullResponse,
+    Throughput,
+    PeakThroughput,
+    DecodedBodyLen,
+    DecompressTime,
+    Redirects,
+    RedirectTotal,
+    Retries,
+    FirstAttempt,
+    EventualSuccess,
+    AuthChallenge,
+    ColdHeaders,
+    ColdFullResponse,
+    WarmHeaders,
+    WarmFullResponse,
     HeaderLen,
     BodyLen,
+    TlsHandshakeBytesSent,
+    TlsHandshakeBytesReceived,
+    RequestBytes,
+    ResponseHeaderBytes,
+    ResponseBodyBytes,
+    WireBytesSent,
+    WireBytesReceived,
+    Requests,
+    ReusedConnection,
+    ZeroRtt,
+    TcpRtt,
+    TcpRttVar,
+    TcpRetransmits,
+    TcpCwnd,
+    TcpBytesAcked,
+    Failure(ErrorKind),
+    Timeout(Phase),
 }
 
 impl Metric {
-    pub fn all_metrics() -> &'static [Metric] {
-        &[
+    pub fn all_metrics() -> Vec<Metric> {
+        let mut metrics = vec![
             Metric::Dns,
             Metric::Connection,
+            Metric::ProxyConnect,
+            Metric::ProxyHandshake,
             Metric::Tls,
+            Metric::TlsFull,
+            Metric::TlsResumed,
+            Metric::Http2Settings,
+            Metric::QuicHandshake,
+            Metric::RequestWrite,
+            Metric::Upload,
+            Metric::FirstByte,
             Metric::Headers,
             Metric::HeaderLen,
+            Metric::FirstBodyByte,
+            Metric::ChunkGap,
             Metric::FullResponse,
             Metric::BodyLen,
-        ]
+            Metric::DecodedBodyLen,
+            Metric::DecompressTime,
+            Metric::Throughput,
+            Metric::PeakThroughput,
+            Metric::Redirects,
+            Metric::RedirectTotal,
+            Metric::Retries,
+            Metric::FirstAttempt,
+            Metric::EventualSuccess,
+            Metric::AuthChallenge,
+            Metric::TlsHandshakeBytesSent,
+            Metric::TlsHandshakeBytesReceived,
+            Metric::RequestBytes,
+            Metric::ResponseHeaderBytes,
+            Metric::ResponseBodyBytes,
+            Metric::WireBytesSent,
+            Metric::WireBytesReceived,
+            Metric::ColdHeaders,
+            Metric::ColdFullResponse,
+            Metric::WarmHeaders,
+            Metric::WarmFullResponse,
+            Metric::Requests,
+            Metric::ReusedConnection,
+            Metric::ZeroRtt,
+            Metric::TcpRtt,
+            Metric::TcpRttVar,
+            Metric::TcpRetransmits,
+            Metric::TcpCwnd,
+            Metric::TcpBytesAcked,
+        ];
+        metrics.extend(Metric::failure_metrics());
+        metrics.extend(Metric::timeout_metrics());
+        metrics
     }
 
     pub fn latency_metrics() -> &'static [Metric] {
         &[
             Metric::Dns,
             Metric::Connection,
+            Metric::ProxyConnect,
+            Metric::ProxyHandshake,
             Metric::Tls,
+            Metric::TlsFull,
+            Metric::TlsResumed,
+            Metric::Http2Settings,
+            Metric::QuicHandshake,
+            Metric::RequestWrite,
+            Metric::Upload,
+            Metric::FirstByte,
             Metric::Headers,
+            Metric::FirstBodyByte,
+            Metric::ChunkGap,
             Metric::FullResponse,
+            Metric::DecompressTime,
+            Metric::RedirectTotal,
+            Metric::FirstAttempt,
+            Metric::EventualSuccess,
+            Metric::AuthChallenge,
+            Metric::ColdHeaders,
+            Metric::ColdFullResponse,
+            Metric::WarmHeaders,
+            Metric::WarmFullResponse,
+            Metric::TcpRtt,
+            Metric::TcpRttVar,
+        ]
+    }
+
+    /// Progress of the request over the connection, read from the connection's IO.  `RequestWrite`
+    /// and `FirstByte` are measured from the start of the request, like `Headers`, and `Upload`
+    /// from the first write of the request to the last.  Not recorded for HTTP/3, and approximate
+    /// for HTTP/2, where requests sharing a connection interleave their writes and reads.
+    pub fn exchange_metrics() -> &'static [Metric] {
+        &[Metric::RequestWrite, Metric::Upload, Metric::FirstByte]
+    }
+
+    /// How the response body arrived.  `ChunkGap` is the wait between consecutive chunks of a
+    /// body, and the throughputs are in bytes per second, see `BodyStats`.
+    pub fn body_metrics() -> &'static [Metric] {
+        &[
+            Metric::FirstBodyByte,
+            Metric::ChunkGap,
+            Metric::Throughput,
+            Metric::PeakThroughput,
+        ]
+    }
+
+    /// Bytes per second
+    pub fn throughput_metrics() -> &'static [Metric] {
+        &[Metric::Throughput, Metric::PeakThroughput]
+    }
+
+    /// Latencies split by whether the connection was new or reused, recorded in keep-alive mode
+    pub fn reuse_metrics() -> &'static [Metric] {
+        &[
+            Metric::ColdHeaders,
+            Metric::ColdFullResponse,
+            Metric::WarmHeaders,
+            Metric::WarmFullResponse,
         ]
     }
 
+    /// TLS handshake latencies split by whether an earlier session was resumed, recorded when
+    /// `TlsOptions::resume` is set
+    pub fn resumption_metrics() -> &'static [Metric] {
+        &[Metric::TlsFull, Metric::TlsResumed]
+    }
+
     pub fn size_metrics() -> &'static [Metric] {
-        &[Metric::HeaderLen, Metric::BodyLen]
+        &[
+            Metric::HeaderLen,
+            Metric::BodyLen,
+            Metric::DecodedBodyLen,
+            Metric::TlsHandshakeBytesSent,
+            Metric::TlsHandshakeBytesReceived,
+            Metric::RequestBytes,
+            Metric::ResponseHeaderBytes,
+            Metric::ResponseBodyBytes,
+            Metric::WireBytesSent,
+            Metric::WireBytesReceived,
+            Metric::TcpBytesAcked,
+        ]
+    }
+
+    /// Bytes counted on the connection for each phase of a request.  The TLS handshake bytes
+    /// are only recorded for new connections.  `RequestBytes` is the request as written before
+    /// any TLS, and the response header and body bytes are as read after TLS but before any
+    /// decoding, so chunked framing counts towards the body.  Those two are HTTP/1 only.  The
+    /// wire bytes are everything that went over the connection for the request and response,
+    /// TLS records included.  Not recorded for HTTP/3, and approximate for HTTP/2, as with
+    /// `exchange_metrics`.
+    pub fn byte_metrics() -> &'static [Metric] {
+        &[
+            Metric::TlsHandshakeBytesSent,
+            Metric::TlsHandshakeBytesReceived,
+            Metric::RequestBytes,
+            Metric::ResponseHeaderBytes,
+            Metric::ResponseBodyBytes,
+            Metric::WireBytesSent,
+            Metric::WireBytesReceived,
+        ]
+    }
+
+    /// Kernel TCP statistics read after each response, on Linux
+    pub fn tcp_metrics() -> &'static [Metric] {
+        &[
+            Metric::TcpRtt,
+            Metric::TcpRttVar,
+            Metric::TcpRetransmits,
+            Metric::TcpCwnd,
+            Metric::TcpBytesAcked,
+        ]
+    }
+
+    /// A count for each class of `ErrorKind`
+    pub fn failure_metrics() -> Vec<Metric> {
+        ErrorKind::all()
+            .iter()
+            .map(|k| Metric::Failure(*k))
+            .collect()
+    }
+
+    /// A count for each `Phase` that can time out
+    pub fn timeout_metrics() -> Vec<Metric> {
+        Phase::all().iter().map(|p| Metric::Timeout(*p)).collect()
+    }
+
+    fn warm_or_cold(info: &ConnectionInfo, m: Metric) -> Metric {
+        match (m, info.is_reused()) {
+            (Metric::Headers, false) => Metric::ColdHeaders,
+            (Metric::Headers, true) => Metric::WarmHeaders,
+            (Metric::FullResponse, false) => Metric::ColdFullResponse,
+            (Metric::FullResponse, true) => Metric::WarmFullResponse,
+            (m, _) => m,
+        }
     }
 
     pub fn get_metrics(m: &[Metric], collector: &Collector<Metric>) -> Vec<Snapshot<Metric>> {
@@ -60,7 +291,7 @@ impl Metric {
             .collect()
     }
     pub fn get_all_metrics(collector: &Collector<Metric>) -> Vec<Snapshot<Metric>> {
-        Metric::get_metrics(Metric::all_metrics(), collector)
+        Metric::get_metrics(&Metric::all_metrics(), collector)
     }
 
     pub fn get_latency_metrics(collector: &Collector<Metric>) -> Vec<Snapshot<Metric>> {
@@ -70,6 +301,14 @@ impl Metric {
     pub fn get_size_metrics(collector: &Collector<Metric>) -> Vec<Snapshot<Metric>> {
         Metric::get_metrics(Metric::size_metrics(), collector)
     }
+
+    pub fn get_failure_metrics(collector: &Collector<Metric>) -> Vec<Snapshot<Metric>> {
+        Metric::get_metrics(&Metric::failure_metrics(), collector)
+    }
+
+    pub fn get_timeout_metrics(collector: &Collector<Metric>) -> Vec<Snapshot<Metric>> {
+        Metric::get_metrics(&Metric::timeout_metrics(), collector)
+    }
 }
 
 impl fmt::Display for Metric {
@@ -79,16 +318,79 @@ impl fmt::Display for Metric {
     }
 }
 
+/// HTTP versions a `Client` will speak
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HttpVersion {
+    /// HTTP/1.1 only
+    #[default]
+    Http1,
+    /// Offer HTTP/2 via ALPN on TLS connections, falling back to HTTP/1.1
+    Http2,
+    /// Speak HTTP/2 without negotiating it, also over plain-text connections
+    Http2PriorKnowledge,
+    /// HTTP/3 over QUIC, for https URLs only
+    Http3,
+}
+
+impl HttpVersion {
+    fn alpn_protocols(self) -> Vec<Vec<u8>> {
+        match self {
+            HttpVersion::Http1 | HttpVersion::Http3 => vec![b"http/1.1".to_vec()],
+            HttpVersion::Http2 => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            HttpVersion::Http2PriorKnowledge => vec![b"h2".to_vec()],
+        }
+    }
+}
+
+/// Settings for a `Client` beyond the collector it reports to
+#[derive(Debug, Clone, Default)]
+pub struct ClientOptions {
+    pub timeouts: Timeouts,
+    pub tls: TlsOptions,
+    pub proxy: ProxyOptions,
+    /// Source address and TCP options for new connections
+    pub socket: SocketOptions,
+    /// Connect to this Unix domain socket instead of the URL's host, bypassing any proxy.
+    /// Unix only, and not for HTTP/3.
+    pub unix_socket: Option<PathBuf>,
+    pub http_version: HttpVersion,
+    /// Which redirects to follow, none by default
+    pub redirects: RedirectOptions,
+    /// Which failures to send again, none by default
+    pub retry: RetryOptions,
+    /// Which response encodings to ask for and decode, none by default
+    pub compression: CompressionOptions,
+    /// Cookies to send with requests, and store those set by responses in.  Cookies aren't
+    /// kept unless there's a jar.
+    pub cookies: Option<CookieJar>,
+    /// Credentials for requests that don't set `Authorization` themselves
+    pub auth: Option<Auth>,
+    /// Keep connections open and reuse them for later requests
+    pub keep_alive: bool,
+    /// Maximum idle connections kept per host when `keep_alive` is set
+    pub pool_max_idle: Option<usize>,
+    /// How long idle connections are kept when `keep_alive` is set
+    pub pool_idle_timeout: Option<Duration>,
+}
+
 pub struct Client<C> {
     client: HyperClient<C, Body>,
+    /// Set for HTTP/3, an error when the QUIC connector couldn't be started
+    quic: Option<io::Result<TracingQuicConnector>>,
     collector: CollectorHandle<Metric>,
+    options: ClientOptions,
+    digest_nonces: DigestNonces,
 }
 
 impl Client<TracingHttpsConnector> {
     pub fn configure_collector_defaults(collector: &mut Collector<Metric>) {
         collector.register(Interest::Count(Metric::Connection));
         collector.register(Interest::Count(Metric::Dns));
+        collector.register(Interest::Count(Metric::ProxyConnect));
+        collector.register(Interest::Count(Metric::ProxyHandshake));
         collector.register(Interest::Count(Metric::Tls));
+        collector.register(Interest::Count(Metric::Http2Settings));
+        collector.register(Interest::Count(Metric::QuicHandshake));
         collector.register(Interest::Count(Metric::Headers));
         collector.register(Interest::Count(Metric::FullResponse));
         collector.register(Interest::Count(Metric::BodyLen));
@@ -96,27 +398,146 @@ impl Client<TracingHttpsConnector> {
 
         collector.register(Interest::LatencyPercentile(Metric::Connection));
         collector.register(Interest::LatencyPercentile(Metric::Dns));
+        collector.register(Interest::LatencyPercentile(Metric::ProxyConnect));
+        collector.register(Interest::LatencyPercentile(Metric::ProxyHandshake));
         collector.register(Interest::LatencyPercentile(Metric::Tls));
+        collector.register(Interest::LatencyPercentile(Metric::Http2Settings));
+        collector.register(Interest::LatencyPercentile(Metric::QuicHandshake));
         collector.register(Interest::LatencyPercentile(Metric::Headers));
         collector.register(Interest::LatencyPercentile(Metric::FullResponse));
 
         collector.register(Interest::Gauge(Metric::Connection));
         collector.register(Interest::Gauge(Metric::Dns));
+        collector.register(Interest::Gauge(Metric::ProxyConnect));
+        collector.register(Interest::Gauge(Metric::ProxyHandshake));
         collector.register(Interest::Gauge(Metric::Tls));
+        collector.register(Interest::Gauge(Metric::Http2Settings));
+        collector.register(Interest::Gauge(Metric::QuicHandshake));
         collector.register(Interest::Gauge(Metric::Headers));
         collector.register(Interest::Gauge(Metric::FullResponse));
         collector.register(Interest::Gauge(Metric::BodyLen));
         collector.register(Interest::Gauge(Metric::HeaderLen));
+
+        for m in Metric::exchange_metrics()
+            .iter()
+            .chain(Metric::reuse_metrics())
+            .chain(Metric::resumption_metrics())
+        {
+            collector.register(Interest::Count(*m));
+            collector.register(Interest::LatencyPercentile(*m));
+            collector.register(Interest::Gauge(*m));
+        }
+
+        for m in Metric::tcp_metrics()
+            .iter()
+            .chain(Metric::throughput_metrics())
+            .chain(Metric::byte_metrics())
+        {
+            collector.register(Interest::Count(*m));
+            collector.register(Interest::Gauge(*m));
+        }
+        collector.register(Interest::Count(Metric::FirstBodyByte));
+        collector.register(Interest::LatencyPercentile(Metric::FirstBodyByte));
+        collector.register(Interest::Gauge(Metric::FirstBodyByte));
+        collector.register(Interest::Count(Metric::ChunkGap));
+        collector.register(Interest::LatencyPercentile(Metric::ChunkGap));
+        collector.register(Interest::Count(Metric::DecodedBodyLen));
+        collector.register(Interest::Gauge(Metric::DecodedBodyLen));
+        collector.register(Interest::Count(Metric::DecompressTime));
+        collector.register(Interest::LatencyPercentile(Metric::DecompressTime));
+        collector.register(Interest::Gauge(Metric::DecompressTime));
+        collector.register(Interest::LatencyPercentile(Metric::TcpRtt));
+        collector.register(Interest::LatencyPercentile(Metric::TcpRttVar));
+
+        // Redirects counts the hops followed, RedirectTotal times whole chains
+        collector.register(Interest::Count(Metric::Redirects));
+        collector.register(Interest::Count(Metric::RedirectTotal));
+        collector.register(Interest::LatencyPercentile(Metric::RedirectTotal));
+        collector.register(Interest::Gauge(Metric::RedirectTotal));
+
+        // Retries counts the attempts sent again.  FirstAttempt times the first attempt at each
+        // request, successful or not, and EventualSuccess the time until a request succeeded,
+        // retries and backoff included.
+        collector.register(Interest::Count(Metric::Retries));
+        for m in &[Metric::FirstAttempt, Metric::EventualSuccess] {
+            collector.register(Interest::Count(*m));
+            collector.register(Interest::LatencyPercentile(*m));
+            collector.register(Interest::Gauge(*m));
+        }
+
+        // AuthChallenge times the round trip for a digest challenge, before the request is sent
+        // with credentials
+        collector.register(Interest::Count(Metric::AuthChallenge));
+        collector.register(Interest::LatencyPercentile(Metric::AuthChallenge));
+        collector.register(Interest::Gauge(Metric::AuthChallenge));
+
+        collector.register(Interest::Count(Metric::Requests));
+        collector.register(Interest::Count(Metric::ReusedConnection));
+        collector.register(Interest::Count(Metric::ZeroRtt));
+        for m in Metric::failure_metrics() {
+            collector.register(Interest::Count(m));
+        }
+        for m in Metric::timeout_metrics() {
+            collector.register(Interest::Count(m));
+        }
     }
 
     pub fn new_with_collector_handle(
         handle: CollectorHandle<Metric>,
     ) -> Client<TracingHttpsConnector> {
-        let connector = TracingHttpsConnector::new(true, handle.clone());
-        let client = HyperClient::builder().keep_alive(false).build(connector);
+        Client::new_with_options(handle, ClientOptions::default())
+    }
+
+    pub fn new_with_options(
+        handle: CollectorHandle<Metric>,
+        mut options: ClientOptions,
+    ) -> Client<TracingHttpsConnector> {
+        let mut connector = TracingHttpsConnector::new(true, handle.clone());
+        connector.set_timeouts(options.timeouts.clone());
+        connector.set_socket_options(options.socket.clone());
+        #[cfg(unix)]
+        {
+            if let Some(ref path) = options.unix_socket {
+                connector.set_unix_socket(path.clone());
+            }
+        }
+        if options.unix_socket.is_some() {
+            options.proxy = ProxyOptions::default();
+        }
+        connector.set_tls_options(&options.tls);
+        connector.set_alpn_protocols(options.http_version.alpn_protocols());
+        // HTTP/2 can't be spoken to a proxy in place of the origin, so it always gets a tunnel
+        connector.set_proxy(
+            options.proxy.clone(),
+            options.http_version != HttpVersion::Http2PriorKnowledge,
+        );
+        let mut builder = HyperClient::builder();
+        builder.keep_alive(options.keep_alive);
+        builder.http2_only(options.http_version == HttpVersion::Http2PriorKnowledge);
+        if let Some(max_idle) = options.pool_max_idle {
+            builder.max_idle_per_host(max_idle);
+        }
+        if let Some(idle_timeout) = options.pool_idle_timeout {
+            builder.keep_alive_timeout(idle_timeout);
+        }
+        let client = builder.build(connector);
+        let quic = if options.http_version == HttpVersion::Http3 {
+            Some(TracingQuicConnector::new(handle.clone()).map(|mut quic| {
+                quic.set_timeouts(options.timeouts.clone());
+                quic.set_tls_options(&options.tls);
+                quic.set_keep_alive(options.keep_alive);
+                quic.set_socket_options(options.socket.clone());
+                quic
+            }))
+        } else {
+            None
+        };
         Client {
             client,
+            quic,
             collector: handle,
+            options,
+            digest_nonces: DigestNonces::default(),
         }
     }
     pub fn new_with_collector(collector: &mut Collector<Metric>) -> Client<TracingHttpsConnector> {
@@ -130,28 +551,679 @@ impl Client<TracingHttpsConnector> {
         (client, collector)
     }
 
-    pub async fn request(&self, req: Request<Body>) -> Result<Response<Body>, HyperError> {
+    /// Send a request, returning once the response headers have arrived.  The response's
+    /// extensions hold its `EventSet` timeline, up to `Event::HeadersReceived`, and its
+    /// `RequestMetrics`.
+    /// Redirects are not followed, and digest challenges are not answered.
+    pub async fn request(&self, req: Request<Body>) -> Result<Response<Body>, ClientError> {
+        self.collector.send_count(Metric::Requests, 1);
+        let auth = self.auth_for(&req);
+        let (res, trace) = self
+            .traced(async {
+                let req = self.authorize(req, auth).await?;
+                self.fetch_headers(req).await
+            })
+            .await;
+        let mut resp = self.record_failure(res)?;
+        resp.extensions_mut().insert(trace.timeline);
+        resp.extensions_mut().insert(trace.metrics);
+        Ok(resp)
+    }
+
+    /// Send a request and read the whole response, following redirects as set in
+    /// `ClientOptions::redirects` and retrying as set in `ClientOptions::retry`.  The returned
+    /// `Parts` hold the final request's `EventSet` timeline, `RequestMetrics` and `BodyStats`
+    /// in their extensions, and the `Redirects` that led to it and `Retries` before it if there
+    /// were any.
+    pub async fn request_fully(&self, req: Request<Body>) -> Result<(Parts, Bytes), ClientError> {
+        let (res, trace) = self.request_fully_traced(req).await;
+        let (mut parts, body) = res?;
+        parts.extensions.insert(trace.timeline);
+        parts.extensions.insert(trace.metrics);
+        Ok((parts, body))
+    }
+
+    /// Like `request_fully`, but also returns what was recorded about requests that failed
+    pub async fn request_fully_traced(
+        &self,
+        req: Request<Body>,
+    ) -> (Result<(Parts, Bytes), ClientError>, RequestTrace) {
+        let mut full_body = Vec::new();
+        let (res, trace) = self
+            .fetch_retrying(req, |chunk| full_body.extend_from_slice(chunk))
+            .await;
+        (res.map(|parts| (parts, full_body.into())), trace)
+    }
+
+    /// Send a request and hand each chunk of the response body to `on_chunk` as it arrives,
+    /// rather than buffering it, so bodies of any size can be measured.  The returned `Parts`
+    /// hold the same extensions as with `request_fully`.
+    pub async fn request_streaming<F>(
+        &self,
+        req: Request<Body>,
+        on_chunk: F,
+    ) -> Result<Parts, ClientError>
+    where
+        F: FnMut(&[u8]) + Send,
+    {
+        let (res, trace) = self.fetch_retrying(req, on_chunk).await;
+        let mut parts = res?;
+        parts.extensions.insert(trace.timeline);
+        parts.extensions.insert(trace.metrics);
+        Ok(parts)
+    }
+
+    /// Send a request and stream its response to `on_chunk`, retrying it as set in
+    /// `ClientOptions::retry`.  Each attempt is traced as a request of its own, and only the
+    /// body of the final response is passed on.
+    async fn fetch_retrying<F: FnMut(&[u8])>(
+        &self,
+        req: Request<Body>,
+        mut on_chunk: F,
+    ) -> (Result<Parts, ClientError>, RequestTrace) {
+        self.collector.send_count(Metric::Requests, 1);
+        let auth = self.auth_for(&req);
+        let req = match self.authorize(req, auth).await {
+            Ok(req) => req,
+            Err(e) => {
+                let (res, trace) = self.traced(futures::future::ready(Err(e))).await;
+                return (self.record_failure(res), trace);
+            }
+        };
+        let options = &self.options.retry;
+        if !options.enabled() {
+            // Boxed, as the redirect and digest handling make for a future too large to keep
+            // on the stack
+            let (res, trace) = Box::pin(self.fetch_following(req, auth, false, on_chunk)).await;
+            return (self.record_failure(res), trace);
+        }
+        let started = Instant::now();
+        // Kept to be sent again for each attempt
+        let hop = match Hop::from_request(req).await {
+            Ok(hop) => hop,
+            Err(e) => {
+                let (res, trace) = self.traced(futures::future::ready(Err(e.into()))).await;
+                return (self.record_failure(res), trace);
+            }
+        };
+        let mut attempts = Vec::new();
+        let mut first = Duration::default();
+        loop {
+            let retry = attempts.len() + 1 < options.max_attempts;
+            // Once the body has been passed on the attempt can't be taken back
+            let mut passed_on = false;
+            let (res, mut trace) =
+                Box::pin(self.fetch_following(hop.request(), auth, retry, |chunk| {
+                    passed_on = true;
+                    on_chunk(chunk)
+                }))
+                .await;
+            if attempts.is_empty() {
+                first = started.elapsed();
+                self.collector.send_elapsed(Metric::FirstAttempt, first);
+            }
+            let reason = match res {
+                Ok(ref parts) if retry && options.retries_status(parts.status) => {
+                    Some(RetryReason::Status(parts.status))
+                }
+                Err(ref e) if retry && !passed_on && options.retries_error(e) => {
+                    Some(RetryReason::Error(e.kind(), e.to_string()))
+                }
+                _ => None,
+            };
+            if let Some(reason) = reason {
+                self.collector.send_count(Metric::Retries, 1);
+                let backoff = options.backoff(attempts.len() as u32 + 1);
+                attempts.push(RetryAttempt {
+                    reason,
+                    metrics: trace.metrics,
+                    timeline: trace.timeline,
+                    backoff,
+                });
+                tokio::time::delay_for(backoff).await;
+                continue;
+            }
+            let mut res = self.record_failure(res);
+            if let Ok(ref mut parts) = res {
+                let total = started.elapsed();
+                // Out of attempts, still with a status that would have been retried
+                let succeeded = !options.retries_status(parts.status);
+                if succeeded {
+                    self.collector.send_elapsed(Metric::EventualSuccess, total);
+                }
+                if !attempts.is_empty() {
+                    let metrics = &mut trace.metrics;
+                    metrics.push(Metric::Retries, SampleValue::Count(attempts.len() as u64));
+                    metrics.push(Metric::FirstAttempt, SampleValue::Elapsed(first));
+                    if succeeded {
+                        metrics.push(Metric::EventualSuccess, SampleValue::Elapsed(total));
+                    }
+                    parts.extensions.insert(Retries { attempts, total });
+                }
+            }
+            return (res, trace);
+        }
+    }
+
+    /// Send a request and stream its response to `on_chunk`, following redirects and answering
+    /// a digest challenge with `auth`.  Each hop is traced as a request of its own, and only the
+    /// final response's body is passed on, unless `retry` is set and its status is one to retry.
+    async fn fetch_following<F: FnMut(&[u8])>(
+        &self,
+        req: Request<Body>,
+        auth: Option<&Auth>,
+        retry: bool,
+        mut on_chunk: F,
+    ) -> (Result<Parts, ClientError>, RequestTrace) {
+        let retries_status = |status| retry && self.options.retry.retries_status(status);
+        let options = &self.options.redirects;
+        let digest = match auth {
+            Some(Auth::Digest { user, password }) => Some((user, password)),
+            _ => None,
+        };
+        if options.max_redirects == 0 && digest.is_none() {
+            return self
+                .traced(async {
+                    let started = Instant::now();
+                    let resp = self.fetch_headers(req).await?;
+                    if retries_status(resp.status()) {
+                        self.read_body(resp, started, |_| ()).await
+                    } else {
+                        self.read_body(resp, started, on_chunk).await
+                    }
+                })
+                .await;
+        }
+        let started = Instant::now();
+        // Kept to be sent again for each redirect
+        let mut hop = match Hop::from_request(req).await {
+            Ok(hop) => hop,
+            Err(e) => return self.traced(futures::future::ready(Err(e.into()))).await,
+        };
+        let origin = hop.uri.clone();
+        let mut hops = Vec::new();
+        let mut answered = false;
+        let mut challenges = Vec::new();
+        loop {
+            if let Some((user, password)) = digest {
+                if !hop.headers.contains_key(AUTHORIZATION) && self.authorizes(&origin, &hop.uri) {
+                    if let Some(challenge) = self.digest_nonces.next(&hop.uri) {
+                        Client::answer_digest(&mut hop, &challenge, user, password);
+                    }
+                }
+            }
+            let mut next = None;
+            let mut challenge = None;
+            let hop_started = Instant::now();
+            let (res, mut trace) = self
+                .traced(async {
+                    let resp = self.fetch_headers(hop.request()).await?;
+                    if digest.is_some()
+                        && !answered
+                        && resp.status() == StatusCode::UNAUTHORIZED
+                        && self.authorizes(&origin, &hop.uri)
+                    {
+                        challenge = DigestChallenge::from_headers(resp.headers());
+                    }
+                    if challenge.is_none() && hops.len() < options.max_redirects {
+                        next = hop.redirect(options, resp.status(), resp.headers());
+                    }
+                    if next.is_some() || challenge.is_some() || retries_status(resp.status()) {
+                        self.read_body(resp, hop_started, |_| ()).await
+                    } else {
+                        self.read_body(resp, hop_started, &mut on_chunk).await
+                    }
+                })
+                .await;
+            let mut parts = match res {
+                Ok(parts) => parts,
+                Err(e) => return (Err(e), trace),
+            };
+            if let (Some(challenge), Some((user, password))) = (challenge, digest) {
+                let elapsed = hop_started.elapsed();
+                self.collector.send_elapsed(Metric::AuthChallenge, elapsed);
+                challenges.push(elapsed);
+                let challenge = self.digest_nonces.insert(&hop.uri, challenge);
+                Client::answer_digest(&mut hop, &challenge, user, password);
+                answered = true;
+                continue;
+            }
+            match next {
+                Some(mut next) => {
+                    self.collector.send_count(Metric::Redirects, 1);
+                    hops.push(RedirectHop {
+                        url: hop.uri,
+                        status: parts.status,
+                        metrics: trace.metrics,
+                        timeline: trace.timeline,
+                    });
+                    // A signature or digest answer is only good for the URL it was made for
+                    if self.authorizes(&origin, &next.uri) {
+                        match auth {
+                            Some(Auth::AwsSigV4(aws)) => {
+                                let now = SystemTime::now();
+                                aws.sign(
+                                    &next.method,
+                                    &next.uri,
+                                    &mut next.headers,
+                                    &next.body,
+                                    now,
+                                );
+                            }
+                            Some(Auth::Digest { .. }) => {
+                                next.headers.remove(AUTHORIZATION);
+                                answered = false;
+                            }
+                            _ => (),
+                        }
+                    }
+                    hop = next;
+                }
+                None => {
+                    for elapsed in challenges {
+                        trace
+                            .metrics
+                            .push(Metric::AuthChallenge, SampleValue::Elapsed(elapsed));
+                    }
+                    if !hops.is_empty() {
+                        let total = started.elapsed();
+                        self.collector.send_elapsed(Metric::RedirectTotal, total);
+                        let count = hops.len() as u64;
+                        trace
+                            .metrics
+                            .push(Metric::Redirects, SampleValue::Count(count));
+                        trace
+                            .metrics
+                            .push(Metric::RedirectTotal, SampleValue::Elapsed(total));
+                        parts.extensions.insert(Redirects {
+                            hops,
+                            url: hop.uri,
+                            total,
+                        });
+                    }
+                    return (Ok(parts), trace);
+                }
+            }
+        }
+    }
+
+    /// The credentials for a request, unless it brings its own
+    fn auth_for(&self, req: &Request<Body>) -> Option<&Auth> {
+        self.options
+            .auth
+            .as_ref()
+            .filter(|_| !req.headers().contains_key(AUTHORIZATION))
+    }
+
+    /// Add credentials to a request before it's first sent.  Digest authentication waits for
+    /// the server's challenge instead.
+    async fn authorize(
+        &self,
+        req: Request<Body>,
+        auth: Option<&Auth>,
+    ) -> Result<Request<Body>, ClientError> {
+        match auth {
+            Some(Auth::AwsSigV4(aws)) => {
+                // The signature covers the body
+                let (mut parts, body) = req.into_parts();
+                let body = hyper::body::to_bytes(body).await?;
+                let now = SystemTime::now();
+                aws.sign(&parts.method, &parts.uri, &mut parts.headers, &body, now);
+                Ok(Request::from_parts(parts, Body::from(body)))
+            }
+            Some(auth) => {
+                let mut req = req;
+                if let Some(value) = auth.header() {
+                    req.headers_mut().insert(AUTHORIZATION, value);
+                }
+                Ok(req)
+            }
+            None => Ok(req),
+        }
+    }
+
+    /// Set `Authorization` to answer a digest challenge
+    fn answer_digest(hop: &mut Hop, challenge: &DigestChallenge, user: &str, password: &str) {
+        let cnonce = format!("{:016x}", fastrand::u64(..));
+        let authorization =
+            challenge.authorization(user, password, &hop.method, &hop.uri, &hop.body, &cnonce);
+        if let Ok(value) = HeaderValue::from_str(&authorization) {
+            hop.headers.insert(AUTHORIZATION, value);
+        }
+    }
+
+    /// Whether credentials for `origin` go with a request for `uri`, as with redirects
+    fn authorizes(&self, origin: &Uri, uri: &Uri) -> bool {
+        self.options.redirects.trusted || redirect::same_origin(origin, uri)
+    }
+
+    /// Run `f` as a new request, within the total timeout
+    async fn traced<T, F>(&self, f: F) -> (Result<T, ClientError>, RequestTrace)
+    where
+        F: Future<Output = Result<T, ClientError>>,
+    {
+        let context = RequestContext::new();
+        context.events.add(Event::Initiated);
+        let total = self.options.timeouts.total;
+        let res = context
+            .scope(async {
+                match timeout(Phase::Total, total, f).await {
+                    Ok(res) => res,
+                    Err(e) => Err(e.into()),
+                }
+            })
+            .await;
+        if res.is_err() {
+            context.events.add(Event::RequestFailed);
+        }
+        (res, context.finish())
+    }
+
+    async fn fetch_headers(&self, mut req: Request<Body>) -> Result<Response<Body>, ClientError> {
+        if self.quic.is_some() && self.options.unix_socket.is_some() {
+            let e = io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "HTTP/3 can't be sent over a Unix socket",
+            );
+            return Err(ClientError::Quic(ErrorKind::Other, e.into()));
+        }
+        let proxy = &self.options.proxy;
+        if self.quic.is_some() && proxy.for_uri(req.uri()).is_some() {
+            return Err(ClientError::Quic(
+                ErrorKind::Proxy,
+                ProxyError::Http3.into(),
+            ));
+        }
+        if self.options.http_version != HttpVersion::Http2PriorKnowledge {
+            let auth = proxy
+                .forwarding_proxy(req.uri())
+                .and_then(|p| p.authorization());
+            if let Some(auth) = auth {
+                let value = HeaderValue::from_str(&auth).expect("base64 is a valid header value");
+                req.headers_mut().insert(PROXY_AUTHORIZATION, value);
+            }
+        }
+        if let Some(accept) = self.options.compression.accept_encoding() {
+            if !req.headers().contains_key(ACCEPT_ENCODING) {
+                let value = HeaderValue::from_str(&accept).expect("encoding names are valid");
+                req.headers_mut().insert(ACCEPT_ENCODING, value);
+            }
+        }
+        let uri = req.uri().clone();
+        if let Some(cookies) = self
+            .options
+            .cookies
+            .as_ref()
+            .and_then(|j| j.header_for(&uri))
+        {
+            // Sent along with any cookies the request was given
+            let value = match req.headers().get(COOKIE) {
+                Some(given) => {
+                    let mut value = given.as_bytes().to_vec();
+                    value.extend_from_slice(b"; ");
+                    value.extend_from_slice(cookies.as_bytes());
+                    HeaderValue::from_bytes(&value).expect("joined from valid header values")
+                }
+                None => cookies,
+            };
+            req.headers_mut().insert(COOKIE, value);
+        }
         let handle = self.collector.clone();
+        let started = Instant::now();
         let stopwatch = Stopwatch::new();
-        let resp = self.client.request(req).await?;
-        handle.send(stopwatch.elapsed(Metric::Headers));
+        let headers = self.options.timeouts.headers;
+        let mut resp = match self.quic {
+            Some(Ok(ref quic)) => timeout(Phase::Headers, headers, quic.request(req))
+                .await?
+                .map_err(ClientError::from_quic)?,
+            Some(Err(ref e)) => {
+                let e = io::Error::new(e.kind(), format!("could not start QUIC runtime: {}", e));
+                return Err(ClientError::Quic(ErrorKind::Other, e.into()));
+            }
+            None => timeout(Phase::Headers, headers, self.client.request(req)).await??,
+        };
+        context::send(&handle, stopwatch.elapsed(Metric::Headers));
+        if let Some(ref jar) = self.options.cookies {
+            jar.store(&uri, resp.headers());
+        }
+        let is_h2 = resp.version() == Version::HTTP_2;
+        if let Some(info) = resp.extensions_mut().get_mut::<ConnectionInfo>() {
+            // Left over from an earlier request if this one never reached the connection
+            let exchange = info.exchange();
+            let write_start = exchange.write_start.filter(|t| *t >= started);
+            if let Some(t) = exchange.write_end.filter(|t| *t >= started) {
+                events::emit_at(Event::RequestWritten, t);
+                context::send(&handle, Sample::elapsed(Metric::RequestWrite, t - started));
+                if let Some(start) = write_start {
+                    context::send(&handle, Sample::elapsed(Metric::Upload, t - start));
+                }
+            }
+            if let Some(t) = exchange.first_read.filter(|t| *t >= started) {
+                events::emit_at(Event::FirstByte, t);
+                context::send(&handle, Sample::elapsed(Metric::FirstByte, t - started));
+            }
+            info.mark_used();
+            if info.is_reused() {
+                context::send(&handle, Sample::count(Metric::ReusedConnection, 1));
+            } else if is_h2 {
+                if let Some(d) = info.first_exchange() {
+                    context::send(&handle, Sample::elapsed(Metric::Http2Settings, d));
+                }
+            }
+            if self.options.keep_alive {
+                let metric = Metric::warm_or_cold(info, Metric::Headers);
+                context::send(&handle, stopwatch.elapsed(metric));
+            }
+        }
+        events::emit(Event::HeadersReceived);
         Ok(resp)
     }
 
-    pub async fn request_fully(&self, req: Request<Body>) -> Result<(Parts, Bytes), HyperError> {
+    /// Read the body of `resp` into `on_chunk`, `started` being when its request was sent
+    async fn read_body<F: FnMut(&[u8])>(
+        &self,
+        resp: Response<Body>,
+        started: Instant,
+        mut on_chunk: F,
+    ) -> Result<Parts, ClientError> {
         let handle = self.collector.clone();
-        let stopwatch = Stopwatch::new();
-        let resp = self.request(req).await?;
-        let (headers, body) = resp.into_parts();
-        let full_body = hyper::body::to_bytes(body).await?;
-        handle.send(stopwatch.elapsed(Metric::FullResponse));
-        Ok((headers, full_body))
+        let (mut headers, mut body) = resp.into_parts();
+        let reuse_metric = match headers.extensions.get::<ConnectionInfo>() {
+            Some(info) if self.options.keep_alive => {
+                Some(Metric::warm_or_cold(info, Metric::FullResponse))
+            }
+            _ => None,
+        };
+        let compression = &self.options.compression;
+        let mut decoder = match headers.headers.get(CONTENT_ENCODING) {
+            Some(v) => match v.to_str().ok().and_then(|v| compression.decodes(v)) {
+                Some(encoding) => Some(Decoder::new(encoding).map_err(ClientError::Decompression)?),
+                None => None,
+            },
+            None => None,
+        };
+        // Either the decoded body is passed on, or the body as it arrived
+        let decoded = compression.body == BodyRepresentation::Decoded;
+        let body_read = self.options.timeouts.body_read;
+        let mut meter = BodyMeter::new(started);
+        while let Some(chunk) = timeout(Phase::BodyRead, body_read, body.data()).await? {
+            let chunk = chunk.map_err(ClientError::from_body)?;
+            match meter.chunk(chunk.len()) {
+                // Too many to keep with each request, they only go to the collector's histogram
+                Some(gap) => handle.send_elapsed(Metric::ChunkGap, gap),
+                None => events::emit(Event::FirstBodyByte),
+            }
+            match decoder {
+                Some(ref mut decoder) if decoded => decoder.decode(&chunk, &mut on_chunk),
+                Some(ref mut decoder) => {
+                    on_chunk(&chunk);
+                    decoder.decode(&chunk, |_| ())
+                }
+                None => {
+                    on_chunk(&chunk);
+                    Ok(())
+                }
+            }
+            .map_err(ClientError::Decompression)?;
+        }
+        if let Some(decoder) = decoder {
+            let decompression = if decoded {
+                decoder.finish(&mut on_chunk)
+            } else {
+                decoder.finish(|_| ())
+            };
+            let decompression = decompression.map_err(ClientError::Decompression)?;
+            let len = Sample::value(Metric::DecodedBodyLen, decompression.decoded_len);
+            context::send(&handle, len);
+            let time = Sample::elapsed(Metric::DecompressTime, decompression.time);
+            context::send(&handle, time);
+            headers.extensions.insert(decompression);
+        }
+        let elapsed = started.elapsed();
+        context::send(&handle, Sample::elapsed(Metric::FullResponse, elapsed));
+        events::emit(Event::FullResponse);
+        if let Some(m) = reuse_metric {
+            context::send(&handle, Sample::elapsed(m, elapsed));
+        }
+        let stats = meter.finish();
+        if let Some(d) = stats.first_byte {
+            context::send(&handle, Sample::elapsed(Metric::FirstBodyByte, d));
+        }
+        if let Some(rate) = stats.throughput {
+            context::send(&handle, Sample::value(Metric::Throughput, rate));
+        }
+        if let Some(rate) = stats.peak_throughput {
+            context::send(&handle, Sample::value(Metric::PeakThroughput, rate));
+        }
+        headers.extensions.insert(stats);
+        let is_h2 = headers.version == Version::HTTP_2;
+        if let Some(info) = headers.extensions.get_mut::<ConnectionInfo>() {
+            let exchange = info.exchange();
+            // Left over from an earlier request if this one never reached the connection
+            if exchange.write_start.is_some_and(|t| t >= started) {
+                context::send(&handle, Sample::value(Metric::RequestBytes, exchange.sent));
+                if let Some(head) = exchange.head.filter(|_| !is_h2) {
+                    let body = exchange.received - head;
+                    context::send(&handle, Sample::value(Metric::ResponseHeaderBytes, head));
+                    context::send(&handle, Sample::value(Metric::ResponseBodyBytes, body));
+                }
+                let wire = info.wire_bytes().since(exchange.wire_start);
+                context::send(&handle, Sample::value(Metric::WireBytesSent, wire.sent));
+                let received = Sample::value(Metric::WireBytesReceived, wire.received);
+                context::send(&handle, received);
+            }
+            if let Some(tcp) = info.capture_tcp_info() {
+                context::send(&handle, Sample::elapsed(Metric::TcpRtt, tcp.rtt));
+                context::send(&handle, Sample::elapsed(Metric::TcpRttVar, tcp.rtt_var));
+                let retransmits = tcp.retransmits.into();
+                context::send(&handle, Sample::value(Metric::TcpRetransmits, retransmits));
+                context::send(&handle, Sample::value(Metric::TcpCwnd, tcp.cwnd.into()));
+                if let Some(acked) = tcp.bytes_acked {
+                    context::send(&handle, Sample::value(Metric::TcpBytesAcked, acked));
+                }
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Count the failure of a request, once it won't be retried
+    fn record_failure<T>(&self, res: Result<T, ClientError>) -> Result<T, ClientError> {
+        if let Err(ref e) = res {
+            self.collector.send_count(Metric::Failure(e.kind()), 1);
+            if let Some(t) = e.timeout() {
+                self.collector.send_count(Metric::Timeout(t.phase), 1);
+            }
+        }
+        res
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+
+    /// A request read by `spawn_server`
+    struct Received {
+        method: String,
+        /// With the query
+        path: String,
+        /// The request line and headers, as sent
+        head: String,
+        body: Vec<u8>,
+        from: std::net::SocketAddr,
+    }
+
+    impl Received {
+        /// Value of the header `name`, lowercased as hyper sends it
+        fn header(&self, name: &str) -> Option<&str> {
+            self.head
+                .lines()
+                .find_map(|l| l.strip_prefix(name)?.strip_prefix(':'))
+                .map(str::trim)
+        }
+
+        fn body_text(&self) -> String {
+            String::from_utf8_lossy(&self.body).into_owned()
+        }
+    }
+
+    /// Answer each request on a blocking thread with the response `respond` makes of it, one
+    /// request per connection, and return the port it listens on
+    fn spawn_server<F>(respond: F) -> u16
+    where
+        F: Fn(Received) -> Vec<u8> + Send + 'static,
+    {
+        use std::io::{BufRead, BufReader, Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = stream.unwrap();
+                let from = stream.peer_addr().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line).unwrap_or(0) == 0 || line == "\r\n" {
+                        break;
+                    }
+                    head.push_str(&line);
+                }
+                if head.is_empty() {
+                    continue;
+                }
+                let mut words = head.split_whitespace();
+                let (method, path) = (words.next().unwrap(), words.next().unwrap());
+                let mut received = Received {
+                    method: method.to_string(),
+                    path: path.to_string(),
+                    head: head.clone(),
+                    body: Vec::new(),
+                    from,
+                };
+                let len = received
+                    .header("content-length")
+                    .map_or(0, |l| l.parse().unwrap());
+                received.body = vec![0; len];
+                reader.read_exact(&mut received.body).unwrap();
+                let _ = reader.get_mut().write_all(&respond(received));
+            }
+        });
+        port
+    }
+
+    /// A response with `headers`, each ending in CRLF, and then `body`
+    fn response<B: AsRef<[u8]>>(status: u16, headers: &str, body: B) -> Vec<u8> {
+        let body = body.as_ref();
+        let head = format!(
+            "HTTP/1.1 {} X\r\n{}Content-Length: {}\r\n\r\n",
+            status,
+            headers,
+            body.len()
+        );
+        [head.as_bytes(), body].concat()
+    }
+
     #[test]
     fn client_test() {
         let (c, collector) = Client::new_client_and_collector();
@@ -183,4 +1255,1757 @@ mod test {
             Err(e) => println!("ERROR: {}", e),
         }
     }
+
+    #[test]
+    fn headers_timeout_test() {
+        // Accept connections but never answer
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let _held: Vec<_> = listener.incoming().collect();
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let mut options = ClientOptions::default();
+        options.timeouts.headers = Some(std::time::Duration::from_millis(200));
+        let c = Client::new_with_options(collector.handle(), options);
+        let req = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .body(Body::empty())
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        match rt.block_on(c.request_fully(req)) {
+            Err(ClientError::Timeout(t)) => assert_eq!(Phase::Headers, t.phase),
+            other => panic!(
+                "Expected response headers timeout, got {:?}",
+                other.map(|_| ())
+            ),
+        }
+        collector.process_outstanding();
+        let snapshot = collector.snapshot(&Metric::Timeout(Phase::Headers));
+        assert_eq!(Some(1), snapshot.count());
+    }
+
+    #[test]
+    fn truncated_body_test() {
+        // Promise more body than is sent, then hang up
+        let port =
+            spawn_server(|_| b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\nshort".to_vec());
+
+        let (c, collector) = Client::new_client_and_collector();
+        let req = Request::builder()
+            .uri(format!("http://127.0.0.1:{}/", port))
+            .body(Body::empty())
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let err = rt.block_on(c.request_fully(req)).unwrap_err();
+        assert_eq!(ErrorKind::BodyTruncated, err.kind());
+        collector.process_outstanding();
+        let snapshot = collector.snapshot(&Metric::Failure(ErrorKind::BodyTruncated));
+        assert_eq!(Some(1), snapshot.count());
+    }
+
+    #[test]
+    fn keep_alive_reuse_test() {
+        use std::io::{Read, Write};
+        // Serve two requests on a single connection
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            for _ in 0..2 {
+                let _ = stream.read(&mut buf).unwrap();
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                    .unwrap();
+            }
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let options = ClientOptions {
+            keep_alive: true,
+            ..ClientOptions::default()
+        };
+        let c = Client::new_with_options(collector.handle(), options);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let reused: Vec<bool> = (0..2)
+            .map(|_| {
+                let req = Request::builder()
+                    .uri(format!("http://{}/", addr))
+                    .body(Body::empty())
+                    .unwrap();
+                let (res, _) = rt.block_on(c.request_fully(req)).unwrap();
+                res.extensions.get::<ConnectionInfo>().unwrap().is_reused()
+            })
+            .collect();
+        assert_eq!(vec![false, true], reused);
+        collector.process_outstanding();
+        assert_eq!(Some(1), collector.snapshot(&Metric::Connection).count());
+        assert_eq!(Some(1), collector.snapshot(&Metric::WarmHeaders).count());
+    }
+
+    /// Serve `ok` over TLS on a blocking thread, one request per connection
+    fn spawn_tls_server(config: rustls::ServerConfig) -> std::net::SocketAddr {
+        use rustls::Session;
+        use std::io::{Read, Write};
+        let config = std::sync::Arc::new(config);
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for tcp in listener.incoming() {
+                let mut tcp = tcp.unwrap();
+                let mut session = rustls::ServerSession::new(&config);
+                let mut buf = [0; 1024];
+                let res = rustls::Stream::new(&mut session, &mut tcp).read(&mut buf);
+                if res.is_ok() {
+                    let mut tls = rustls::Stream::new(&mut session, &mut tcp);
+                    let _ = tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok");
+                } else {
+                    // Send the alert explaining why the client was rejected, then wait for the
+                    // client to hang up so unread data doesn't reset the connection before the
+                    // alert is read
+                    let _ = session.write_tls(&mut tcp);
+                    let _ = tcp.shutdown(std::net::Shutdown::Write);
+                    let _ = std::io::copy(&mut tcp, &mut std::io::sink());
+                }
+            }
+        });
+        addr
+    }
+
+    /// Server config presenting `chain`, leaf first, whose key is `key`
+    fn tls_server_config(
+        client_auth: std::sync::Arc<dyn rustls::ClientCertVerifier>,
+        chain: &[&[u8]],
+        key: &rcgen::KeyPair,
+    ) -> rustls::ServerConfig {
+        let mut config = rustls::ServerConfig::new(client_auth);
+        let chain = chain.iter().map(|c| rustls::Certificate(c.to_vec()));
+        config
+            .set_single_cert(chain.collect(), rustls::PrivateKey(key.serialize_der()))
+            .unwrap();
+        config
+    }
+
+    /// Serve `ok` over TLS with a new certificate for `names`, signed by itself.  Returns the
+    /// server's address and the certificate, to trust as a root.
+    fn self_signed_tls_server(names: Vec<String>) -> (std::net::SocketAddr, Vec<u8>) {
+        self_signed_tls_server_with(names, |_| {})
+    }
+
+    /// `self_signed_tls_server`, with `configure` adjusting the config before the server starts
+    fn self_signed_tls_server_with<F: FnOnce(&mut rustls::ServerConfig)>(
+        names: Vec<String>,
+        configure: F,
+    ) -> (std::net::SocketAddr, Vec<u8>) {
+        let cert = rcgen::generate_simple_self_signed(names).unwrap();
+        let cert_der = cert.cert.der().to_vec();
+        let client_auth = rustls::NoClientAuth::new();
+        let mut config = tls_server_config(client_auth, &[&cert_der], &cert.key_pair);
+        configure(&mut config);
+        (spawn_tls_server(config), cert_der)
+    }
+
+    #[test]
+    fn tls_trust_test() {
+        use crate::tls::Certificate;
+        let (addr, cert_der) = self_signed_tls_server(vec!["localhost".to_string()]);
+        let cert_len = cert_der.len() as u64;
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let collector = Collector::new();
+        let mut fetch = |tls: TlsOptions| {
+            let options = ClientOptions {
+                tls,
+                ..ClientOptions::default()
+            };
+            let c = Client::new_with_options(collector.handle(), options);
+            let req = Request::builder()
+                .uri(format!("https://localhost:{}/", addr.port()))
+                .body(Body::empty())
+                .unwrap();
+            rt.block_on(c.request_fully(req))
+        };
+        // Self-signed, so only trusted when given as a root or when verification is off
+        let err = fetch(TlsOptions::default()).unwrap_err();
+        assert_eq!(ErrorKind::TlsVerification, err.kind());
+        let trusted = TlsOptions {
+            roots: Some(vec![Certificate(cert_der)]),
+            ..TlsOptions::default()
+        };
+        let (parts, body) = fetch(trusted).unwrap();
+        assert_eq!(&b"ok"[..], &body[..]);
+        let info = parts.extensions.get::<ConnectionInfo>().unwrap();
+        // The server's certificate is part of what it sends during the handshake
+        let handshake = info.tls_handshake_bytes().unwrap();
+        assert!(handshake.sent > 0);
+        assert!(handshake.received > cert_len);
+        let tls = info.tls().unwrap();
+        assert_eq!(Some("TLSv1.3"), tls.version.as_deref());
+        assert_eq!(Some("localhost"), tls.sni.as_deref());
+        assert_eq!(1, tls.chain_len);
+        let leaf = tls.leaf.as_ref().unwrap();
+        assert_eq!(vec!["localhost".to_string()], leaf.sans);
+        assert!(leaf.days_until_expiry() > 365);
+        let insecure = TlsOptions {
+            insecure: true,
+            ..TlsOptions::default()
+        };
+        assert_eq!(&b"ok"[..], &fetch(insecure).unwrap().1[..]);
+    }
+
+    #[test]
+    fn certificate_info_test() {
+        use crate::tls::Certificate;
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let mut ca_params = rcgen::CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        ca_params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "Tracer Test CA");
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        let mut params = rcgen::CertificateParams::new(names).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "localhost");
+        params.not_after = rcgen::date_time_ymd(2100, 1, 1);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+        let chain: &[&[u8]] = &[cert.der(), ca.der()];
+        let config = tls_server_config(rustls::NoClientAuth::new(), chain, &key);
+        let addr = spawn_tls_server(config);
+
+        let options = ClientOptions {
+            tls: TlsOptions {
+                roots: Some(vec![Certificate(ca.der().to_vec())]),
+                ..TlsOptions::default()
+            },
+            ..ClientOptions::default()
+        };
+        let collector = Collector::new();
+        let c = Client::new_with_options(collector.handle(), options);
+        let req = Request::builder()
+            .uri(format!("https://localhost:{}/", addr.port()))
+            .body(Body::empty())
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (parts, _) = rt.block_on(c.request_fully(req)).unwrap();
+        let tls = parts
+            .extensions
+            .get::<ConnectionInfo>()
+            .unwrap()
+            .tls()
+            .unwrap();
+        assert_eq!(2, tls.chain_len);
+        assert!(!tls.resumed);
+        let leaf = tls.leaf.as_ref().unwrap();
+        assert_eq!("CN=localhost", leaf.subject);
+        assert_eq!("CN=Tracer Test CA", leaf.issuer);
+        assert_eq!(vec!["localhost", "127.0.0.1"], leaf.sans);
+        // 2100-01-01T00:00:00Z
+        let not_after = UNIX_EPOCH + Duration::from_secs(4_102_444_800);
+        assert_eq!(not_after, leaf.not_after);
+        let left = not_after.duration_since(SystemTime::now()).unwrap();
+        let days = (left.as_secs() / (24 * 60 * 60)) as i64;
+        // Unless a day boundary passed in between
+        assert!((days - 1..=days).contains(&leaf.days_until_expiry()));
+    }
+
+    #[test]
+    fn client_cert_test() {
+        use crate::tls::{self, Certificate};
+        let server = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let server_der = server.cert.der().to_vec();
+        let mut ca_params = rcgen::CertificateParams::new(vec![]).unwrap();
+        ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        let ca_key = rcgen::KeyPair::generate().unwrap();
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let client_key = rcgen::KeyPair::generate().unwrap();
+        let client = rcgen::CertificateParams::new(vec!["client".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca, &ca_key)
+            .unwrap();
+
+        let mut client_roots = rustls::RootCertStore::empty();
+        client_roots
+            .add(&rustls::Certificate(ca.der().to_vec()))
+            .unwrap();
+        let client_auth = rustls::AllowAnyAuthenticatedClient::new(client_roots);
+        let config = tls_server_config(client_auth, &[&server_der], &server.key_pair);
+        let addr = spawn_tls_server(config);
+
+        let dir = std::env::temp_dir().join(format!("tracer-client-cert-{}", addr.port()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("client.pem"), client.pem()).unwrap();
+        std::fs::write(dir.join("client.key"), client_key.serialize_pem()).unwrap();
+        let client_cert = tls::load_client_cert(dir.join("client.pem"), dir.join("client.key"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let collector = Collector::new();
+        let mut fetch = |client_cert| {
+            let options = ClientOptions {
+                tls: TlsOptions {
+                    roots: Some(vec![Certificate(server_der.clone())]),
+                    client_cert,
+                    ..TlsOptions::default()
+                },
+                ..ClientOptions::default()
+            };
+            let c = Client::new_with_options(collector.handle(), options);
+            let req = Request::builder()
+                .uri(format!("https://localhost:{}/", addr.port()))
+                .body(Body::empty())
+                .unwrap();
+            rt.block_on(c.request_fully(req)).map(|(_, body)| body)
+        };
+        let err = fetch(None).unwrap_err();
+        assert_eq!(ErrorKind::TlsClientAuth, err.kind());
+        assert_eq!(&b"ok"[..], &fetch(Some(client_cert.unwrap())).unwrap()[..]);
+    }
+
+    #[test]
+    fn tls_resumption_test() {
+        use crate::tls::Certificate;
+        let (addr, cert_der) = self_signed_tls_server(vec!["localhost".to_string()]);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut resumed = |resume: bool| {
+            let mut collector = Collector::new();
+            Client::configure_collector_defaults(&mut collector);
+            let options = ClientOptions {
+                tls: TlsOptions {
+                    roots: Some(vec![Certificate(cert_der.clone())]),
+                    resume,
+                    ..TlsOptions::default()
+                },
+                ..ClientOptions::default()
+            };
+            let c = Client::new_with_options(collector.handle(), options);
+            let resumed: Vec<bool> = (0..3)
+                .map(|_| {
+                    let req = Request::builder()
+                        .uri(format!("https://localhost:{}/", addr.port()))
+                        .body(Body::empty())
+                        .unwrap();
+                    let (res, _) = rt.block_on(c.request_fully(req)).unwrap();
+                    let info = res.extensions.get::<ConnectionInfo>().unwrap();
+                    info.tls().unwrap().resumed
+                })
+                .collect();
+            collector.process_outstanding();
+            let count = |m| collector.snapshot(&m).count().unwrap_or(0);
+            (resumed, count(Metric::TlsFull), count(Metric::TlsResumed))
+        };
+        assert_eq!((vec![false, false, false], 0, 0), resumed(false));
+        assert_eq!((vec![false, true, true], 1, 2), resumed(true));
+    }
+
+    #[test]
+    fn ip_literal_test() {
+        use crate::tls::Certificate;
+        let names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+        let (addr, cert_der) = self_signed_tls_server(names);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let collector = Collector::new();
+        let mut fetch = |server_name: Option<&str>| {
+            let options = ClientOptions {
+                tls: TlsOptions {
+                    roots: Some(vec![Certificate(cert_der.clone())]),
+                    server_name: server_name.map(String::from),
+                    ..TlsOptions::default()
+                },
+                ..ClientOptions::default()
+            };
+            let c = Client::new_with_options(collector.handle(), options);
+            let req = Request::builder()
+                .uri(format!("https://127.0.0.1:{}/", addr.port()))
+                .body(Body::empty())
+                .unwrap();
+            rt.block_on(c.request_fully(req))
+        };
+        // Verified against the IP SAN, without SNI
+        let (parts, _) = fetch(None).unwrap();
+        let tls = parts.extensions.get::<ConnectionInfo>().unwrap().tls();
+        assert_eq!(None, tls.unwrap().sni);
+        let (parts, _) = fetch(Some("localhost")).unwrap();
+        let tls = parts.extensions.get::<ConnectionInfo>().unwrap().tls();
+        assert_eq!(Some("localhost"), tls.unwrap().sni.as_deref());
+        let err = fetch(Some("example.com")).unwrap_err();
+        assert_eq!(ErrorKind::TlsVerification, err.kind());
+        let err = fetch(Some("127.0.0.2")).unwrap_err();
+        assert_eq!(ErrorKind::TlsVerification, err.kind());
+    }
+
+    #[test]
+    fn tls_version_pinning_test() {
+        use crate::tls::{Certificate, TlsVersion};
+        let server = |versions: Vec<rustls::ProtocolVersion>| {
+            self_signed_tls_server_with(vec!["localhost".to_string()], |config| {
+                config.versions = versions
+            })
+        };
+        let any_version = server(vec![
+            rustls::ProtocolVersion::TLSv1_3,
+            rustls::ProtocolVersion::TLSv1_2,
+        ]);
+        let tls13_only = server(vec![rustls::ProtocolVersion::TLSv1_3]);
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let collector = Collector::new();
+        let mut fetch = |server: &(std::net::SocketAddr, Vec<u8>), version, suite: &str| {
+            let (addr, cert_der) = server;
+            let options = ClientOptions {
+                tls: TlsOptions {
+                    roots: Some(vec![Certificate(cert_der.clone())]),
+                    versions: Some(vec![version]),
+                    cipher_suites: Some(vec![suite.to_string()]),
+                    ..TlsOptions::default()
+                },
+                ..ClientOptions::default()
+            };
+            let c = Client::new_with_options(collector.handle(), options);
+            let req = Request::builder()
+                .uri(format!("https://localhost:{}/", addr.port()))
+                .body(Body::empty())
+                .unwrap();
+            rt.block_on(c.request_fully(req)).map(|(parts, _)| {
+                let info = parts.extensions.get::<ConnectionInfo>().unwrap();
+                let tls = info.tls().unwrap().clone();
+                (tls.version.unwrap(), tls.cipher_suite.unwrap())
+            })
+        };
+        let suite = "TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384";
+        assert_eq!(
+            ("TLSv1.2".to_string(), suite.to_string()),
+            fetch(&any_version, TlsVersion::Tls12, suite).unwrap()
+        );
+        let suite = "TLS13_CHACHA20_POLY1305_SHA256";
+        assert_eq!(
+            ("TLSv1.3".to_string(), suite.to_string()),
+            fetch(&any_version, TlsVersion::Tls13, suite).unwrap()
+        );
+        let err = fetch(
+            &tls13_only,
+            TlsVersion::Tls12,
+            "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256",
+        )
+        .unwrap_err();
+        assert_eq!(ErrorKind::TlsHandshake, err.kind());
+    }
+
+    /// Copy data both ways between two connections until either side hangs up
+    fn pipe(a: std::net::TcpStream, b: std::net::TcpStream) {
+        let (mut a2, mut b2) = (a.try_clone().unwrap(), b.try_clone().unwrap());
+        let (mut a, mut b) = (a, b);
+        std::thread::spawn(move || {
+            let _ = std::io::copy(&mut a, &mut b2);
+            let _ = b2.shutdown(std::net::Shutdown::Write);
+        });
+        std::thread::spawn(move || {
+            let _ = std::io::copy(&mut b, &mut a2);
+            let _ = a2.shutdown(std::net::Shutdown::Write);
+        });
+    }
+
+    #[test]
+    fn http_proxy_test() {
+        use crate::proxy::Proxy;
+        use crate::tls::Certificate;
+        use std::io::{BufRead, BufReader, Write};
+        let (origin, cert_der) = self_signed_tls_server(vec!["localhost".to_string()]);
+
+        // Tunnels CONNECT requests and answers forwarded ones itself, if they carry credentials
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for tcp in listener.incoming() {
+                let mut tcp = tcp.unwrap();
+                let mut reader = BufReader::new(tcp.try_clone().unwrap());
+                let mut head = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    head.push(line);
+                }
+                // dXNlcjpwYXNz is user:pass
+                let auth = "proxy-authorization: basic dxnlcjpwyxnz\r\n";
+                if !head.iter().any(|h| h.to_lowercase() == auth) {
+                    let _ = tcp.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n");
+                } else if head[0].starts_with("CONNECT ") {
+                    let target = head[0].split(' ').nth(1).unwrap();
+                    let port = target.rsplit(':').next().unwrap();
+                    let upstream =
+                        std::net::TcpStream::connect(("127.0.0.1", port.parse::<u16>().unwrap()))
+                            .unwrap();
+                    tcp.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                        .unwrap();
+                    pipe(tcp, upstream);
+                } else {
+                    assert!(head[0].starts_with("GET http://example.invalid/ "));
+                    let _ = tcp.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\n\r\nforwarded");
+                }
+            }
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let mut fetch = |url: String, user: &str| {
+            let mut collector = Collector::new();
+            Client::configure_collector_defaults(&mut collector);
+            let proxy = Proxy::parse(&format!("http://{}@{}", user, proxy_addr)).unwrap();
+            let options = ClientOptions {
+                tls: TlsOptions {
+                    roots: Some(vec![Certificate(cert_der.clone())]),
+                    ..TlsOptions::default()
+                },
+                proxy: ProxyOptions::all(proxy),
+                ..ClientOptions::default()
+            };
+            let c = Client::new_with_options(collector.handle(), options);
+            let req = Request::builder().uri(url).body(Body::empty()).unwrap();
+            let res = rt.block_on(c.request_fully(req));
+            collector.process_outstanding();
+            let count = |m| collector.snapshot(&m).count().unwrap_or(0);
+            let counts = (
+                count(Metric::Connection),
+                count(Metric::ProxyConnect),
+                count(Metric::ProxyHandshake),
+                count(Metric::Tls),
+            );
+            (res.map(|(_, body)| body), counts)
+        };
+        let (body, counts) = fetch(format!("https://localhost:{}/", origin.port()), "user:pass");
+        assert_eq!(&b"ok"[..], &body.unwrap()[..]);
+        assert_eq!((0, 1, 1, 1), counts);
+        let (body, counts) = fetch("http://example.invalid/".into(), "user:pass");
+        assert_eq!(&b"forwarded"[..], &body.unwrap()[..]);
+        assert_eq!((0, 1, 0, 0), counts);
+        let (res, _) = fetch(
+            format!("https://localhost:{}/", origin.port()),
+            "user:wrong",
+        );
+        assert_eq!(ErrorKind::Proxy, res.unwrap_err().kind());
+    }
+
+    #[test]
+    fn socks5_proxy_test() {
+        use crate::proxy::Proxy;
+        use std::io::{Read, Write};
+        let origin = spawn_server(|_| response(200, "", "ok"));
+
+        // Requires user:pass, and reports the address type of each request back to the test
+        let (types, received) = std::sync::mpsc::channel();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for tcp in listener.incoming() {
+                let mut tcp = tcp.unwrap();
+                let mut greeting = [0; 4];
+                tcp.read_exact(&mut greeting).unwrap();
+                assert_eq!([5, 2, 0, 2], greeting);
+                tcp.write_all(&[5, 2]).unwrap();
+                let mut auth = [0; 11];
+                tcp.read_exact(&mut auth).unwrap();
+                assert_eq!(b"\x01\x04user\x04pass", &auth);
+                tcp.write_all(&[1, 0]).unwrap();
+                let mut req = [0; 4];
+                tcp.read_exact(&mut req).unwrap();
+                let mut addr = vec![0; if req[3] == 1 { 4 } else { 1 }];
+                tcp.read_exact(&mut addr).unwrap();
+                if req[3] == 3 {
+                    addr = vec![0; addr[0] as usize];
+                    tcp.read_exact(&mut addr).unwrap();
+                }
+                let mut port = [0; 2];
+                tcp.read_exact(&mut port).unwrap();
+                types.send((req[3], addr)).unwrap();
+                let upstream =
+                    std::net::TcpStream::connect(("127.0.0.1", u16::from_be_bytes(port))).unwrap();
+                tcp.write_all(&[5, 0, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+                pipe(tcp, upstream);
+            }
+        });
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let collector = Collector::new();
+        let mut fetch = |scheme: &str| {
+            let proxy = Proxy::parse(&format!("{}://user:pass@{}", scheme, proxy_addr)).unwrap();
+            let options = ClientOptions {
+                proxy: ProxyOptions::all(proxy),
+                ..ClientOptions::default()
+            };
+            let c = Client::new_with_options(collector.handle(), options);
+            let req = Request::builder()
+                .uri(format!("http://localhost:{}/", origin))
+                .body(Body::empty())
+                .unwrap();
+            let (_, body) = rt.block_on(c.request_fully(req)).unwrap();
+            assert_eq!(&b"ok"[..], &body[..]);
+            received.recv().unwrap()
+        };
+        // socks5 resolves locally and sends the address, socks5h leaves it to the proxy
+        assert_eq!((1, vec![127, 0, 0, 1]), fetch("socks5"));
+        assert_eq!((3, b"localhost".to_vec()), fetch("socks5h"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn unix_socket_test() {
+        use std::io::{Read, Write};
+        let path = std::env::temp_dir().join(format!("tracer-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let (requests, received) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let n = stream.read(&mut buf).unwrap();
+            requests
+                .send(String::from_utf8_lossy(&buf[..n]).into_owned())
+                .unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let options = ClientOptions {
+            unix_socket: Some(path.clone()),
+            ..ClientOptions::default()
+        };
+        let c = Client::new_with_options(collector.handle(), options);
+        let req = Request::builder()
+            .uri("http://sidecar.invalid/status")
+            .body(Body::empty())
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (_, body) = rt.block_on(c.request_fully(req)).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(&b"ok"[..], &body[..]);
+        let request = received.recv().unwrap();
+        assert!(request.starts_with("GET /status HTTP/1.1\r\n"));
+        assert!(request.contains("host: sidecar.invalid\r\n"));
+        collector.process_outstanding();
+        assert_eq!(Some(1), collector.snapshot(&Metric::Connection).count());
+        assert_eq!(Some(0), collector.snapshot(&Metric::Dns).count());
+    }
+
+    #[test]
+    fn local_address_test() {
+        let (peers, peer) = std::sync::mpsc::channel();
+        let port = spawn_server(move |req| {
+            peers.send(req.from).unwrap();
+            response(200, "", "ok")
+        });
+        let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
+        // Find a free port to connect from
+        let local_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let options = ClientOptions {
+            socket: SocketOptions {
+                local_address: Some([127, 0, 0, 1].into()),
+                local_port: Some(local_port),
+                keepalive: Some(Duration::from_secs(30)),
+                ttl: Some(16),
+                ..SocketOptions::default()
+            },
+            ..ClientOptions::default()
+        };
+        let c = Client::new_with_options(collector.handle(), options.clone());
+        let req = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .body(Body::empty())
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (parts, _) = rt.block_on(c.request_fully(req)).unwrap();
+        let info = parts.extensions.get::<ConnectionInfo>().unwrap();
+        let from = peer.recv().unwrap();
+        assert_eq!(local_port, from.port());
+        assert_eq!(Some(from), info.local_addr());
+
+        // An IPv6 source address can't reach an IPv4 server
+        let options = ClientOptions {
+            socket: SocketOptions {
+                local_address: Some("::1".parse().unwrap()),
+                ..SocketOptions::default()
+            },
+            ..ClientOptions::default()
+        };
+        let c = Client::new_with_options(collector.handle(), options);
+        let req = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .body(Body::empty())
+            .unwrap();
+        assert!(rt.block_on(c.request_fully(req)).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn tcp_info_test() {
+        let port = spawn_server(|_| response(200, "", "ok"));
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let c = Client::new_with_collector_handle(collector.handle());
+        let req = Request::builder()
+            .uri(format!("http://127.0.0.1:{}/", port))
+            .body(Body::empty())
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let (parts, _) = rt.block_on(c.request_fully(req)).unwrap();
+        let info = parts.extensions.get::<ConnectionInfo>().unwrap();
+        assert!(info.connect_tcp_info().is_some());
+        let tcp = info.tcp_info().unwrap();
+        assert!(tcp.cwnd > 0);
+        // The request has been acknowledged by the time the response is read
+        assert!(tcp.bytes_acked.unwrap() > 0);
+        collector.process_outstanding();
+        assert_eq!(Some(1), collector.snapshot(&Metric::TcpRtt).count());
+        assert_eq!(Some(1), collector.snapshot(&Metric::TcpCwnd).count());
+    }
+
+    #[test]
+    fn timeline_test() {
+        use crate::events::{Event, EventSet};
+        let port = spawn_server(|_| response(200, "", "ok"));
+
+        let (c, _collector) = Client::new_client_and_collector();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let req = Request::builder()
+            .uri(format!("http://localhost:{}/", port))
+            .body(Body::empty())
+            .unwrap();
+        let (parts, _) = rt.block_on(c.request_fully(req)).unwrap();
+        let timeline = parts.extensions.get::<EventSet>().unwrap();
+        let events: Vec<Event> = timeline.since_initiated().map(|(e, _)| e).collect();
+        assert_eq!(
+            vec![
+                Event::Initiated,
+                Event::DnsResolutionStarted,
+                Event::DnsResolutionFinished,
+                Event::ConnectionStarted,
+                Event::Connected,
+                Event::RequestWritten,
+                Event::FirstByte,
+                Event::HeadersReceived,
+                Event::FirstBodyByte,
+                Event::FullResponse,
+            ],
+            events
+        );
+        assert!(timeline
+            .time_between(Event::RequestWritten, Event::FirstByte)
+            .is_some());
+
+        // Nothing listens on the port any more
+        let req = Request::builder()
+            .uri(format!(
+                "http://{}/",
+                std::net::TcpListener::bind("127.0.0.1:0")
+                    .unwrap()
+                    .local_addr()
+                    .unwrap()
+            ))
+            .body(Body::empty())
+            .unwrap();
+        let (res, trace) = rt.block_on(c.request_fully_traced(req));
+        assert!(res.is_err());
+        let events: Vec<Event> = trace.timeline.since_initiated().map(|(e, _)| e).collect();
+        assert_eq!(
+            vec![
+                Event::Initiated,
+                Event::ConnectionStarted,
+                Event::ConnectionError,
+                Event::RequestFailed,
+            ],
+            events
+        );
+    }
+
+    #[test]
+    fn exchange_metrics_test() {
+        use crate::context::RequestMetrics;
+        use std::io::{Read, Write};
+        const UPLOAD: usize = 256 * 1024;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 16 * 1024];
+            // Read the whole upload before answering
+            loop {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+                let head = request.windows(4).position(|w| w == b"\r\n\r\n");
+                if n == 0
+                    || head
+                        .map(|h| request.len() >= h + 4 + UPLOAD)
+                        .unwrap_or(false)
+                {
+                    break;
+                }
+            }
+            // Server think time, then a response whose headers arrive slowly
+            std::thread::sleep(Duration::from_millis(100));
+            stream.write_all(b"HTTP/1.1 200 OK\r\n").unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+            stream.write_all(b"Content-Length: 2\r\n\r\nok").unwrap();
+        });
+
+        let (c, _collector) = Client::new_client_and_collector();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri(format!("http://{}/", addr))
+            .body(Body::from(vec![b'x'; UPLOAD]))
+            .unwrap();
+        let (mut parts, _) = rt.block_on(c.request_fully(req)).unwrap();
+        let metrics = parts.extensions.remove::<RequestMetrics>().unwrap();
+        let written = metrics.elapsed(Metric::RequestWrite).unwrap();
+        let upload = metrics.elapsed(Metric::Upload).unwrap();
+        let first_byte = metrics.elapsed(Metric::FirstByte).unwrap();
+        let headers = metrics.elapsed(Metric::Headers).unwrap();
+        assert!(upload <= written);
+        assert!(first_byte >= written + Duration::from_millis(100));
+        assert!(headers >= first_byte + Duration::from_millis(200));
+    }
+
+    #[test]
+    fn body_stream_test() {
+        use crate::body::BodyStats;
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 30000\r\n\r\n")
+                .unwrap();
+            // The body trickles in, in three parts
+            for _ in 0..3 {
+                std::thread::sleep(Duration::from_millis(150));
+                stream.write_all(&[b'x'; 10000]).unwrap();
+            }
+        });
+
+        let (c, collector) = Client::new_client_and_collector();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let req = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .body(Body::empty())
+            .unwrap();
+        let mut received = 0;
+        let parts = rt
+            .block_on(c.request_streaming(req, |chunk| received += chunk.len()))
+            .unwrap();
+        assert_eq!(30000, received);
+        let stats = parts.extensions.get::<BodyStats>().unwrap();
+        assert_eq!(30000, stats.len);
+        assert!(stats.chunks >= 3);
+        assert!(stats.first_byte.unwrap() >= Duration::from_millis(150));
+        assert!(stats.max_gap.unwrap() >= Duration::from_millis(100));
+        assert!(stats.transfer >= Duration::from_millis(300));
+        let throughput = stats.throughput.unwrap();
+        assert!(throughput > 0 && throughput <= 30000 * 10 / 3);
+        assert!(stats.peak_throughput.is_some());
+        collector.process_outstanding();
+        assert!(collector.snapshot(&Metric::ChunkGap).count().unwrap() >= 2);
+        assert_eq!(Some(1), collector.snapshot(&Metric::Throughput).count());
+    }
+
+    #[test]
+    fn byte_count_test() {
+        use crate::context::RequestMetrics;
+        use std::io::{Read, Write};
+        const HEAD: &[u8] = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n";
+        const BODY: &[u8] = b"2\r\nok\r\n0\r\n\r\n";
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            tx.send(stream.read(&mut buf).unwrap()).unwrap();
+            // The head and body arrive separately, so the end of the head is split across reads
+            stream.write_all(&HEAD[..HEAD.len() - 2]).unwrap();
+            stream.flush().unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            stream.write_all(&HEAD[HEAD.len() - 2..]).unwrap();
+            stream.write_all(BODY).unwrap();
+        });
+
+        let (c, _collector) = Client::new_client_and_collector();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let req = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .body(Body::empty())
+            .unwrap();
+        let (mut parts, body) = rt.block_on(c.request_fully(req)).unwrap();
+        assert_eq!(&b"ok"[..], &body[..]);
+        let request_len = rx.recv().unwrap() as u64;
+        let metrics = parts.extensions.remove::<RequestMetrics>().unwrap();
+        assert_eq!(Some(request_len), metrics.value(Metric::RequestBytes));
+        assert_eq!(
+            Some(HEAD.len() as u64),
+            metrics.value(Metric::ResponseHeaderBytes)
+        );
+        assert_eq!(
+            Some(BODY.len() as u64),
+            metrics.value(Metric::ResponseBodyBytes)
+        );
+        assert_eq!(Some(request_len), metrics.value(Metric::WireBytesSent));
+        assert_eq!(
+            Some((HEAD.len() + BODY.len()) as u64),
+            metrics.value(Metric::WireBytesReceived)
+        );
+        assert_eq!(None, metrics.value(Metric::TlsHandshakeBytesSent));
+    }
+
+    #[test]
+    fn concurrent_metrics_test() {
+        use crate::context::RequestMetrics;
+        // Two servers, one much slower to respond than the other
+        let ports: Vec<u16> = [0, 300]
+            .iter()
+            .map(|delay| {
+                let delay = Duration::from_millis(*delay);
+                spawn_server(move |_| {
+                    std::thread::sleep(delay);
+                    response(200, "", "ok")
+                })
+            })
+            .collect();
+
+        let (c, _collector) = Client::new_client_and_collector();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let request = |port: u16| {
+            let req = Request::builder()
+                .uri(format!("http://127.0.0.1:{}/", port))
+                .body(Body::empty())
+                .unwrap();
+            c.request_fully(req)
+        };
+        let (fast, slow) = rt.block_on(futures::future::join(request(ports[0]), request(ports[1])));
+        let fast = fast
+            .unwrap()
+            .0
+            .extensions
+            .remove::<RequestMetrics>()
+            .unwrap();
+        let slow = slow
+            .unwrap()
+            .0
+            .extensions
+            .remove::<RequestMetrics>()
+            .unwrap();
+        assert_ne!(fast.id(), slow.id());
+        assert_eq!(
+            1,
+            fast.iter()
+                .filter(|(m, _)| *m == Metric::Connection)
+                .count()
+        );
+        assert_eq!(
+            1,
+            slow.iter()
+                .filter(|(m, _)| *m == Metric::Connection)
+                .count()
+        );
+        assert!(fast.elapsed(Metric::Headers).unwrap() < Duration::from_millis(300));
+        assert!(slow.elapsed(Metric::Headers).unwrap() >= Duration::from_millis(300));
+    }
+
+    #[test]
+    fn redirect_test() {
+        use crate::context::RequestMetrics;
+        use crate::redirect::{RedirectOptions, Redirects};
+        use hyper::http::StatusCode;
+        // Ends the chain on another port, so another origin
+        let other = spawn_server(|req| {
+            let auth = req.header("authorization").is_some();
+            let body = format!("{} {} auth={}", req.method, req.body_text(), auth);
+            response(200, "", body)
+        });
+        let port = spawn_server(move |req| match req.path.as_str() {
+            "/start" => response(302, "Location: next?a=1#frag\r\n", ""),
+            "/next?a=1" => {
+                let location = format!("Location: http://127.0.0.1:{}/end\r\n", other);
+                response(307, &location, "")
+            }
+            "/keep" => response(308, "Location: /echo\r\n", ""),
+            "/echo" => response(200, "", format!("{} {}", req.method, req.body_text())),
+            _ => response(404, "", ""),
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let options = ClientOptions {
+            redirects: RedirectOptions {
+                max_redirects: 10,
+                ..RedirectOptions::default()
+            },
+            ..ClientOptions::default()
+        };
+        let c = Client::new_with_options(collector.handle(), options.clone());
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let post = |path: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("http://127.0.0.1:{}{}", port, path))
+                .header("authorization", "Bearer secret")
+                .body(Body::from("data"))
+                .unwrap()
+        };
+
+        // POST becomes GET on 302, and 307 keeps it a GET without a body.  Credentials
+        // aren't passed to the other origin.
+        let (mut res, body) = rt.block_on(c.request_fully(post("/start"))).unwrap();
+        assert_eq!(StatusCode::OK, res.status);
+        assert_eq!(&b"GET  auth=false"[..], &body[..]);
+        let redirects = res.extensions.remove::<Redirects>().unwrap();
+        let statuses: Vec<u16> = redirects.hops.iter().map(|h| h.status.as_u16()).collect();
+        assert_eq!(vec![302, 307], statuses);
+        assert_eq!(
+            format!("http://127.0.0.1:{}/next?a=1", port),
+            redirects.hops[1].url.to_string()
+        );
+        assert_eq!(
+            format!("http://127.0.0.1:{}/end", other),
+            redirects.url.to_string()
+        );
+        for hop in &redirects.hops {
+            assert!(hop.metrics.elapsed(Metric::Connection).is_some());
+            assert!(hop.metrics.elapsed(Metric::FullResponse).is_some());
+        }
+        let metrics = res.extensions.remove::<RequestMetrics>().unwrap();
+        assert_eq!(Some(2), metrics.value(Metric::Redirects));
+        assert!(metrics.elapsed(Metric::RedirectTotal).unwrap() >= redirects.total);
+
+        // 308 sends the same method and body again, unless told not to
+        let (_, body) = rt.block_on(c.request_fully(post("/keep"))).unwrap();
+        assert_eq!(&b"POST data"[..], &body[..]);
+        let mut no_resend = options.clone();
+        no_resend.redirects.resend_body = false;
+        let c = Client::new_with_options(collector.handle(), no_resend);
+        let (res, _) = rt.block_on(c.request_fully(post("/keep"))).unwrap();
+        assert_eq!(StatusCode::PERMANENT_REDIRECT, res.status);
+        assert!(res.extensions.get::<Redirects>().is_none());
+
+        // The last redirect is returned once the limit is reached
+        let mut limited = options;
+        limited.redirects.max_redirects = 1;
+        let c = Client::new_with_options(collector.handle(), limited);
+        let (res, _) = rt.block_on(c.request_fully(post("/start"))).unwrap();
+        assert_eq!(StatusCode::TEMPORARY_REDIRECT, res.status);
+        assert_eq!(1, res.extensions.get::<Redirects>().unwrap().hops.len());
+
+        collector.process_outstanding();
+        assert_eq!(Some(4), collector.snapshot(&Metric::Requests).count());
+        assert_eq!(Some(4), collector.snapshot(&Metric::Redirects).count());
+        assert_eq!(Some(3), collector.snapshot(&Metric::RedirectTotal).count());
+    }
+
+    #[test]
+    fn cookie_test() {
+        use crate::cookies::CookieJar;
+        use hyper::header::HeaderMap;
+        use hyper::Uri;
+        use std::sync::mpsc;
+        use std::time::UNIX_EPOCH;
+        let (sent, cookies_sent) = mpsc::channel();
+        let port = spawn_server(move |req| {
+            sent.send(req.header("cookie").map(str::to_string)).unwrap();
+            match req.path.as_str() {
+                "/login" => response(
+                    200,
+                    "Set-Cookie: session=abc; Path=/; Max-Age=3600\r\n\
+                     Set-Cookie: app=x; Path=/app; HttpOnly\r\n\
+                     Set-Cookie: secret=1; Secure\r\n\
+                     Set-Cookie: elsewhere=1; Domain=example.com\r\n\
+                     Set-Cookie: old=1; Expires=Wed, 21 Oct 2015 07:28:00 GMT\r\n",
+                    "",
+                ),
+                "/go" => response(302, "Location: /app/page\r\n", ""),
+                "/logout" => response(200, "Set-Cookie: session=; Path=/; Max-Age=0\r\n", ""),
+                _ => response(200, "", ""),
+            }
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let jar = CookieJar::new();
+        let mut options = ClientOptions {
+            cookies: Some(jar.clone()),
+            ..ClientOptions::default()
+        };
+        options.redirects.max_redirects = 10;
+        let c = Client::new_with_options(collector.handle(), options);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let get = |path: &str| {
+            Request::builder()
+                .uri(format!("http://127.0.0.1:{}{}", port, path))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let mut fetch = |req| {
+            rt.block_on(c.request_fully(req)).unwrap();
+            cookies_sent.recv().unwrap()
+        };
+
+        assert_eq!(None, fetch(get("/login")));
+        // Another domain's cookie and an expired one aren't kept
+        let mut names: Vec<String> = jar.cookies().into_iter().map(|c| c.name).collect();
+        names.sort();
+        assert_eq!(vec!["app", "secret", "session"], names);
+        // Longer paths first, and the secure cookie isn't sent over http
+        assert_eq!(
+            Some("app=x; session=abc".to_string()),
+            fetch(get("/app/page"))
+        );
+        assert_eq!(Some("session=abc".to_string()), fetch(get("/application")));
+        // Sent after the request's own cookies, and along the redirect
+        let mut req = get("/go");
+        req.headers_mut()
+            .insert(COOKIE, HeaderValue::from_static("mine=1"));
+        assert_eq!(Some("mine=1; session=abc".to_string()), fetch(req));
+        assert_eq!(
+            Some("mine=1; app=x; session=abc".to_string()),
+            cookies_sent.recv().unwrap()
+        );
+        // Max-Age=0 deletes it
+        assert_eq!(Some("session=abc".to_string()), fetch(get("/logout")));
+        assert_eq!(None, fetch(get("/")));
+
+        // A session cookie and one that expires survive saving and loading
+        let mut res = HeaderMap::new();
+        res.insert(
+            "set-cookie",
+            HeaderValue::from_static("kept=1; Expires=Fri, 01-Jan-2100 00:00:00 GMT"),
+        );
+        let uri: Uri = format!("http://127.0.0.1:{}/", port).parse().unwrap();
+        jar.store(&uri, &res);
+        let path = std::env::temp_dir().join(format!("tracer-cookies-{}.txt", port));
+        jar.save(&path).unwrap();
+        let loaded = CookieJar::new();
+        loaded.load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut saved = jar.cookies();
+        let mut reloaded = loaded.cookies();
+        saved.sort_by(|a, b| a.name.cmp(&b.name));
+        reloaded.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(saved, reloaded);
+        let kept = reloaded.iter().find(|c| c.name == "kept").unwrap();
+        let secs = kept.expires.unwrap().duration_since(UNIX_EPOCH).unwrap();
+        assert_eq!(4_102_444_800, secs.as_secs());
+        assert!(reloaded.iter().find(|c| c.name == "app").unwrap().http_only);
+    }
+
+    #[test]
+    fn auth_test() {
+        use crate::auth::AwsSigV4;
+        use crate::context::RequestMetrics;
+        use md5::{Digest, Md5};
+
+        let aws = AwsSigV4 {
+            access_key: "AKIDEXAMPLE".to_string(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+            region: "us-east-1".to_string(),
+            service: "service".to_string(),
+        };
+
+        // Answers with the Authorization received, or a digest challenge without one.  /stale
+        // challenges any answer to another nonce, /headers answers with the credentials received,
+        // and /elsewhere redirects there on another host.
+        let port = spawn_server(|req| {
+            let authorization = req.header("authorization");
+            match (req.path.as_str(), authorization) {
+                ("/moved", _) => response(307, "Location: /digest\r\n", ""),
+                ("/elsewhere", _) => {
+                    let port = req.header("host").and_then(|h| h.rsplit(':').next());
+                    let location =
+                        format!("Location: http://localhost:{}/headers\r\n", port.unwrap());
+                    response(307, &location, "")
+                }
+                ("/headers", _) => {
+                    let body: String = req
+                        .head
+                        .lines()
+                        .filter(|l| l.starts_with("authorization:") || l.starts_with("x-amz-"))
+                        .map(|l| format!("{}\r\n", l))
+                        .collect();
+                    response(200, "", body)
+                }
+                ("/stale", a) if !a.is_some_and(|a| a.contains("\"def\"")) => response(
+                    401,
+                    "WWW-Authenticate: Digest realm=\"tracer\", qop=\"auth\", nonce=\"def\", \
+                     stale=true\r\n",
+                    "",
+                ),
+                ("/digest", None) => response(
+                    401,
+                    "WWW-Authenticate: Digest realm=\"tracer\", qop=\"auth\", nonce=\"abc\"\r\n",
+                    "denied",
+                ),
+                (_, authorization) => {
+                    let body = format!("{} {}", authorization.unwrap_or_default(), req.body_text());
+                    response(200, "", body)
+                }
+            }
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let client = |auth| {
+            let mut options = ClientOptions {
+                auth: Some(auth),
+                ..ClientOptions::default()
+            };
+            options.redirects.max_redirects = 10;
+            Client::new_with_options(collector.handle(), options)
+        };
+        let post = |path: &str| {
+            Request::builder()
+                .method("POST")
+                .uri(format!("http://127.0.0.1:{}{}", port, path))
+                .body(Body::from("data"))
+                .unwrap()
+        };
+        let text = |body: Bytes| String::from_utf8(body.to_vec()).unwrap();
+
+        let basic = client(Auth::Basic {
+            user: "user".to_string(),
+            password: "pass".to_string(),
+        });
+        let (_, body) = rt.block_on(basic.request_fully(post("/"))).unwrap();
+        assert_eq!("Basic dXNlcjpwYXNz data", text(body));
+        // The request's own Authorization wins
+        let mut req = post("/");
+        req.headers_mut()
+            .insert(AUTHORIZATION, HeaderValue::from_static("Custom"));
+        let (_, body) = rt.block_on(basic.request_fully(req)).unwrap();
+        assert_eq!("Custom data", text(body));
+        let bearer = client(Auth::Bearer("token".to_string()));
+        let (_, body) = rt.block_on(bearer.request_fully(post("/"))).unwrap();
+        assert_eq!("Bearer token data", text(body));
+
+        // The challenge is answered after a redirect, and the body sent again with the answer
+        let digest = client(Auth::Digest {
+            user: "user".to_string(),
+            password: "pass".to_string(),
+        });
+        let (mut res, body) = rt.block_on(digest.request_fully(post("/moved"))).unwrap();
+        assert_eq!(StatusCode::OK, res.status);
+        let body = text(body);
+        assert!(body.ends_with(" data"));
+        let cnonce = body
+            .split("cnonce=\"")
+            .nth(1)
+            .and_then(|c| c.split('"').next())
+            .unwrap();
+        let md5 = |s: String| format!("{:x}", Md5::digest(s.as_bytes()));
+        let response = md5(format!(
+            "{}:abc:00000001:{}:auth:{}",
+            md5("user:tracer:pass".to_string()),
+            cnonce,
+            md5("POST:/digest".to_string())
+        ));
+        assert!(body.contains(&format!("response=\"{}\"", response)));
+        let metrics = res.extensions.remove::<RequestMetrics>().unwrap();
+        assert!(metrics.elapsed(Metric::AuthChallenge).is_some());
+        assert_eq!(Some(1), metrics.value(Metric::Redirects));
+        // Later requests answer the challenge up front, counting up its nonce count
+        for nc in &["00000002", "00000003"] {
+            let (mut res, body) = rt.block_on(digest.request_fully(post("/digest"))).unwrap();
+            assert!(text(body).contains(&format!("nc={}", nc)));
+            let metrics = res.extensions.remove::<RequestMetrics>().unwrap();
+            assert!(metrics.elapsed(Metric::AuthChallenge).is_none());
+        }
+        // A challenge to the answer given up front is answered once more, with the new nonce
+        let (mut res, body) = rt.block_on(digest.request_fully(post("/stale"))).unwrap();
+        assert_eq!(StatusCode::OK, res.status);
+        let body = text(body);
+        assert!(body.contains("nonce=\"def\"") && body.contains("nc=00000001"));
+        let metrics = res.extensions.remove::<RequestMetrics>().unwrap();
+        assert!(metrics.elapsed(Metric::AuthChallenge).is_some());
+        let (_, body) = rt.block_on(digest.request_fully(post("/stale"))).unwrap();
+        assert!(text(body).contains("nc=00000002"));
+
+        // The signing headers only go to the host the request was signed for
+        let s3 = client(Auth::AwsSigV4(AwsSigV4 {
+            session_token: Some("token".to_string()),
+            service: "s3".to_string(),
+            ..aws.clone()
+        }));
+        let (_, body) = rt.block_on(s3.request_fully(post("/headers"))).unwrap();
+        let signed = text(body);
+        for name in &[
+            "authorization: AWS4-HMAC-SHA256 ",
+            "x-amz-date: ",
+            "x-amz-security-token: token\r\n",
+            "x-amz-content-sha256: ",
+        ] {
+            assert!(signed.contains(name), "{}", signed);
+        }
+        let (res, body) = rt.block_on(s3.request_fully(post("/elsewhere"))).unwrap();
+        assert_eq!(StatusCode::OK, res.status);
+        assert_eq!("", text(body));
+
+        let aws = client(Auth::AwsSigV4(aws));
+        let (_, body) = rt.block_on(aws.request_fully(post("/"))).unwrap();
+        assert!(body.starts_with(b"AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+        assert!(text(body).contains("SignedHeaders=host;x-amz-date, "));
+
+        collector.process_outstanding();
+        assert_eq!(Some(2), collector.snapshot(&Metric::AuthChallenge).count());
+    }
+
+    #[test]
+    fn retry_test() {
+        use crate::context::RequestMetrics;
+        use crate::retry::{Retries, RetryOptions, RetryReason};
+        use hyper::http::StatusCode;
+        use std::io::{Read, Write};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        // Busy for the first two of every three requests
+        let served = AtomicUsize::new(0);
+        let port = spawn_server(move |_| match served.fetch_add(1, Ordering::SeqCst) % 3 {
+            2 => response(200, "", "ok"),
+            _ => response(503, "", "busy"),
+        });
+        // Never answers the first connection
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let slow_port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || {
+            let _first = listener.accept().unwrap();
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            let _ = stream.read(&mut buf).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+                .unwrap();
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let mut options = ClientOptions {
+            retry: RetryOptions {
+                max_attempts: 3,
+                backoff: Duration::from_millis(50),
+                jitter: false,
+                ..RetryOptions::default()
+            },
+            ..ClientOptions::default()
+        };
+        options.timeouts.headers = Some(Duration::from_millis(200));
+        let c = Client::new_with_options(collector.handle(), options.clone());
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let get = |port: u16| {
+            Request::builder()
+                .uri(format!("http://127.0.0.1:{}/", port))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Only the body of the final response is passed on
+        let (mut res, body) = rt.block_on(c.request_fully(get(port))).unwrap();
+        assert_eq!(StatusCode::OK, res.status);
+        assert_eq!(&b"ok"[..], &body[..]);
+        let retries = res.extensions.remove::<Retries>().unwrap();
+        let backoffs: Vec<Duration> = retries.attempts.iter().map(|a| a.backoff).collect();
+        assert_eq!(
+            vec![Duration::from_millis(50), Duration::from_millis(100)],
+            backoffs
+        );
+        for attempt in &retries.attempts {
+            assert_eq!(
+                RetryReason::Status(StatusCode::SERVICE_UNAVAILABLE),
+                attempt.reason
+            );
+            assert!(attempt.metrics.elapsed(Metric::FullResponse).is_some());
+        }
+        assert!(retries.total >= Duration::from_millis(150));
+        let metrics = res.extensions.remove::<RequestMetrics>().unwrap();
+        assert_eq!(Some(2), metrics.value(Metric::Retries));
+        let first = metrics.elapsed(Metric::FirstAttempt).unwrap();
+        assert!(first < metrics.elapsed(Metric::EventualSuccess).unwrap());
+
+        // Timeouts are retried as well
+        let (mut res, body) = rt.block_on(c.request_fully(get(slow_port))).unwrap();
+        assert_eq!(&b"ok"[..], &body[..]);
+        let retries = res.extensions.remove::<Retries>().unwrap();
+        match retries.attempts[0].reason {
+            RetryReason::Error(kind, _) => assert_eq!(ErrorKind::Timeout, kind),
+            ref other => panic!("Expected a timeout, got {:?}", other),
+        }
+
+        // Out of attempts, the last response is returned as it is
+        let mut once = options;
+        once.retry.max_attempts = 2;
+        let c = Client::new_with_options(collector.handle(), once);
+        let (mut res, body) = rt.block_on(c.request_fully(get(port))).unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status);
+        assert_eq!(&b"busy"[..], &body[..]);
+        assert_eq!(
+            1,
+            res.extensions.remove::<Retries>().unwrap().attempts.len()
+        );
+        let metrics = res.extensions.remove::<RequestMetrics>().unwrap();
+        assert!(metrics.elapsed(Metric::EventualSuccess).is_none());
+
+        collector.process_outstanding();
+        assert_eq!(Some(3), collector.snapshot(&Metric::Requests).count());
+        assert_eq!(Some(4), collector.snapshot(&Metric::Retries).count());
+        assert_eq!(Some(3), collector.snapshot(&Metric::FirstAttempt).count());
+        assert_eq!(
+            Some(2),
+            collector.snapshot(&Metric::EventualSuccess).count()
+        );
+        // Attempts that were retried aren't counted as failures
+        let timeouts = collector.snapshot(&Metric::Failure(ErrorKind::Timeout));
+        assert_eq!(0, timeouts.count().unwrap_or(0));
+    }
+
+    #[test]
+    fn compression_test() {
+        use crate::body::BodyStats;
+        use crate::compression::{
+            BodyRepresentation, CompressionOptions, ContentEncoding, Decompression,
+        };
+        use crate::context::RequestMetrics;
+        use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+        use std::collections::HashMap;
+        use std::io::Write;
+        use std::sync::mpsc;
+        let data = "hello ".repeat(1000).into_bytes();
+        let gzip = {
+            let mut e = GzEncoder::new(Vec::new(), Compression::default());
+            e.write_all(&data).unwrap();
+            e.finish().unwrap()
+        };
+        let zlib = {
+            let mut e = ZlibEncoder::new(Vec::new(), Compression::default());
+            e.write_all(&data).unwrap();
+            e.finish().unwrap()
+        };
+        let raw_deflate = {
+            let mut e = DeflateEncoder::new(Vec::new(), Compression::default());
+            e.write_all(&data).unwrap();
+            e.finish().unwrap()
+        };
+        let zstd = zstd::encode_all(&data[..], 0).unwrap();
+        // A single uncompressed meta-block, as there's no brotli encoder at hand
+        let brotli = [&[0x40, 0x00, 0x10][..], b"hello", &[0x03]].concat();
+        let mut corrupt = gzip.clone();
+        corrupt[20] ^= 0xff;
+        let bodies: HashMap<&str, (&str, Vec<u8>)> = vec![
+            ("/gzip", ("gzip", gzip.clone())),
+            ("/zlib", ("deflate", zlib)),
+            ("/raw", ("deflate", raw_deflate)),
+            ("/br", ("br", brotli)),
+            ("/zstd", ("zstd", zstd.clone())),
+            ("/corrupt", ("gzip", corrupt)),
+        ]
+        .into_iter()
+        .collect();
+        let (accepted, accept_encodings) = mpsc::channel();
+        let port = spawn_server(move |req| {
+            let accept = req.header("accept-encoding").map(str::to_string);
+            accepted.send(accept).unwrap();
+            let (encoding, ref body) = bodies[req.path.as_str()];
+            response(200, &format!("Content-Encoding: {}\r\n", encoding), body)
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let options = ClientOptions {
+            compression: CompressionOptions {
+                encodings: ContentEncoding::all().to_vec(),
+                body: BodyRepresentation::Decoded,
+            },
+            ..ClientOptions::default()
+        };
+        let c = Client::new_with_options(collector.handle(), options.clone());
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let get = |path: &str| {
+            Request::builder()
+                .uri(format!("http://127.0.0.1:{}{}", port, path))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let (res, body) = rt.block_on(c.request_fully(get("/gzip"))).unwrap();
+        assert_eq!(
+            Some("gzip, deflate, br, zstd".to_string()),
+            accept_encodings.recv().unwrap()
+        );
+        assert_eq!(data, &body[..]);
+        let decompression = res.extensions.get::<Decompression>().unwrap();
+        assert_eq!(ContentEncoding::Gzip, decompression.encoding);
+        assert_eq!(gzip.len() as u64, decompression.encoded_len);
+        assert_eq!(data.len() as u64, decompression.decoded_len);
+        let stats = res.extensions.get::<BodyStats>().unwrap();
+        assert_eq!(gzip.len() as u64, stats.len);
+        let metrics = res.extensions.get::<RequestMetrics>().unwrap();
+        assert_eq!(
+            Some(data.len() as u64),
+            metrics.value(Metric::DecodedBodyLen)
+        );
+        assert!(metrics.elapsed(Metric::DecompressTime).is_some());
+
+        // deflate comes zlib wrapped or raw
+        for path in &["/zlib", "/raw", "/zstd"] {
+            let (_, body) = rt.block_on(c.request_fully(get(path))).unwrap();
+            assert_eq!(data, &body[..], "{}", path);
+        }
+        let (_, body) = rt.block_on(c.request_fully(get("/br"))).unwrap();
+        assert_eq!(&b"hello"[..], &body[..]);
+        let err = rt.block_on(c.request_fully(get("/corrupt"))).unwrap_err();
+        assert_eq!(ErrorKind::Decompression, err.kind());
+
+        // The body as sent, though still decoded to be measured
+        let mut encoded = options;
+        encoded.compression.body = BodyRepresentation::Encoded;
+        encoded.compression.encodings = vec![ContentEncoding::Gzip];
+        let c = Client::new_with_options(collector.handle(), encoded);
+        let (res, body) = rt.block_on(c.request_fully(get("/gzip"))).unwrap();
+        assert_eq!(gzip, &body[..]);
+        let decompression = res.extensions.get::<Decompression>().unwrap();
+        assert_eq!(data.len() as u64, decompression.decoded_len);
+        // Encodings that weren't asked for are left alone
+        let (res, body) = rt.block_on(c.request_fully(get("/zstd"))).unwrap();
+        assert_eq!(zstd, &body[..]);
+        assert!(res.extensions.get::<Decompression>().is_none());
+        // Requests that ask for encodings themselves keep their header
+        let mut req = get("/gzip");
+        req.headers_mut()
+            .insert(ACCEPT_ENCODING, HeaderValue::from_static("identity"));
+        rt.block_on(c.request_fully(req)).unwrap();
+        let accepted: Vec<_> = accept_encodings.try_iter().collect();
+        assert_eq!(Some(&Some("identity".to_string())), accepted.last());
+
+        collector.process_outstanding();
+        assert_eq!(
+            Some(1),
+            collector
+                .snapshot(&Metric::Failure(ErrorKind::Decompression))
+                .count()
+        );
+    }
+
+    #[test]
+    fn http2_prior_knowledge_test() {
+        use hyper::server::conn::Http;
+        use hyper::service::service_fn;
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        // Plain-text server that only speaks HTTP/2
+        let mut listener = rt
+            .block_on(tokio::net::TcpListener::bind(std::net::SocketAddr::from((
+                [127, 0, 0, 1],
+                0,
+            ))))
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        rt.spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|_| async {
+                Ok::<_, hyper::Error>(hyper::Response::new(Body::from("ok")))
+            });
+            let _ = Http::new()
+                .http2_only(true)
+                .serve_connection(stream, service)
+                .await;
+        });
+
+        let mut collector = Collector::new();
+        Client::configure_collector_defaults(&mut collector);
+        let options = ClientOptions {
+            http_version: HttpVersion::Http2PriorKnowledge,
+            ..ClientOptions::default()
+        };
+        let c = Client::new_with_options(collector.handle(), options);
+        let req = Request::builder()
+            .uri(format!("http://{}/", addr))
+            .body(Body::empty())
+            .unwrap();
+        let (res, body) = rt.block_on(c.request_fully(req)).unwrap();
+        assert_eq!(hyper::Version::HTTP_2, res.version);
+        assert_eq!(&b"ok"[..], &body[..]);
+        collector.process_outstanding();
+        assert_eq!(Some(1), collector.snapshot(&Metric::Http2Settings).count());
+    }
+
+    #[test]
+    fn http3_zero_rtt_test() {
+        use crate::tls::Certificate;
+        use hyper::Method;
+        use quinn::crypto::rustls::QuicServerConfig;
+        use quinn::rustls;
+        use std::convert::TryFrom;
+        use std::net::SocketAddr;
+        use std::sync::Arc;
+        // HTTP/3 servers on their own runtime, accepting early data.  Each has its own session
+        // ticket keys, so a ticket from one makes the other reject early data.
+        let server_rt = quic_tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["127.0.0.1".to_string()]).unwrap();
+        let cert_der = cert.cert.der().clone();
+        let server = || {
+            let key = rustls::pki_types::PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+            let provider = Arc::new(rustls::crypto::ring::default_provider());
+            let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+                .with_protocol_versions(&[&rustls::version::TLS13])
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(vec![cert_der.clone()], key.into())
+                .unwrap();
+            tls.alpn_protocols = vec![b"h3".to_vec()];
+            tls.max_early_data_size = u32::MAX;
+            let tls = QuicServerConfig::try_from(tls).unwrap();
+            let server_config = quinn::ServerConfig::with_crypto(Arc::new(tls));
+            let endpoint = {
+                let _guard = server_rt.enter();
+                let addr = std::net::SocketAddr::from(([127, 0, 0, 1], 0));
+                quinn::Endpoint::server(server_config, addr).unwrap()
+            };
+            let addr = endpoint.local_addr().unwrap();
+            server_rt.spawn(async move {
+                while let Some(incoming) = endpoint.accept().await {
+                    quic_tokio::spawn(async move {
+                        let conn = match incoming.accept().unwrap().into_0rtt() {
+                            Ok((conn, _)) => conn,
+                            Err(connecting) => connecting.await.unwrap(),
+                        };
+                        let conn = h3_quinn::Connection::new(conn);
+                        let mut conn = h3::server::Connection::<_, quic_bytes::Bytes>::new(conn)
+                            .await
+                            .unwrap();
+                        while let Ok(Some(resolver)) = conn.accept().await {
+                            let (_, mut stream) = resolver.resolve_request().await.unwrap();
+                            stream
+                                .send_response(quic_http::Response::new(()))
+                                .await
+                                .unwrap();
+                            stream.send_data("ok".into()).await.unwrap();
+                            stream.finish().await.unwrap();
+                        }
+                    });
+                }
+            });
+            addr
+        };
+        let (addr, other_addr) = (server(), server());
+
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        // Whether each request's connection had its early data accepted, with the handshake and
+        // ZeroRtt counts
+        let mut zero_rtt = |early_data: bool, method: Method, addrs: &[SocketAddr]| {
+            let mut collector = Collector::new();
+            Client::configure_collector_defaults(&mut collector);
+            let options = ClientOptions {
+                http_version: HttpVersion::Http3,
+                tls: TlsOptions {
+                    roots: Some(vec![Certificate(cert_der.to_vec())]),
+                    resume: early_data,
+                    early_data,
+                    ..TlsOptions::default()
+                },
+                ..ClientOptions::default()
+            };
+            let c = Client::new_with_options(collector.handle(), options);
+            let zero_rtt: Vec<bool> = addrs
+                .iter()
+                .map(|addr| {
+                    let req = Request::builder()
+                        .method(method.clone())
+                        .uri(format!("https://{}/", addr))
+                        .body(Body::empty())
+                        .unwrap();
+                    let (res, body) = rt.block_on(c.request_fully(req)).unwrap();
+                    assert_eq!(Version::HTTP_3, res.version);
+                    assert_eq!(&b"ok"[..], &body[..]);
+                    let info = res.extensions.get::<ConnectionInfo>().unwrap();
+                    assert_eq!(info.is_zero_rtt(), info.tls().unwrap().resumed);
+                    info.is_zero_rtt()
+                })
+                .collect();
+            collector.process_outstanding();
+            let count = |m: Metric| collector.snapshot(&m).count().unwrap_or(0);
+            (
+                zero_rtt,
+                count(Metric::QuicHandshake),
+                count(Metric::ZeroRtt),
+            )
+        };
+        // The second connection resumes the first one's session and sends its request as early
+        // data, only when asked to and for idempotent methods
+        let both = [addr, addr];
+        assert_eq!(
+            (vec![false, true], 2, 1),
+            zero_rtt(true, Method::GET, &both)
+        );
+        assert_eq!(
+            (vec![false, false], 2, 0),
+            zero_rtt(false, Method::GET, &both)
+        );
+        assert_eq!(
+            (vec![false, false], 2, 0),
+            zero_rtt(true, Method::POST, &both)
+        );
+        // A request whose early data was rejected is sent again on a new connection
+        let rejected = zero_rtt(true, Method::GET, &[addr, other_addr]);
+        assert_eq!((vec![false, false], 3, 0), rejected);
+    }
+
+    #[test]
+    fn quic_runtime_error_test() {
+        let collector = Collector::new();
+        let options = ClientOptions {
+            http_version: HttpVersion::Http3,
+            ..ClientOptions::default()
+        };
+        let mut c = Client::new_with_options(collector.handle(), options);
+        // As if the OS refused the runtime its threads
+        let refused = io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "Resource temporarily unavailable",
+        );
+        c.quic = Some(Err(refused));
+        let req = Request::builder()
+            .uri("https://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let err = rt.block_on(c.request_fully(req)).unwrap_err();
+        assert_eq!(ErrorKind::Other, err.kind());
+        assert_eq!(
+            "could not start QUIC runtime: Resource temporarily unavailable",
+            err.to_string()
+        );
+    }
 }
@@ -0,0 +1,365 @@
+use brotli_decompressor::DecompressorWriter;
+use flate2::write::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Content codings `Client` can decode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContentEncoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl ContentEncoding {
+    pub fn all() -> &'static [ContentEncoding] {
+        &[
+            ContentEncoding::Gzip,
+            ContentEncoding::Deflate,
+            ContentEncoding::Brotli,
+            ContentEncoding::Zstd,
+        ]
+    }
+
+    /// Name in `Accept-Encoding` and `Content-Encoding` headers
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+            ContentEncoding::Brotli => "br",
+            ContentEncoding::Zstd => "zstd",
+        }
+    }
+}
+
+impl fmt::Display for ContentEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown content encoding {0}, expected gzip, deflate, br or zstd")]
+pub struct ContentEncodingParseError(String);
+
+impl FromStr for ContentEncoding {
+    type Err = ContentEncodingParseError;
+
+    fn from_str(s: &str) -> Result<ContentEncoding, ContentEncodingParseError> {
+        match s {
+            "gzip" | "x-gzip" => Ok(ContentEncoding::Gzip),
+            "deflate" => Ok(ContentEncoding::Deflate),
+            "br" => Ok(ContentEncoding::Brotli),
+            "zstd" => Ok(ContentEncoding::Zstd),
+            _ => Err(ContentEncodingParseError(s.to_string())),
+        }
+    }
+}
+
+/// Which form of a decoded body is handed on to the caller
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BodyRepresentation {
+    /// After decoding
+    #[default]
+    Decoded,
+    /// As sent by the server
+    Encoded,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown body representation {0}, expected decoded or encoded")]
+pub struct BodyRepresentationParseError(String);
+
+impl FromStr for BodyRepresentation {
+    type Err = BodyRepresentationParseError;
+
+    fn from_str(s: &str) -> Result<BodyRepresentation, BodyRepresentationParseError> {
+        match s {
+            "decoded" => Ok(BodyRepresentation::Decoded),
+            "encoded" => Ok(BodyRepresentation::Encoded),
+            _ => Err(BodyRepresentationParseError(s.to_string())),
+        }
+    }
+}
+
+/// Response decompression, off unless there are `encodings`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompressionOptions {
+    /// Encodings offered in `Accept-Encoding`, unless the request sets it, and decoded when a
+    /// response uses one of them
+    pub encodings: Vec<ContentEncoding>,
+    /// What `Client::request_streaming` and `Client::request_fully` return of decoded bodies
+    pub body: BodyRepresentation,
+}
+
+impl CompressionOptions {
+    /// Value for `Accept-Encoding`, `None` when decompression is off
+    pub(crate) fn accept_encoding(&self) -> Option<String> {
+        if self.encodings.is_empty() {
+            return None;
+        }
+        let names: Vec<&str> = self.encodings.iter().map(|e| e.name()).collect();
+        Some(names.join(", "))
+    }
+
+    /// The encoding a response with this `Content-Encoding` is decoded from, if it's one of
+    /// `encodings`.  Stacked encodings aren't decoded.
+    pub(crate) fn decodes(&self, content_encoding: &str) -> Option<ContentEncoding> {
+        let encoding = content_encoding.trim().to_ascii_lowercase().parse().ok()?;
+        Some(encoding).filter(|e| self.encodings.contains(e))
+    }
+}
+
+/// How a response body was decompressed, attached to responses that were
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decompression {
+    pub encoding: ContentEncoding,
+    /// Body length as sent
+    pub encoded_len: u64,
+    pub decoded_len: u64,
+    /// Time spent decoding, as the body arrived
+    pub time: Duration,
+}
+
+/// Decodes a body chunk by chunk
+pub(crate) struct Decoder {
+    writer: Box<dyn DecodeWrite + Send>,
+    stats: Decompression,
+    /// `deflate` is meant to be zlib wrapped, but some servers send it raw.  The first chunk
+    /// tells which.
+    raw_deflate_checked: bool,
+}
+
+/// A decompressing writer that collects its output in a `Vec<u8>`
+trait DecodeWrite: Write {
+    fn output(&mut self) -> &mut Vec<u8>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+impl DecodeWrite for GzDecoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+}
+
+impl DecodeWrite for ZlibDecoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+}
+
+impl DecodeWrite for DeflateDecoder<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.try_finish()
+    }
+}
+
+impl DecodeWrite for DecompressorWriter<Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.close()
+    }
+}
+
+impl DecodeWrite for zstd::stream::write::Decoder<'static, Vec<u8>> {
+    fn output(&mut self) -> &mut Vec<u8> {
+        self.get_mut()
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.flush()
+    }
+}
+
+/// Size of the brotli decoder's buffer
+const BROTLI_BUFFER: usize = 4096;
+
+impl Decoder {
+    pub(crate) fn new(encoding: ContentEncoding) -> io::Result<Decoder> {
+        let writer: Box<dyn DecodeWrite + Send> = match encoding {
+            ContentEncoding::Gzip => Box::new(GzDecoder::new(Vec::new())),
+            ContentEncoding::Deflate => Box::new(ZlibDecoder::new(Vec::new())),
+            ContentEncoding::Brotli => Box::new(DecompressorWriter::new(Vec::new(), BROTLI_BUFFER)),
+            ContentEncoding::Zstd => Box::new(zstd::stream::write::Decoder::new(Vec::new())?),
+        };
+        Ok(Decoder {
+            writer,
+            stats: Decompression {
+                encoding,
+                encoded_len: 0,
+                decoded_len: 0,
+                time: Duration::default(),
+            },
+            raw_deflate_checked: encoding != ContentEncoding::Deflate,
+        })
+    }
+
+    /// Decode `chunk`, handing what it decodes to `out`
+    pub(crate) fn decode<F: FnMut(&[u8])>(&mut self, chunk: &[u8], out: F) -> io::Result<()> {
+        if !self.raw_deflate_checked && !chunk.is_empty() {
+            self.raw_deflate_checked = true;
+            if !is_zlib_header(chunk) {
+                self.writer = Box::new(DeflateDecoder::new(Vec::new()));
+            }
+        }
+        let started = Instant::now();
+        self.stats.encoded_len += chunk.len() as u64;
+        let res = self.writer.write_all(chunk);
+        self.stats.time += started.elapsed();
+        res?;
+        self.drain(out);
+        Ok(())
+    }
+
+    /// Decode whatever is left once the body has been read
+    pub(crate) fn finish<F: FnMut(&[u8])>(mut self, out: F) -> io::Result<Decompression> {
+        let started = Instant::now();
+        let res = self.writer.finish();
+        self.stats.time += started.elapsed();
+        res?;
+        self.drain(out);
+        Ok(self.stats)
+    }
+
+    fn drain<F: FnMut(&[u8])>(&mut self, mut out: F) {
+        let output = self.writer.output();
+        if !output.is_empty() {
+            self.stats.decoded_len += output.len() as u64;
+            out(output);
+            output.clear();
+        }
+    }
+}
+
+/// Whether a deflate body starts with a zlib header, rather than raw deflate data
+fn is_zlib_header(data: &[u8]) -> bool {
+    match data {
+        [cmf, flg, ..] => cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0,
+        // Too short to tell, and most servers do send zlib
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::{DeflateEncoder, GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    #[test]
+    fn parse_test() {
+        for encoding in ContentEncoding::all() {
+            assert_eq!(*encoding, encoding.name().parse().unwrap());
+        }
+        assert_eq!(ContentEncoding::Gzip, "x-gzip".parse().unwrap());
+        assert!("identity".parse::<ContentEncoding>().is_err());
+        assert!("GZIP".parse::<ContentEncoding>().is_err());
+
+        let options = CompressionOptions {
+            encodings: vec![ContentEncoding::Gzip, ContentEncoding::Brotli],
+            ..CompressionOptions::default()
+        };
+        assert_eq!(Some("gzip, br".to_string()), options.accept_encoding());
+        assert_eq!(None, CompressionOptions::default().accept_encoding());
+        let cases = [
+            ("gzip", Some(ContentEncoding::Gzip)),
+            (" GZip ", Some(ContentEncoding::Gzip)),
+            ("x-gzip", Some(ContentEncoding::Gzip)),
+            ("br", Some(ContentEncoding::Brotli)),
+            // Not asked for
+            ("zstd", None),
+            ("identity", None),
+            // Stacked
+            ("gzip, br", None),
+            ("", None),
+        ];
+        for (header, expected) in cases.iter() {
+            assert_eq!(options.decodes(header), *expected, "{}", header);
+        }
+    }
+
+    #[test]
+    fn decoder_test() {
+        let data = "hello ".repeat(1000).into_bytes();
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        gzip.write_all(&data).unwrap();
+        let mut zlib = ZlibEncoder::new(Vec::new(), Compression::default());
+        zlib.write_all(&data).unwrap();
+        let mut raw = DeflateEncoder::new(Vec::new(), Compression::default());
+        raw.write_all(&data).unwrap();
+        let cases = vec![
+            (ContentEncoding::Gzip, gzip.finish().unwrap()),
+            (ContentEncoding::Deflate, zlib.finish().unwrap()),
+            (ContentEncoding::Deflate, raw.finish().unwrap()),
+            (
+                ContentEncoding::Zstd,
+                zstd::encode_all(&data[..], 0).unwrap(),
+            ),
+        ];
+        for (encoding, encoded) in cases {
+            // Fed a few bytes at a time, as a body arrives
+            let mut decoder = Decoder::new(encoding).unwrap();
+            let mut decoded = Vec::new();
+            for chunk in encoded.chunks(7) {
+                decoder
+                    .decode(chunk, |out| decoded.extend_from_slice(out))
+                    .unwrap();
+            }
+            let stats = decoder
+                .finish(|out| decoded.extend_from_slice(out))
+                .unwrap();
+            assert_eq!(data, decoded, "{}", encoding);
+            assert_eq!(encoding, stats.encoding);
+            assert_eq!(encoded.len() as u64, stats.encoded_len);
+            assert_eq!(data.len() as u64, stats.decoded_len);
+        }
+
+        // A single uncompressed meta-block, as there's no brotli encoder at hand
+        let brotli = [&[0x40, 0x00, 0x10][..], b"hello", &[0x03]].concat();
+        let mut decoder = Decoder::new(ContentEncoding::Brotli).unwrap();
+        let mut decoded = Vec::new();
+        decoder
+            .decode(&brotli, |out| decoded.extend_from_slice(out))
+            .unwrap();
+        decoder
+            .finish(|out| decoded.extend_from_slice(out))
+            .unwrap();
+        assert_eq!(&b"hello"[..], &decoded[..]);
+
+        let mut decoder = Decoder::new(ContentEncoding::Gzip).unwrap();
+        assert!(decoder.decode(b"not gzip at all", |_| ()).is_err());
+    }
+
+    #[test]
+    fn zlib_header_test() {
+        // The usual headers for each compression level
+        for header in &[[0x78, 0x01], [0x78, 0x5e], [0x78, 0x9c], [0x78, 0xda]] {
+            assert!(is_zlib_header(header), "{:?}", header);
+        }
+        // Not deflate, and a bad check value
+        assert!(!is_zlib_header(&[0x79, 0x9c]));
+        assert!(!is_zlib_header(&[0x78, 0x9d]));
+        // Raw deflate, starting with a fixed Huffman block
+        assert!(!is_zlib_header(&[0xcb, 0x48]));
+        assert!(is_zlib_header(&[0x78]));
+    }
+}
@@ -1,5 +1,10 @@
 use crate::client::Metric;
-use crate::dns::TracingResolver;
+use crate::context;
+use crate::dns::{self, DnsError, TracingResolver};
+use crate::events::{self, Event};
+use crate::proxy::{self, ProxyKind, ProxyOptions, Target};
+use crate::socket::SocketOptions;
+use crate::timeouts::{timeout, Phase, Timeouts};
 use crate::FutureResponse;
 use futures::prelude::*;
 use hyper::client::connect::dns::Name;
@@ -10,6 +15,7 @@ use std::net::SocketAddr;
 use std::str::FromStr;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tracer_metrics::{CollectorHandle, Stopwatch};
 
@@ -17,7 +23,10 @@ use tracer_metrics::{CollectorHandle, Stopwatch};
 pub struct TracingConnector {
     resolver: TracingResolver,
     collector: CollectorHandle<Metric>,
-    nodelay: bool,
+    socket: SocketOptions,
+    timeouts: Timeouts,
+    proxy: ProxyOptions,
+    forward_plain_http: bool,
 }
 
 impl TracingConnector {
@@ -26,12 +35,40 @@ impl TracingConnector {
         TracingConnector {
             resolver,
             collector,
-            nodelay: false,
+            socket: SocketOptions {
+                nodelay: false,
+                ..SocketOptions::default()
+            },
+            timeouts: Timeouts::default(),
+            proxy: ProxyOptions::default(),
+            forward_plain_http: true,
         }
     }
 
     pub fn set_nodelay(&mut self, nodelay: bool) {
-        self.nodelay = nodelay;
+        self.socket.nodelay = nodelay;
+    }
+
+    /// Set the source address and options for new TCP connections, to the target or a proxy
+    pub fn set_socket_options(&mut self, socket: SocketOptions) {
+        self.socket = socket;
+    }
+
+    /// Set the timeouts used for DNS resolution and TCP connect
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Connect through proxies.  Plain HTTP requests are forwarded to HTTP proxies unless
+    /// `forward_plain_http` is false, in which case they are tunnelled like HTTPS.
+    pub fn set_proxy(&mut self, proxy: ProxyOptions, forward_plain_http: bool) {
+        self.proxy = proxy;
+        self.forward_plain_http = forward_plain_http;
+    }
+
+    /// Whether the connection for `dst` goes to an HTTP proxy that requests are forwarded to
+    pub fn is_forwarded(&self, dst: &Uri) -> bool {
+        self.forward_plain_http && self.proxy.forwarding_proxy(dst).is_some()
     }
 }
 
@@ -53,32 +90,78 @@ impl Service<Uri> for TracingConnector {
             Some(host) => host.to_string(),
         };
         let is_https = dst.scheme().filter(|s| *s == "https").is_some();
-        let port = dst
-            .port_u16()
-            .unwrap_or_else(|| if is_https { 443 } else { 80 });
-        let nodelay = self.nodelay;
+        let port = dst.port_u16().unwrap_or(if is_https { 443 } else { 80 });
+        let proxy = self.proxy.for_uri(&dst).cloned();
+        let forwarded = self.is_forwarded(&dst);
+        let socket = self.socket.clone();
+        let dns_timeout = self.timeouts.dns;
+        let connect_timeout = self.timeouts.connect;
         let collector = self.collector.clone();
         let mut resolver = self.resolver.clone();
         async move {
-            let name = Name::from_str(&host).map_err(|e| {
-                io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid Host: {}", e))
-            })?;
-            let addrs = resolver.call(name).await?;
-            let addr = addrs.map(|a| SocketAddr::new(a, port)).next();
-            let addr = if let Some(a) = addr {
-                a
-            } else {
-                return Err(io::Error::new(
-                    io::ErrorKind::InvalidInput,
-                    "Did not resolve an address",
-                ));
+            let proxy = match proxy {
+                Some(proxy) => proxy,
+                None => {
+                    let addr = resolve(&mut resolver, &host, port, dns_timeout, &socket).await?;
+                    events::emit(Event::ConnectionStarted);
+                    let stopwatch = Stopwatch::new();
+                    let stream =
+                        timeout(Phase::Connect, connect_timeout, socket.connect(&addr)).await??;
+                    context::send(&collector, stopwatch.elapsed(Metric::Connection));
+                    events::emit(Event::Connected);
+                    return Ok(stream);
+                }
+            };
+
+            let addr =
+                resolve(&mut resolver, &proxy.host, proxy.port, dns_timeout, &socket).await?;
+            events::emit(Event::ConnectionStarted);
+            let stopwatch = Stopwatch::new();
+            let mut stream =
+                timeout(Phase::Connect, connect_timeout, socket.connect(&addr)).await??;
+            context::send(&collector, stopwatch.elapsed(Metric::ProxyConnect));
+            if forwarded {
+                events::emit(Event::Connected);
+                return Ok(stream);
+            }
+            let target = match dns::ip_literal(&host) {
+                Some(ip) => Target::Addr(SocketAddr::new(ip, port)),
+                None if proxy.kind == ProxyKind::Socks5 => {
+                    // The proxy connects onwards, so its address family doesn't matter
+                    let any = SocketOptions::default();
+                    Target::Addr(resolve(&mut resolver, &host, port, dns_timeout, &any).await?)
+                }
+                None => Target::Name(host, port),
             };
             let stopwatch = Stopwatch::new();
-            let stream = TcpStream::connect(&addr).await?;
-            collector.send(stopwatch.elapsed(Metric::Connection));
-            stream.set_nodelay(nodelay)?;
+            let tunnel = proxy::tunnel(&mut stream, &proxy, target);
+            timeout(Phase::Connect, connect_timeout, tunnel).await??;
+            context::send(&collector, stopwatch.elapsed(Metric::ProxyHandshake));
+            events::emit(Event::Connected);
             Ok(stream)
         }
         .boxed()
     }
 }
+
+async fn resolve(
+    resolver: &mut TracingResolver,
+    host: &str,
+    port: u16,
+    dns_timeout: Option<Duration>,
+    socket: &SocketOptions,
+) -> io::Result<SocketAddr> {
+    let name = Name::from_str(host)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid Host: {}", e)))?;
+    let addrs = timeout(Phase::Dns, dns_timeout, resolver.call(name)).await??;
+    // Prefer an address the source address can reach, connecting reports the mismatch otherwise
+    let addrs: Vec<SocketAddr> = addrs.map(|a| SocketAddr::new(a, port)).collect();
+    let preferred = addrs.iter().find(|a| socket.can_reach(a));
+    match preferred.or_else(|| addrs.first()).copied() {
+        Some(addr) => Ok(addr),
+        None => {
+            let e = io::Error::new(io::ErrorKind::InvalidInput, "Did not resolve an address");
+            Err(DnsError::new(host, e).into())
+        }
+    }
+}
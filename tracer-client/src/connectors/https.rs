@@ -1,28 +1,48 @@
 use super::http::TracingConnector;
+use super::stream::{CountingIo, TracingStream, Transport};
+#[cfg(unix)]
+use super::unix::TracingUnixConnector;
 use crate::client::Metric;
+use crate::context;
+use crate::dns;
+use crate::error::TlsHandshakeError;
+use crate::events::{self, Event};
+use crate::proxy::ProxyOptions;
+use crate::socket::SocketOptions;
+use crate::timeouts::{timeout, Phase, Timeouts};
+use crate::tls::{self, IpAddrVerification, NoVerification, TlsOptions};
 use crate::FutureResponse;
 use futures::prelude::*;
 use hyper::service::Service;
 use hyper::Uri;
-use hyper_rustls::HttpsConnector;
 use hyper_rustls::MaybeHttpsStream;
-use rustls::ClientConfig;
+use rustls::{ClientConfig, ClientSessionMemoryCache, NoClientSessionStorage};
 use std::convert::From;
 use std::error::Error;
 use std::io;
+#[cfg(unix)]
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
-use tokio::net::TcpStream;
+use std::time::Instant;
 use tokio_rustls::TlsConnector;
-use tracer_metrics::{CollectorHandle, Stopwatch};
+use tracer_metrics::data::Sample;
+use tracer_metrics::CollectorHandle;
 use webpki::DNSNameRef;
 
 #[derive(Clone)]
 pub struct TracingHttpsConnector {
     http: TracingConnector,
+    /// Connects to this instead of the URL's host when set
+    #[cfg(unix)]
+    unix: Option<TracingUnixConnector>,
     tls_config: Arc<ClientConfig>,
     collector: CollectorHandle<Metric>,
+    timeouts: Timeouts,
+    resume: bool,
+    insecure: bool,
+    server_name: Option<String>,
 }
 
 impl TracingHttpsConnector {
@@ -33,12 +53,96 @@ impl TracingHttpsConnector {
         config
             .root_store
             .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        // Every connection does a full handshake unless resumption is asked for
+        config.set_persistence(Arc::new(NoClientSessionStorage {}));
         TracingHttpsConnector {
             http,
+            #[cfg(unix)]
+            unix: None,
             tls_config: Arc::new(config),
             collector,
+            timeouts: Timeouts::default(),
+            resume: false,
+            insecure: false,
+            server_name: None,
         }
     }
+
+    /// Set the protocols offered via ALPN during the TLS handshake, in order of preference
+    pub fn set_alpn_protocols(&mut self, protocols: Vec<Vec<u8>>) {
+        Arc::make_mut(&mut self.tls_config).set_protocols(&protocols);
+    }
+
+    /// Set the certificates servers are verified against, or turn verification off, and the
+    /// client certificate to present
+    pub fn set_tls_options(&mut self, options: &TlsOptions) {
+        let config = Arc::make_mut(&mut self.tls_config);
+        config.root_store = tls::root_store(options);
+        if options.insecure {
+            config
+                .dangerous()
+                .set_certificate_verifier(Arc::new(NoVerification));
+        }
+        if let Some(ref cert) = options.client_cert {
+            let (chain, key) = tls::client_cert_chain(cert);
+            config.set_single_client_cert(chain, key);
+        }
+        tls::pin_versions_and_suites(config, options);
+        if options.resume {
+            // Connectors are cloned per connection, the cache is shared between all of them
+            config.set_persistence(ClientSessionMemoryCache::new(32));
+        }
+        self.resume = options.resume;
+        self.insecure = options.insecure;
+        self.server_name = options.server_name.clone();
+    }
+
+    /// Connect through proxies, see `TracingConnector::set_proxy`
+    pub fn set_proxy(&mut self, proxy: ProxyOptions, forward_plain_http: bool) {
+        self.http.set_proxy(proxy, forward_plain_http);
+    }
+
+    /// Set the source address and options for new TCP connections
+    pub fn set_socket_options(&mut self, socket: SocketOptions) {
+        self.http.set_socket_options(socket);
+    }
+
+    /// Set the timeouts for connecting, including those used by the underlying `TracingConnector`
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        #[cfg(unix)]
+        {
+            if let Some(ref mut unix) = self.unix {
+                unix.set_timeouts(timeouts.clone());
+            }
+        }
+        self.http.set_timeouts(timeouts.clone());
+        self.timeouts = timeouts;
+    }
+
+    /// Connect to a Unix domain socket for every request, instead of the host in the URL.
+    /// Proxies are not used.
+    #[cfg(unix)]
+    pub fn set_unix_socket<P: Into<PathBuf>>(&mut self, path: P) {
+        let mut unix = TracingUnixConnector::new(path, self.collector.clone());
+        unix.set_timeouts(self.timeouts.clone());
+        self.unix = Some(unix);
+    }
+
+    /// Open the connection underneath TLS, and tell whether it leads to an HTTP proxy that
+    /// requests are forwarded to
+    fn connect(&mut self, dst: &Uri) -> (FutureResponse<Transport, io::Error>, bool) {
+        #[cfg(unix)]
+        {
+            if let Some(ref mut unix) = self.unix {
+                return (
+                    unix.call(dst.clone()).map_ok(Transport::Unix).boxed(),
+                    false,
+                );
+            }
+        }
+        let connecting = self.http.call(dst.clone()).map_ok(Transport::Tcp).boxed();
+        (connecting, self.http.is_forwarded(dst))
+    }
 }
 
 impl From<(TracingConnector, ClientConfig, CollectorHandle<Metric>)> for TracingHttpsConnector {
@@ -47,51 +151,93 @@ impl From<(TracingConnector, ClientConfig, CollectorHandle<Metric>)> for Tracing
     ) -> TracingHttpsConnector {
         TracingHttpsConnector {
             http: args.0,
+            #[cfg(unix)]
+            unix: None,
             tls_config: Arc::new(args.1),
             collector: args.2,
+            timeouts: Timeouts::default(),
+            resume: false,
+            insecure: false,
+            server_name: None,
         }
     }
 }
 
 impl Service<Uri> for TracingHttpsConnector {
-    type Response = <HttpsConnector<TracingConnector> as Service<Uri>>::Response;
-    type Error = <HttpsConnector<TracingConnector> as Service<Uri>>::Error;
-    type Future =
-        FutureResponse<MaybeHttpsStream<TcpStream>, Box<dyn Error + Send + Sync + 'static>>;
+    type Response = TracingStream;
+    type Error = Box<dyn Error + Send + Sync + 'static>;
+    type Future = FutureResponse<Self::Response, Self::Error>;
 
     fn call(&mut self, dst: Uri) -> Self::Future {
         let collector = self.collector.clone();
-        let connecting = self.http.call(dst.clone());
+        let (connecting, forwarded) = self.connect(&dst);
         let cfg = self.tls_config.clone();
+        let tls_timeout = self.timeouts.tls;
+        let resume = self.resume;
+        let insecure = self.insecure;
+        let server_name = self.server_name.clone();
         async move {
             let is_https = dst.scheme().filter(|s| *s == "https").is_some();
-            let tcp = connecting.await?;
+            // Counted from here on, after any proxy tunnel was set up
+            let tcp = CountingIo::new(connecting.await?);
             if !is_https {
-                return Ok(MaybeHttpsStream::Http(tcp));
+                return Ok(TracingStream::new(MaybeHttpsStream::Http(tcp)).proxied(forwarded));
             }
 
-            let connector = TlsConnector::from(cfg);
-            let hostname = if let Some(h) = dst.host() {
-                h.to_string()
-            } else {
-                return Err(io::Error::new(io::ErrorKind::InvalidInput, "Missing Host").into());
+            let server_name = match (server_name, dst.host()) {
+                (Some(name), _) => name,
+                (None, Some(host)) => host.to_string(),
+                (None, None) => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "Missing Host").into())
+                }
+            };
+            // IP addresses aren't sent via SNI, and are matched against the certificate's IP SANs
+            let (cfg, hostname, sni) = match dns::ip_literal(&server_name) {
+                Some(ip) => {
+                    let mut cfg = (*cfg).clone();
+                    cfg.enable_sni = false;
+                    if !insecure {
+                        cfg.dangerous()
+                            .set_certificate_verifier(Arc::new(IpAddrVerification(ip)));
+                    }
+                    (Arc::new(cfg), tls::ip_placeholder_name(ip), None)
+                }
+                None => (cfg, server_name.clone(), Some(server_name)),
             };
 
+            let connector = TlsConnector::from(cfg);
             let dnsname = match DNSNameRef::try_from_ascii_str(&hostname) {
                 Ok(dnsname) => dnsname,
-                Err(e) => {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("invalid dnsname: {}", e),
-                    )
-                    .into())
-                }
+                Err(e) => return Err(io::Error::other(format!("invalid dnsname: {}", e)).into()),
             };
-            let stopwatch = Stopwatch::new();
-            let tls = connector.connect(dnsname, tcp).await?;
-            collector.send(stopwatch.elapsed(Metric::Tls));
-            Ok(MaybeHttpsStream::Https(tls))
+            events::emit(Event::TlsNegotiationStarted);
+            let start = Instant::now();
+            let tls = timeout(Phase::Tls, tls_timeout, connector.connect(dnsname, tcp))
+                .await?
+                .map_err(TlsHandshakeError)?;
+            let elapsed = start.elapsed();
+            events::emit(Event::TlsNegotiated);
+            context::send(&collector, Sample::elapsed(Metric::Tls, elapsed));
+            let stream =
+                TracingStream::new(MaybeHttpsStream::Https(tls)).with_tls_info(sni.as_deref());
+            if let Some(bytes) = stream.info().tls_handshake_bytes() {
+                let sent = Sample::value(Metric::TlsHandshakeBytesSent, bytes.sent);
+                context::send(&collector, sent);
+                let received = Sample::value(Metric::TlsHandshakeBytesReceived, bytes.received);
+                context::send(&collector, received);
+            }
+            if resume {
+                let resumed = stream.info().tls().map(|t| t.resumed).unwrap_or(false);
+                let metric = if resumed {
+                    Metric::TlsResumed
+                } else {
+                    Metric::TlsFull
+                };
+                context::send(&collector, Sample::elapsed(metric, elapsed));
+            }
+            Ok(stream)
         }
+        .inspect_err(|_| events::emit(Event::ConnectionError))
         .boxed()
     }
     fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
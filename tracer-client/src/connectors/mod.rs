@@ -1,5 +1,13 @@
 pub mod http;
 pub mod https;
+pub mod quic;
+pub mod stream;
+#[cfg(unix)]
+pub mod unix;
 
 pub use self::http::TracingConnector;
 pub use self::https::TracingHttpsConnector;
+pub use self::quic::TracingQuicConnector;
+pub use self::stream::{ByteCount, ConnectionInfo, CountingIo, TracingStream, Transport};
+#[cfg(unix)]
+pub use self::unix::TracingUnixConnector;
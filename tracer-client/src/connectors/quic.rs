@@ -0,0 +1,465 @@
+use super::stream::ConnectionInfo;
+use crate::client::Metric;
+use crate::context::{self, RequestContext};
+use crate::dns::{self, DnsError, TracingResolver};
+use crate::events::{self, Event};
+use crate::socket::SocketOptions;
+use crate::timeouts::{timeout, Phase, Timeouts};
+use crate::tls::{self, QuicNoVerification, TlsInfo, TlsOptions, TlsVersion};
+use futures::channel::oneshot;
+use futures::prelude::*;
+use hyper::body::Bytes;
+use hyper::client::connect::dns::Name;
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::http::request;
+use hyper::http::{Method, Request, Response, StatusCode, Version};
+use hyper::service::Service;
+use hyper::Body;
+use quic_bytes::Buf;
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::rustls::{self, client::Resumption};
+use quinn::Endpoint;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tracer_metrics::data::Sample;
+use tracer_metrics::{CollectorHandle, Stopwatch};
+
+type BoxError = Box<dyn Error + Send + Sync>;
+type SendRequest = h3::client::SendRequest<h3_quinn::OpenStreams, quic_bytes::Bytes>;
+
+const ALPN_H3: &[u8] = b"h3";
+
+/// Headers that only apply to HTTP/1.x connections and are not allowed in HTTP/3
+const CONNECTION_HEADERS: &[&str] = &[
+    "connection",
+    "host",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// A QUIC connection with an HTTP/3 session on top of it
+#[derive(Clone)]
+struct QuicConnection {
+    conn: quinn::Connection,
+    send_request: SendRequest,
+    info: ConnectionInfo,
+}
+
+/// A new QUIC connection, possibly still finishing its handshake
+struct Handshake {
+    conn: QuicConnection,
+    /// Server name sent via SNI
+    sni: Option<String>,
+    /// Resolves to whether the server accepted the early data once the handshake is done, when
+    /// the connection was handed out early to send its first request as 0-RTT early data
+    early_data: Option<Pin<Box<dyn Future<Output = bool> + Send>>>,
+}
+
+/// Sends requests over HTTP/3.
+///
+/// quinn and h3 need tokio 1 and rustls 0.23, while hyper 0.13 is tied to tokio 0.2 and rustls
+/// 0.16, so they run on a small runtime of their own, separate from the one driving the `Client`;
+/// results are handed back over channels.  Both TLS stacks are configured from the same
+/// `TlsOptions`.  DNS resolution goes through the same `TracingResolver` as TCP connections, and
+/// the QUIC handshake is recorded as `Metric::QuicHandshake`.
+pub struct TracingQuicConnector {
+    resolver: TracingResolver,
+    collector: CollectorHandle<Metric>,
+    timeouts: Timeouts,
+    keep_alive: bool,
+    /// Send the first request on a resumed connection as 0-RTT early data, if it is idempotent
+    early_data: bool,
+    server_name: Option<String>,
+    socket: SocketOptions,
+    /// Fails when the TLS options rule out TLS 1.3, which QUIC requires
+    tls_config: Result<Arc<rustls::ClientConfig>, rustls::Error>,
+    endpoints: Mutex<HashMap<bool, Endpoint>>,
+    pool: Mutex<HashMap<String, QuicConnection>>,
+    runtime: quic_tokio::runtime::Runtime,
+}
+
+impl TracingQuicConnector {
+    /// Fails when the runtime for QUIC connections can't be started
+    pub fn new(collector: CollectorHandle<Metric>) -> io::Result<TracingQuicConnector> {
+        let runtime = quic_tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("tracer-quic")
+            .enable_all()
+            .build()?;
+        Ok(TracingQuicConnector {
+            resolver: TracingResolver::new(collector.clone()),
+            collector,
+            timeouts: Timeouts::default(),
+            keep_alive: false,
+            early_data: false,
+            server_name: None,
+            socket: SocketOptions::default(),
+            tls_config: tls_config(&TlsOptions::default()).map(Arc::new),
+            endpoints: Mutex::new(HashMap::new()),
+            pool: Mutex::new(HashMap::new()),
+            runtime,
+        })
+    }
+
+    /// Set the timeouts used for DNS resolution and the QUIC handshake
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+
+    /// Keep connections open and reuse them for later requests to the same host
+    pub fn set_keep_alive(&mut self, keep_alive: bool) {
+        self.keep_alive = keep_alive;
+    }
+
+    /// Set the certificates servers are verified against, or turn verification off, the client
+    /// certificate to present, and whether sessions are resumed and early data sent
+    pub fn set_tls_options(&mut self, options: &TlsOptions) {
+        self.tls_config = tls_config(options).map(Arc::new);
+        self.server_name = options.server_name.clone();
+        self.early_data = options.resume && options.early_data;
+    }
+
+    /// Set the source address and port UDP endpoints are bound to, the other options only
+    /// apply to TCP
+    pub fn set_socket_options(&mut self, socket: SocketOptions) {
+        self.socket = socket;
+        self.endpoints.lock().unwrap().clear();
+    }
+
+    /// Send a request, returning once the response headers have arrived
+    pub async fn request(&self, req: Request<Body>) -> Result<Response<Body>, BoxError> {
+        let (parts, body) = req.into_parts();
+        let (host, port) = match (parts.uri.scheme_str(), parts.uri.host()) {
+            (Some("https"), Some(host)) => (host.to_string(), parts.uri.port_u16().unwrap_or(443)),
+            _ => {
+                let e = io::Error::new(io::ErrorKind::InvalidInput, "HTTP/3 needs an https URL");
+                return Err(e.into());
+            }
+        };
+        let body = hyper::body::to_bytes(body).await?;
+        let send = |conn: &QuicConnection| -> Result<_, BoxError> {
+            Ok(self.spawn(exchange(
+                conn.send_request.clone(),
+                convert_request(&parts)?,
+                body.clone(),
+                context::current(),
+            )))
+        };
+
+        let key = format!("{}:{}", host, port);
+        let (conn, (head, body)) = match self.pooled(&key) {
+            Some(conn) => {
+                let exchanged = send(&conn)?.await?;
+                (conn, exchanged)
+            }
+            None => {
+                // A replayed early data request must do no more harm than a repeated one
+                let early_data = self.early_data && is_idempotent(&parts.method);
+                let mut handshake = self.connect(host.clone(), port, early_data).await?;
+                let mut exchanged = send(&handshake.conn)?;
+                let zero_rtt = match handshake.early_data.take() {
+                    Some(accepted) => {
+                        let accepted = self.spawn(accepted.map(Ok)).await?;
+                        if !accepted {
+                            // Rejected early data never reached the server, and the HTTP/3
+                            // session doesn't survive its streams being rejected
+                            handshake
+                                .conn
+                                .conn
+                                .close(0u32.into(), b"early data rejected");
+                            handshake = self.connect(host, port, false).await?;
+                            exchanged = send(&handshake.conn)?;
+                        }
+                        accepted
+                    }
+                    None => false,
+                };
+                let Handshake { mut conn, sni, .. } = handshake;
+                conn.info.set_zero_rtt(zero_rtt);
+                // Early data can only be sent when resuming a session
+                conn.info
+                    .set_tls(TlsInfo::from_quic(&conn.conn, sni, zero_rtt));
+                let exchanged = exchanged.await?;
+                if self.keep_alive {
+                    self.pool.lock().unwrap().insert(key, conn.clone());
+                }
+                (conn, exchanged)
+            }
+        };
+        let mut res = Response::new(body);
+        *res.status_mut() = StatusCode::from_u16(head.status.as_u16())?;
+        *res.version_mut() = Version::HTTP_3;
+        for (name, value) in head.headers.iter() {
+            res.headers_mut().append(
+                HeaderName::from_bytes(name.as_str().as_bytes())?,
+                HeaderValue::from_bytes(value.as_bytes())?,
+            );
+        }
+        res.extensions_mut().insert(conn.info);
+        Ok(res)
+    }
+
+    fn pooled(&self, key: &str) -> Option<QuicConnection> {
+        if !self.keep_alive {
+            return None;
+        }
+        let mut pool = self.pool.lock().unwrap();
+        match pool.get(key) {
+            Some(conn) if conn.conn.close_reason().is_none() => Some(conn.clone()),
+            Some(_) => {
+                pool.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn connect(
+        &self,
+        host: String,
+        port: u16,
+        early_data: bool,
+    ) -> Result<Handshake, BoxError> {
+        let name = Name::from_str(&host).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid Host: {}", e))
+        })?;
+        let mut resolver = self.resolver.clone();
+        let addrs = timeout(Phase::Dns, self.timeouts.dns, resolver.call(name)).await??;
+        let addrs: Vec<SocketAddr> = addrs.map(|a| SocketAddr::new(a, port)).collect();
+        let preferred = addrs.iter().find(|a| self.socket.can_reach(a));
+        let addr = match preferred.or_else(|| addrs.first()).copied() {
+            Some(a) => a,
+            None => {
+                let e = io::Error::new(io::ErrorKind::InvalidInput, "Did not resolve an address");
+                return Err(DnsError::new(host, e).into());
+            }
+        };
+        let endpoint = self.endpoint(&addr)?;
+        let config = QuicClientConfig::try_from(self.tls_config.clone()?)?;
+        let config = quinn::ClientConfig::new(Arc::new(config));
+        let server_name = self.server_name.clone().unwrap_or(host);
+        // Bracketed IPv6 literals aren't valid server names
+        let server_name = match dns::ip_literal(&server_name) {
+            Some(ip) => ip.to_string(),
+            None => server_name,
+        };
+        let handshake = handshake(
+            endpoint,
+            config,
+            addr,
+            server_name,
+            early_data,
+            self.collector.clone(),
+            context::current(),
+        );
+        events::emit(Event::ConnectionStarted);
+        let conn = timeout(Phase::Connect, self.timeouts.connect, self.spawn(handshake))
+            .await
+            .map_err(BoxError::from)
+            .and_then(|res| res);
+        events::emit(match conn {
+            Ok(_) => Event::Connected,
+            Err(_) => Event::ConnectionError,
+        });
+        conn
+    }
+
+    /// UDP endpoint for connecting to `addr`, one per address family
+    fn endpoint(&self, addr: &SocketAddr) -> io::Result<Endpoint> {
+        let mut endpoints = self.endpoints.lock().unwrap();
+        if let Some(endpoint) = endpoints.get(&addr.is_ipv6()) {
+            return Ok(endpoint.clone());
+        }
+        let bind = match self.socket.local_bind(addr)? {
+            Some(bind) => bind,
+            None if addr.is_ipv6() => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+            None => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        };
+        let _guard = self.runtime.enter();
+        let endpoint = Endpoint::client(bind)?;
+        endpoints.insert(addr.is_ipv6(), endpoint.clone());
+        Ok(endpoint)
+    }
+
+    /// Run a future on the QUIC runtime
+    fn spawn<T, F>(&self, fut: F) -> impl Future<Output = Result<T, BoxError>>
+    where
+        T: Send + 'static,
+        F: Future<Output = Result<T, BoxError>> + Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        self.runtime.spawn(async move {
+            let _ = tx.send(fut.await);
+        });
+        rx.map(|res| res.map_err(BoxError::from).and_then(|res| res))
+    }
+}
+
+fn tls_config(options: &TlsOptions) -> Result<rustls::ClientConfig, rustls::Error> {
+    if let Some(ref versions) = options.versions {
+        if !versions.contains(&TlsVersion::Tls13) {
+            return Err(rustls::Error::General("QUIC requires TLS 1.3".into()));
+        }
+    }
+    let provider = Arc::new(tls::quic_crypto_provider(options));
+    let builder = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_root_certificates(tls::quic_root_store(options));
+    let mut config = match options.client_cert {
+        Some(ref cert) => {
+            let (chain, key) = tls::quic_client_cert_chain(cert);
+            builder
+                .with_client_auth_cert(chain, key)
+                .expect("client key was checked when it was loaded")
+        }
+        None => builder.with_no_client_auth(),
+    };
+    if options.insecure {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(QuicNoVerification(provider)));
+    }
+    config.alpn_protocols = vec![ALPN_H3.to_vec()];
+    if !options.resume {
+        config.resumption = Resumption::disabled();
+    }
+    config.enable_early_data = options.resume && options.early_data;
+    Ok(config)
+}
+
+/// Methods that can safely be sent as early data, as they may be replayed (RFC 7231 §4.2.2)
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::TRACE | Method::PUT | Method::DELETE
+    )
+}
+
+fn convert_request(parts: &request::Parts) -> Result<quic_http::Request<()>, BoxError> {
+    let mut builder = quic_http::Request::builder()
+        .method(parts.method.as_str())
+        .uri(parts.uri.to_string());
+    for (name, value) in parts.headers.iter() {
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+    }
+    Ok(builder.body(())?)
+}
+
+/// Establish a QUIC connection.  With `early_data`, and a session ticket from an earlier
+/// connection that allows it, the connection is handed out before the handshake is done, for
+/// the first request to go out as 0-RTT early data.
+async fn handshake(
+    endpoint: Endpoint,
+    config: quinn::ClientConfig,
+    addr: SocketAddr,
+    server_name: String,
+    early_data: bool,
+    collector: CollectorHandle<Metric>,
+    context: Option<RequestContext>,
+) -> Result<Handshake, BoxError> {
+    // Runs on the QUIC runtime, outside of the task making the request
+    let send = move |sample| match context {
+        Some(ref context) => context.send(&collector, sample),
+        None => collector.send(sample),
+    };
+    let stopwatch = Stopwatch::new();
+    let connecting = endpoint.connect_with(config, addr, &server_name)?;
+    let connecting = if early_data {
+        connecting.into_0rtt()
+    } else {
+        Err(connecting)
+    };
+    let (conn, early_data) = match connecting {
+        // The handshake finishes in the background while the request is sent
+        Ok((conn, accepted)) => {
+            let accepted = async move {
+                let accepted = accepted.await;
+                if accepted {
+                    send(Sample::count(Metric::ZeroRtt, 1));
+                }
+                send(stopwatch.elapsed(Metric::QuicHandshake));
+                accepted
+            };
+            let accepted: Pin<Box<dyn Future<Output = bool> + Send>> = Box::pin(accepted);
+            (conn, Some(accepted))
+        }
+        Err(connecting) => {
+            let conn = connecting.await?;
+            send(stopwatch.elapsed(Metric::QuicHandshake));
+            (conn, None)
+        }
+    };
+    let (mut driver, send_request) =
+        h3::client::new(h3_quinn::Connection::new(conn.clone())).await?;
+    quic_tokio::spawn(async move {
+        let _ = future::poll_fn(|cx| driver.poll_close(cx)).await;
+    });
+    let mut info = ConnectionInfo::new(Some(ALPN_H3.to_vec()));
+    info.set_local_addr(endpoint.local_addr().ok());
+    // SNI is only sent for DNS names
+    let sni = match server_name.parse::<IpAddr>() {
+        Ok(_) => None,
+        Err(_) => Some(server_name),
+    };
+    Ok(Handshake {
+        conn: QuicConnection {
+            conn,
+            send_request,
+            info,
+        },
+        sni,
+        early_data,
+    })
+}
+
+/// Send a request and wait for the response headers.  The body is streamed into the returned
+/// `Body` by a separate task.
+async fn exchange(
+    mut send_request: SendRequest,
+    req: quic_http::Request<()>,
+    body: Bytes,
+    context: Option<RequestContext>,
+) -> Result<(quic_http::response::Parts, Body), BoxError> {
+    let mut stream = send_request.send_request(req).await?;
+    if !body.is_empty() {
+        stream
+            .send_data(quic_bytes::Bytes::copy_from_slice(&body))
+            .await?;
+    }
+    stream.finish().await?;
+    if let Some(context) = context {
+        context.events.add(Event::RequestWritten);
+    }
+    let (head, ()) = stream.recv_response().await?.into_parts();
+    let (mut tx, body) = Body::channel();
+    quic_tokio::spawn(async move {
+        // Holding on to the request handle keeps the connection open until the body is read
+        let _send_request = send_request;
+        loop {
+            match stream.recv_data().await {
+                Ok(Some(mut chunk)) => {
+                    let chunk = chunk.copy_to_bytes(chunk.remaining());
+                    if tx.send_data(Bytes::copy_from_slice(&chunk)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(None) => break,
+                Err(_) => {
+                    tx.abort();
+                    break;
+                }
+            }
+        }
+    });
+    Ok((head, body))
+}
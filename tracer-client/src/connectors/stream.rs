@@ -0,0 +1,574 @@
+use crate::socket::TcpInfo;
+use crate::tls::TlsInfo;
+use hyper::client::connect::{Connected, Connection};
+use hyper_rustls::MaybeHttpsStream;
+use rustls::Session;
+use std::io;
+use std::mem::MaybeUninit;
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+/// Details about the connection a response was received on.
+///
+/// Available in the extensions of every `Response` returned by `Client`.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    uses: Arc<AtomicUsize>,
+    reused: bool,
+    alpn_protocol: Option<Vec<u8>>,
+    zero_rtt: bool,
+    tls: Option<Arc<TlsInfo>>,
+    local_addr: Option<SocketAddr>,
+    io_times: Arc<Mutex<IoTimes>>,
+    tcp: Arc<Mutex<TcpInfoSource>>,
+    connect_tcp_info: Option<TcpInfo>,
+    response_tcp_info: Option<TcpInfo>,
+    wire: Arc<WireBytes>,
+    tls_handshake_bytes: Option<ByteCount>,
+}
+
+#[derive(Debug, Default)]
+struct IoTimes {
+    first_write: Option<Instant>,
+    first_read: Option<Instant>,
+    exchange: Exchange,
+    /// Whether data was read since the last write, so the next write starts a new exchange
+    reading: bool,
+    /// How much of the `\r\n\r\n` ending an HTTP/1 response head was read last
+    head_end: usize,
+}
+
+/// When a request was written and its response started arriving, as seen by the connection.
+///
+/// HTTP/1.1 connections carry one exchange at a time, so these belong to the latest request.
+/// Requests multiplexed on an HTTP/2 connection share them.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Exchange {
+    pub(crate) write_start: Option<Instant>,
+    pub(crate) write_end: Option<Instant>,
+    pub(crate) first_read: Option<Instant>,
+    /// Bytes handed to the connection, before any TLS
+    pub(crate) sent: u64,
+    /// Bytes read from the connection, after any TLS
+    pub(crate) received: u64,
+    /// Length of the HTTP/1 response head among the bytes received, including the status line
+    /// and the blank line ending it
+    pub(crate) head: Option<u64>,
+    /// Bytes that had gone over the wire before the exchange started
+    pub(crate) wire_start: ByteCount,
+}
+
+/// Bytes sent and received
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ByteCount {
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl ByteCount {
+    /// Bytes sent and received since `earlier`
+    pub(crate) fn since(self, earlier: ByteCount) -> ByteCount {
+        ByteCount {
+            sent: self.sent - earlier.sent,
+            received: self.received - earlier.received,
+        }
+    }
+}
+
+/// Running totals of a `CountingIo`
+#[derive(Debug, Default)]
+struct WireBytes {
+    sent: AtomicU64,
+    received: AtomicU64,
+}
+
+impl WireBytes {
+    fn get(&self) -> ByteCount {
+        ByteCount {
+            sent: self.sent.load(Ordering::Relaxed),
+            received: self.received.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Reads `TCP_INFO` from the socket while the connection is open, keeping the last reading
+/// for once it has closed
+#[derive(Debug, Default)]
+struct TcpInfoSource {
+    #[cfg(target_os = "linux")]
+    fd: Option<RawFd>,
+    last: Option<TcpInfo>,
+}
+
+impl TcpInfoSource {
+    fn read(&mut self) -> Option<TcpInfo> {
+        #[cfg(target_os = "linux")]
+        {
+            if let Some(fd) = self.fd {
+                self.last = crate::socket::tcp_info(fd).ok().or(self.last);
+            }
+        }
+        self.last
+    }
+
+    /// Take a final reading before the socket is closed and its descriptor reused
+    fn close(&mut self) {
+        self.read();
+        #[cfg(target_os = "linux")]
+        {
+            self.fd = None;
+        }
+    }
+}
+
+impl ConnectionInfo {
+    pub(crate) fn new(alpn_protocol: Option<Vec<u8>>) -> ConnectionInfo {
+        ConnectionInfo {
+            uses: Arc::new(AtomicUsize::new(0)),
+            reused: false,
+            alpn_protocol,
+            zero_rtt: false,
+            tls: None,
+            local_addr: None,
+            io_times: Arc::new(Mutex::new(IoTimes::default())),
+            tcp: Arc::new(Mutex::new(TcpInfoSource::default())),
+            connect_tcp_info: None,
+            response_tcp_info: None,
+            wire: Arc::new(WireBytes::default()),
+            tls_handshake_bytes: None,
+        }
+    }
+
+    /// Count a response received on this connection, noting if earlier requests already used it.
+    pub(crate) fn mark_used(&mut self) {
+        self.reused = self.uses.fetch_add(1, Ordering::SeqCst) > 0;
+    }
+
+    /// Whether the connection had already served another request
+    pub fn is_reused(&self) -> bool {
+        self.reused
+    }
+
+    /// Protocol agreed on via ALPN during the TLS handshake, if any
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    pub(crate) fn set_zero_rtt(&mut self, zero_rtt: bool) {
+        self.zero_rtt = zero_rtt;
+    }
+
+    /// Whether requests were sent as 0-RTT early data, before the QUIC handshake completed
+    pub fn is_zero_rtt(&self) -> bool {
+        self.zero_rtt
+    }
+
+    pub(crate) fn set_tls(&mut self, tls: TlsInfo) {
+        self.tls = Some(Arc::new(tls));
+    }
+
+    /// What was negotiated during the TLS handshake, `None` for plain HTTP connections
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_deref()
+    }
+
+    pub(crate) fn set_local_addr(&mut self, local_addr: Option<SocketAddr>) {
+        self.local_addr = local_addr;
+    }
+
+    /// Source address and port of the connection, `None` for Unix sockets
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Kernel TCP statistics from once the connection was established, including any TLS
+    /// handshake.  Only on Linux, and `None` for QUIC and Unix socket connections.
+    pub fn connect_tcp_info(&self) -> Option<&TcpInfo> {
+        self.connect_tcp_info.as_ref()
+    }
+
+    /// Kernel TCP statistics from after the response was read, see `connect_tcp_info`
+    pub fn tcp_info(&self) -> Option<&TcpInfo> {
+        self.response_tcp_info.as_ref()
+    }
+
+    /// Read the current TCP statistics, or the last ones if the connection has closed since
+    pub(crate) fn capture_tcp_info(&mut self) -> Option<TcpInfo> {
+        self.response_tcp_info = self.tcp.lock().unwrap().read();
+        self.response_tcp_info
+    }
+
+    /// Bytes that went over the connection during the TLS handshake, `None` for plain HTTP
+    /// connections
+    pub fn tls_handshake_bytes(&self) -> Option<ByteCount> {
+        self.tls_handshake_bytes
+    }
+
+    /// Bytes that went over the connection so far, including any TLS framing
+    pub(crate) fn wire_bytes(&self) -> ByteCount {
+        self.wire.get()
+    }
+
+    /// Timings and sizes of the latest request and response on the connection
+    pub(crate) fn exchange(&self) -> Exchange {
+        self.io_times.lock().unwrap().exchange
+    }
+
+    /// Time from the first write on the connection until the first byte from the server arrived.
+    ///
+    /// On an HTTP/2 connection the server has to open with its SETTINGS frame, so this is the
+    /// time taken to exchange connection prefaces.
+    pub fn first_exchange(&self) -> Option<Duration> {
+        let times = self.io_times.lock().unwrap();
+        match (times.first_write, times.first_read) {
+            (Some(w), Some(r)) if r >= w => Some(r - w),
+            _ => None,
+        }
+    }
+}
+
+/// The connection underneath HTTP or TLS
+pub enum Transport {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream),
+}
+
+impl Transport {
+    #[cfg(target_os = "linux")]
+    fn raw_fd(&self) -> Option<RawFd> {
+        match self {
+            Transport::Tcp(tcp) => Some(tcp.as_raw_fd()),
+            Transport::Unix(_) => None,
+        }
+    }
+
+    fn local_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Transport::Tcp(tcp) => tcp.local_addr().ok(),
+            #[cfg(unix)]
+            Transport::Unix(_) => None,
+        }
+    }
+}
+
+impl Connection for Transport {
+    fn connected(&self) -> Connected {
+        match self {
+            Transport::Tcp(tcp) => tcp.connected(),
+            #[cfg(unix)]
+            Transport::Unix(_) => Connected::new(),
+        }
+    }
+}
+
+impl AsyncRead for Transport {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        match self {
+            Transport::Tcp(tcp) => tcp.prepare_uninitialized_buffer(buf),
+            #[cfg(unix)]
+            Transport::Unix(unix) => unix.prepare_uninitialized_buffer(buf),
+        }
+    }
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        match self.get_mut() {
+            Transport::Tcp(tcp) => Pin::new(tcp).poll_read(cx, buf),
+            #[cfg(unix)]
+            Transport::Unix(unix) => Pin::new(unix).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Transport {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        match self.get_mut() {
+            Transport::Tcp(tcp) => Pin::new(tcp).poll_write(cx, buf),
+            #[cfg(unix)]
+            Transport::Unix(unix) => Pin::new(unix).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        match self.get_mut() {
+            Transport::Tcp(tcp) => Pin::new(tcp).poll_flush(cx),
+            #[cfg(unix)]
+            Transport::Unix(unix) => Pin::new(unix).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        match self.get_mut() {
+            Transport::Tcp(tcp) => Pin::new(tcp).poll_shutdown(cx),
+            #[cfg(unix)]
+            Transport::Unix(unix) => Pin::new(unix).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Counts the bytes read and written through `inner`.  Placed underneath TLS, so what's counted
+/// is what goes over the wire.
+pub struct CountingIo<T> {
+    inner: T,
+    bytes: Arc<WireBytes>,
+}
+
+impl<T> CountingIo<T> {
+    pub(crate) fn new(inner: T) -> CountingIo<T> {
+        CountingIo {
+            inner,
+            bytes: Arc::new(WireBytes::default()),
+        }
+    }
+
+    pub(crate) fn get_ref(&self) -> &T {
+        &self.inner
+    }
+}
+
+impl<T: Connection> Connection for CountingIo<T> {
+    fn connected(&self) -> Connected {
+        self.inner.connected()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for CountingIo<T> {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            this.bytes.received.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for CountingIo<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            this.bytes.sent.fetch_add(n as u64, Ordering::Relaxed);
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+/// The stream handed to hyper by `TracingHttpsConnector`, which attaches a `ConnectionInfo` to responses
+pub struct TracingStream {
+    inner: MaybeHttpsStream<CountingIo<Transport>>,
+    info: ConnectionInfo,
+    proxied: bool,
+    read: bool,
+    written: bool,
+    /// Responses are HTTP/2 frames, with no HTTP/1 head to find
+    h2: bool,
+}
+
+impl TracingStream {
+    pub(crate) fn new(inner: MaybeHttpsStream<CountingIo<Transport>>) -> TracingStream {
+        let alpn_protocol = match inner {
+            MaybeHttpsStream::Http(_) => None,
+            MaybeHttpsStream::Https(ref tls) => tls.get_ref().1.get_alpn_protocol().map(Vec::from),
+        };
+        let counting = match inner {
+            MaybeHttpsStream::Http(ref counting) => counting,
+            MaybeHttpsStream::Https(ref tls) => tls.get_ref().0,
+        };
+        let transport = counting.get_ref();
+        let mut info = ConnectionInfo::new(alpn_protocol);
+        info.wire = counting.bytes.clone();
+        if let MaybeHttpsStream::Https(_) = inner {
+            // Nothing else has gone over the connection yet
+            info.tls_handshake_bytes = Some(info.wire.get());
+        }
+        info.set_local_addr(transport.local_addr());
+        #[cfg(target_os = "linux")]
+        {
+            let mut tcp = info.tcp.lock().unwrap();
+            tcp.fd = transport.raw_fd();
+            info.connect_tcp_info = tcp.read();
+        }
+        let h2 = info.alpn_protocol() == Some(&b"h2"[..]);
+        TracingStream {
+            inner,
+            info,
+            proxied: false,
+            read: false,
+            written: false,
+            h2,
+        }
+    }
+
+    pub(crate) fn info(&self) -> &ConnectionInfo {
+        &self.info
+    }
+
+    /// Mark the connection as leading to an HTTP proxy, so hyper sends requests in absolute form
+    pub(crate) fn proxied(mut self, proxied: bool) -> TracingStream {
+        self.proxied = proxied;
+        self
+    }
+
+    /// Record the details of a TLS connection, `sni` being the server name it was opened with
+    pub(crate) fn with_tls_info(mut self, sni: Option<&str>) -> TracingStream {
+        if let MaybeHttpsStream::Https(ref tls) = self.inner {
+            self.info
+                .set_tls(TlsInfo::from_session(tls.get_ref().1, sni));
+        }
+        self
+    }
+}
+
+impl Drop for TracingStream {
+    fn drop(&mut self) {
+        self.info.tcp.lock().unwrap().close();
+    }
+}
+
+impl Connection for TracingStream {
+    fn connected(&self) -> Connected {
+        self.inner
+            .connected()
+            .proxy(self.proxied)
+            .extra(self.info.clone())
+    }
+}
+
+impl AsyncRead for TracingStream {
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [MaybeUninit<u8>]) -> bool {
+        self.inner.prepare_uninitialized_buffer(buf)
+    }
+
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        let res = Pin::new(&mut this.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            if n > 0 {
+                let now = Instant::now();
+                let mut times = this.info.io_times.lock().unwrap();
+                if !this.read {
+                    this.read = true;
+                    times.first_read = Some(now);
+                }
+                if !times.reading {
+                    times.reading = true;
+                    times.exchange.first_read = Some(now);
+                }
+                if times.exchange.head.is_none() && !this.h2 {
+                    if let Some(end) = find_head_end(&mut times.head_end, &buf[..n]) {
+                        times.exchange.head = Some(times.exchange.received + end as u64);
+                    }
+                }
+                times.exchange.received += n as u64;
+            }
+        }
+        res
+    }
+}
+
+/// Look for the blank line ending an HTTP/1 response head in `buf`, continuing from the
+/// `matched` bytes of it found at the end of the previous read.  Returns the length of the head
+/// within `buf`.
+fn find_head_end(matched: &mut usize, buf: &[u8]) -> Option<usize> {
+    const END: &[u8] = b"\r\n\r\n";
+    for (i, b) in buf.iter().enumerate() {
+        *matched = if *b == END[*matched] {
+            *matched + 1
+        } else if *b == END[0] {
+            1
+        } else {
+            0
+        };
+        if *matched == END.len() {
+            *matched = 0;
+            return Some(i + 1);
+        }
+    }
+    None
+}
+
+impl AsyncWrite for TracingStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = self.get_mut();
+        if !this.written {
+            this.written = true;
+            this.info.io_times.lock().unwrap().first_write = Some(Instant::now());
+        }
+        // Before the write, which may put it on the wire right away
+        let wire = this.info.wire.get();
+        let res = Pin::new(&mut this.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            if n > 0 {
+                let now = Instant::now();
+                let mut times = this.info.io_times.lock().unwrap();
+                if times.reading || times.exchange.write_start.is_none() {
+                    times.reading = false;
+                    times.head_end = 0;
+                    times.exchange = Exchange {
+                        write_start: Some(now),
+                        wire_start: wire,
+                        ..Exchange::default()
+                    };
+                }
+                times.exchange.write_end = Some(now);
+                times.exchange.sent += n as u64;
+            }
+        }
+        res
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,66 @@
+use crate::client::Metric;
+use crate::context;
+use crate::events::{self, Event};
+use crate::timeouts::{timeout, Phase, Timeouts};
+use crate::FutureResponse;
+use futures::prelude::*;
+use hyper::service::Service;
+use hyper::Uri;
+use std::path::PathBuf;
+use std::task::Context;
+use std::task::Poll;
+use tokio::net::UnixStream;
+use tracer_metrics::{CollectorHandle, Stopwatch};
+
+/// Connects to a Unix domain socket instead of the host in the URL, which is still sent in
+/// requests as usual
+#[derive(Clone)]
+pub struct TracingUnixConnector {
+    path: PathBuf,
+    collector: CollectorHandle<Metric>,
+    timeouts: Timeouts,
+}
+
+impl TracingUnixConnector {
+    pub fn new<P: Into<PathBuf>>(
+        path: P,
+        collector: CollectorHandle<Metric>,
+    ) -> TracingUnixConnector {
+        TracingUnixConnector {
+            path: path.into(),
+            collector,
+            timeouts: Timeouts::default(),
+        }
+    }
+
+    /// Set the timeouts used to connect
+    pub fn set_timeouts(&mut self, timeouts: Timeouts) {
+        self.timeouts = timeouts;
+    }
+}
+
+impl Service<Uri> for TracingUnixConnector {
+    type Response = UnixStream;
+    type Error = std::io::Error;
+    type Future = FutureResponse<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _dst: Uri) -> Self::Future {
+        let path = self.path.clone();
+        let connect_timeout = self.timeouts.connect;
+        let collector = self.collector.clone();
+        async move {
+            events::emit(Event::ConnectionStarted);
+            let stopwatch = Stopwatch::new();
+            let stream =
+                timeout(Phase::Connect, connect_timeout, UnixStream::connect(&path)).await??;
+            context::send(&collector, stopwatch.elapsed(Metric::Connection));
+            events::emit(Event::Connected);
+            Ok(stream)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,137 @@
+use crate::client::Metric;
+use crate::events::{EventCollector, EventSet};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracer_metrics::data::{Sample, SampleValue};
+use tracer_metrics::CollectorHandle;
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    static CONTEXT: RequestContext;
+}
+
+/// Identity of a request made by `Client` and what has been recorded about it so far.
+///
+/// Carried by the task making the request, so the resolver and connectors can attribute their
+/// measurements to it however many other requests are in flight.
+#[derive(Debug, Clone)]
+pub(crate) struct RequestContext {
+    id: u64,
+    pub(crate) events: EventCollector,
+    samples: Arc<Mutex<Vec<(Metric, SampleValue)>>>,
+}
+
+impl RequestContext {
+    pub(crate) fn new() -> RequestContext {
+        RequestContext {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            events: EventCollector::new(),
+            samples: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Run `f` as part of this request
+    pub(crate) async fn scope<F: Future>(&self, f: F) -> F::Output {
+        CONTEXT.scope(self.clone(), f).await
+    }
+
+    /// Send `sample` to `collector`, keeping a copy with this request's metrics
+    pub(crate) fn send(&self, collector: &CollectorHandle<Metric>, sample: Sample<Metric>) {
+        self.samples
+            .lock()
+            .unwrap()
+            .push((sample.key(), sample.value.clone()));
+        collector.send(sample);
+    }
+
+    /// What was recorded about the request, once it has finished
+    pub(crate) fn finish(&self) -> RequestTrace {
+        RequestTrace {
+            timeline: self.events.drain_events(),
+            metrics: RequestMetrics {
+                id: self.id,
+                samples: self.samples.lock().unwrap().clone(),
+            },
+        }
+    }
+}
+
+/// Everything recorded about a single request
+#[derive(Debug, Clone, Default)]
+pub struct RequestTrace {
+    pub timeline: EventSet,
+    pub metrics: RequestMetrics,
+}
+
+/// Run `f` with the context of the request the current task is making, if any
+pub(crate) fn with_current<R, F: FnOnce(&RequestContext) -> R>(f: F) -> Option<R> {
+    CONTEXT.try_with(f).ok()
+}
+
+/// The context of the request the current task is making, to hand to other tasks
+pub(crate) fn current() -> Option<RequestContext> {
+    with_current(RequestContext::clone)
+}
+
+/// Send `sample` to `collector`, and to the request the current task is making, if any
+pub(crate) fn send(collector: &CollectorHandle<Metric>, sample: Sample<Metric>) {
+    match current() {
+        Some(context) => context.send(collector, sample),
+        None => collector.send(sample),
+    }
+}
+
+/// The metrics recorded for a single request, attached to the responses `Client` returns.
+///
+/// Unlike the gauges of a shared `Collector`, these are exact for the request they came with
+/// when several requests run at once.  Metrics for steps the request skipped, like connecting
+/// when a connection was reused, are absent.
+#[derive(Debug, Clone, Default)]
+pub struct RequestMetrics {
+    id: u64,
+    samples: Vec<(Metric, SampleValue)>,
+}
+
+impl RequestMetrics {
+    /// Identifies the request among all requests made by this process
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Add a metric measured outside of the client, such as the size of a response once read
+    pub fn push(&mut self, metric: Metric, value: SampleValue) {
+        self.samples.push((metric, value));
+    }
+
+    /// The latest value recorded for `metric`
+    pub fn get(&self, metric: Metric) -> Option<&SampleValue> {
+        self.samples
+            .iter()
+            .rev()
+            .find(|(m, _)| *m == metric)
+            .map(|(_, v)| v)
+    }
+
+    /// The latest duration recorded for `metric`
+    pub fn elapsed(&self, metric: Metric) -> Option<Duration> {
+        match self.get(metric) {
+            Some(SampleValue::Elapsed(d)) => Some(*d),
+            _ => None,
+        }
+    }
+
+    /// The latest value or count recorded for `metric`
+    pub fn value(&self, metric: Metric) -> Option<u64> {
+        match self.get(metric) {
+            Some(SampleValue::Value(v)) | Some(SampleValue::Count(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Metric, SampleValue)> {
+        self.samples.iter()
+    }
+}
@@ -0,0 +1,478 @@
+use crate::date::DateTime;
+use hyper::header::{HeaderMap, HeaderValue, SET_COOKIE};
+use hyper::Uri;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A cookie stored from a `Set-Cookie` header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    pub name: String,
+    pub value: String,
+    /// Lowercased, without a leading dot
+    pub domain: String,
+    /// Only sent to `domain` itself, not its subdomains, as when `Set-Cookie` has no `Domain`
+    pub host_only: bool,
+    pub path: String,
+    /// `None` for session cookies, which last as long as the jar
+    pub expires: Option<SystemTime>,
+    /// Only sent over https
+    pub secure: bool,
+    pub http_only: bool,
+}
+
+impl Cookie {
+    /// Parse a `Set-Cookie` header received from `url`, as in RFC 6265 section 5.2.  `None` if
+    /// it's malformed or for a domain `url` can't set cookies for.
+    fn parse(url: &Uri, header: &str, now: SystemTime) -> Option<Cookie> {
+        let host = url.host()?.trim_start_matches('[').trim_end_matches(']');
+        let host = host.to_ascii_lowercase();
+        let mut parts = header.split(';');
+        let (name, value) = parts.next()?.split_once('=')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+        let mut cookie = Cookie {
+            name: name.to_string(),
+            value: value.trim().to_string(),
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url.path()),
+            expires: None,
+            secure: false,
+            http_only: false,
+        };
+        let mut max_age = None;
+        for attr in parts {
+            let (key, value) = attr.split_once('=').unwrap_or((attr, ""));
+            let value = value.trim();
+            match key.trim().to_ascii_lowercase().as_str() {
+                "expires" => {
+                    if let Some(t) = parse_cookie_date(value) {
+                        cookie.expires = Some(t);
+                    }
+                }
+                "max-age" => {
+                    if let Ok(secs) = value.parse::<i64>() {
+                        max_age = Some(secs);
+                    }
+                }
+                "domain" => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    if !domain.is_empty() {
+                        cookie.domain = domain;
+                        cookie.host_only = false;
+                    }
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => (),
+            }
+        }
+        // Max-Age wins over Expires, and anything not in the future deletes the cookie
+        if let Some(secs) = max_age {
+            cookie.expires = Some(if secs > 0 {
+                now + Duration::from_secs(secs as u64)
+            } else {
+                UNIX_EPOCH
+            });
+        }
+        if !cookie.host_only && !domain_match(&host, &cookie.domain) {
+            return None;
+        }
+        Some(cookie)
+    }
+
+    fn is_expired(&self, now: SystemTime) -> bool {
+        self.expires.is_some_and(|t| t <= now)
+    }
+
+    /// Whether this cookie goes with a request for `url`
+    fn matches(&self, url: &Uri) -> bool {
+        let host = match url.host() {
+            Some(host) => host.trim_start_matches('[').trim_end_matches(']'),
+            None => return false,
+        };
+        let host = host.to_ascii_lowercase();
+        let domain = if self.host_only {
+            host == self.domain
+        } else {
+            domain_match(&host, &self.domain)
+        };
+        domain && path_match(url.path(), &self.path) && (!self.secure || is_secure(url))
+    }
+
+    /// Same cookie as far as replacing it goes
+    fn same_key(&self, other: &Cookie) -> bool {
+        self.name == other.name && self.domain == other.domain && self.path == other.path
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)
+    }
+}
+
+/// Cookies kept between requests.  Clones share the same cookies, so one jar can be given to
+/// several `Client`s.
+///
+/// Cookies aren't checked against the public suffix list, so a server can set them for a whole
+/// top level domain.
+#[derive(Debug, Clone, Default)]
+pub struct CookieJar {
+    cookies: Arc<Mutex<Vec<Cookie>>>,
+}
+
+impl CookieJar {
+    pub fn new() -> CookieJar {
+        CookieJar::default()
+    }
+
+    /// Store the cookies set by a response from `url`
+    pub fn store(&self, url: &Uri, headers: &HeaderMap) {
+        let now = SystemTime::now();
+        let mut cookies = self.cookies.lock().unwrap();
+        for header in headers.get_all(SET_COOKIE) {
+            let cookie = match header.to_str() {
+                Ok(header) => Cookie::parse(url, header, now),
+                Err(_) => None,
+            };
+            if let Some(cookie) = cookie {
+                cookies.retain(|c| !c.same_key(&cookie));
+                if !cookie.is_expired(now) {
+                    cookies.push(cookie);
+                }
+            }
+        }
+    }
+
+    /// Value for the `Cookie` header of a request for `url`, if any cookies go with it
+    pub fn header_for(&self, url: &Uri) -> Option<HeaderValue> {
+        let now = SystemTime::now();
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|c| !c.is_expired(now));
+        let mut matching: Vec<&Cookie> = cookies.iter().filter(|c| c.matches(url)).collect();
+        if matching.is_empty() {
+            return None;
+        }
+        // Longer paths first, otherwise in the order they were set
+        matching.sort_by_key(|c| std::cmp::Reverse(c.path.len()));
+        let pairs: Vec<String> = matching.iter().map(|c| c.to_string()).collect();
+        HeaderValue::from_str(&pairs.join("; ")).ok()
+    }
+
+    /// The cookies in the jar, expired ones included until they're next looked up
+    pub fn cookies(&self) -> Vec<Cookie> {
+        self.cookies.lock().unwrap().clone()
+    }
+
+    pub fn insert(&self, cookie: Cookie) {
+        let mut cookies = self.cookies.lock().unwrap();
+        cookies.retain(|c| !c.same_key(&cookie));
+        cookies.push(cookie);
+    }
+
+    /// Add the cookies from a Netscape format cookie file, as written by curl and browsers
+    pub fn load<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let contents = fs::read_to_string(path)?;
+        for (n, line) in contents.lines().enumerate() {
+            let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields: Vec<&str> = line.split('\t').collect();
+            let invalid = || {
+                let msg = format!("invalid cookie on line {}", n + 1);
+                io::Error::new(io::ErrorKind::InvalidData, msg)
+            };
+            if fields.len() != 7 {
+                return Err(invalid());
+            }
+            let expires: u64 = fields[4].parse().map_err(|_| invalid())?;
+            let domain = fields[0];
+            self.insert(Cookie {
+                name: fields[5].to_string(),
+                value: fields[6].to_string(),
+                domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+                host_only: !fields[1].eq_ignore_ascii_case("TRUE"),
+                path: fields[2].to_string(),
+                expires: match expires {
+                    0 => None,
+                    secs => Some(UNIX_EPOCH + Duration::from_secs(secs)),
+                },
+                secure: fields[3].eq_ignore_ascii_case("TRUE"),
+                http_only,
+            });
+        }
+        Ok(())
+    }
+
+    /// Write the jar to a Netscape format cookie file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let now = SystemTime::now();
+        let mut contents = String::from("# Netscape HTTP Cookie File\n");
+        for c in self.cookies().iter().filter(|c| !c.is_expired(now)) {
+            let flag = |b: bool| if b { "TRUE" } else { "FALSE" };
+            let expires = c
+                .expires
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            contents.push_str(&format!(
+                "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if c.http_only { "#HttpOnly_" } else { "" },
+                if c.host_only { "" } else { "." },
+                c.domain,
+                flag(!c.host_only),
+                c.path,
+                flag(c.secure),
+                expires,
+                c.name,
+                c.value
+            ));
+        }
+        fs::write(path, contents)
+    }
+}
+
+fn is_secure(url: &Uri) -> bool {
+    url.scheme_str() == Some("https")
+}
+
+/// RFC 6265 section 5.1.3
+fn domain_match(host: &str, domain: &str) -> bool {
+    host == domain
+        || (host.ends_with(domain)
+            && host[..host.len() - domain.len()].ends_with('.')
+            && host.parse::<std::net::IpAddr>().is_err())
+}
+
+/// RFC 6265 section 5.1.4
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(i) => path[..i].to_string(),
+    }
+}
+
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// Parse a date the lenient way RFC 6265 section 5.1.1 asks for, which copes with the several
+/// formats servers send in `Expires`
+fn parse_cookie_date(s: &str) -> Option<SystemTime> {
+    let is_delimiter = |c: char| {
+        c == '\t'
+            || (' '..='/').contains(&c)
+            || (';'..='@').contains(&c)
+            || ('['..='`').contains(&c)
+            || ('{'..='~').contains(&c)
+    };
+    let (mut time, mut day, mut month, mut year) = (None, None, None, None);
+    for token in s.split(is_delimiter).filter(|t| !t.is_empty()) {
+        let digits = token.chars().take_while(|c| c.is_ascii_digit()).count();
+        if time.is_none() {
+            let hms: Vec<&str> = token.split(':').collect();
+            if hms.len() == 3 && hms.iter().all(|p| (1..=2).contains(&p.len())) {
+                if let (Ok(h), Ok(m), Ok(s)) = (hms[0].parse(), hms[1].parse(), hms[2].parse()) {
+                    time = Some((h, m, s));
+                    continue;
+                }
+            }
+        }
+        if day.is_none() && (1..=2).contains(&digits) {
+            day = token[..digits].parse::<u32>().ok();
+            continue;
+        }
+        if month.is_none() && token.len() >= 3 {
+            let months = [
+                "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+            ];
+            let prefix = token.get(..3).map(|p| p.to_ascii_lowercase());
+            if let Some(i) = months.iter().position(|m| Some(*m) == prefix.as_deref()) {
+                month = Some(i as u32 + 1);
+                continue;
+            }
+        }
+        if year.is_none() && (2..=4).contains(&digits) {
+            year = token[..digits].parse::<i64>().ok();
+        }
+    }
+    let (hour, minute, second) = time?;
+    let (day, month, year) = (day?, month?, year?);
+    let year = match year {
+        70..=99 => year + 1900,
+        0..=69 => year + 2000,
+        y => y,
+    };
+    if !(1..=31).contains(&day) || year < 1601 || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    let date = DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    };
+    Some(date.to_system_time())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_cookie_date_test() {
+        let cases = [
+            // RFC 1123, as RFC 7231 prefers
+            ("Wed, 09 Jun 2021 10:18:14 GMT", Some(1_623_233_894)),
+            // RFC 850, with a two digit year
+            ("Wednesday, 09-Jun-21 10:18:14 GMT", Some(1_623_233_894)),
+            // asctime
+            ("Wed Jun  9 10:18:14 2021", Some(1_623_233_894)),
+            // Out of order, odd delimiters and case
+            ("10:18:14 2021 JUNE 9", Some(1_623_233_894)),
+            ("Wed, 09-jun-2021 10:18:14 UTC", Some(1_623_233_894)),
+            ("Fri, 31 Dec 99 23:59:59 GMT", Some(946_684_799)),
+            ("Tue, 01 Jan 69 00:00:00 GMT", Some(3_124_224_000)),
+            ("Thu, 01 Jan 1970 00:00:00 GMT", Some(0)),
+            // Before 1970 counts as already expired
+            ("Wed, 31 Dec 1969 23:59:59 GMT", Some(0)),
+            ("Wed, 09 Jun 2021 GMT", None),
+            ("Wed, 09 2021 10:18:14 GMT", None),
+            ("Wed, Jun 2021 10:18:14 GMT", None),
+            ("Wed, 09 Jun 10:18:14 GMT", None),
+            ("Wed, 32 Jun 2021 10:18:14 GMT", None),
+            ("Wed, 09 Jun 2021 24:00:00 GMT", None),
+            ("Wed, 09 Jun 1600 10:18:14 GMT", None),
+            ("", None),
+        ];
+        for (date, secs) in cases.iter() {
+            let expected = secs.map(|s| UNIX_EPOCH + Duration::from_secs(s));
+            assert_eq!(parse_cookie_date(date), expected, "{}", date);
+        }
+    }
+
+    #[test]
+    fn domain_match_test() {
+        let cases = [
+            ("example.com", "example.com", true),
+            ("www.example.com", "example.com", true),
+            ("a.b.example.com", "example.com", true),
+            ("example.com", "www.example.com", false),
+            ("badexample.com", "example.com", false),
+            ("example.com.evil", "example.com", false),
+            ("127.0.0.1", "127.0.0.1", true),
+            ("192.168.0.1", "168.0.1", false),
+        ];
+        for (host, domain, matches) in cases.iter() {
+            assert_eq!(domain_match(host, domain), *matches, "{} {}", host, domain);
+        }
+    }
+
+    #[test]
+    fn path_match_test() {
+        let cases = [
+            ("/", "/", true),
+            ("/docs", "/", true),
+            ("/docs", "/docs", true),
+            ("/docs/", "/docs", true),
+            ("/docs/a", "/docs", true),
+            ("/docs/a", "/docs/", true),
+            ("/docsets", "/docs", false),
+            ("/doc", "/docs", false),
+            ("/", "/docs", false),
+        ];
+        for (path, cookie_path, matches) in cases.iter() {
+            assert_eq!(
+                path_match(path, cookie_path),
+                *matches,
+                "{} {}",
+                path,
+                cookie_path
+            );
+        }
+        let defaults = [
+            ("", "/"),
+            ("/", "/"),
+            ("/docs", "/"),
+            ("/docs/", "/docs"),
+            ("/docs/a/b", "/docs/a"),
+        ];
+        for (path, expected) in defaults.iter() {
+            assert_eq!(default_path(path), *expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn parse_test() {
+        let url: Uri = "https://www.example.com/docs/a".parse().unwrap();
+        let now = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let cookie = Cookie::parse(&url, "id=1", now).unwrap();
+        assert_eq!(cookie.domain, "www.example.com");
+        assert!(cookie.host_only);
+        assert_eq!(cookie.path, "/docs");
+        assert_eq!(cookie.expires, None);
+
+        let header = "id = 1; Domain=.Example.COM; Path=/; Secure; HttpOnly; \
+                      Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=60";
+        let cookie = Cookie::parse(&url, header, now).unwrap();
+        assert_eq!(cookie.to_string(), "id=1");
+        assert_eq!(cookie.domain, "example.com");
+        assert!(!cookie.host_only);
+        assert_eq!(cookie.path, "/");
+        assert!(cookie.secure && cookie.http_only);
+        assert_eq!(cookie.expires, Some(now + Duration::from_secs(60)));
+
+        let cookie = Cookie::parse(&url, "id=1; Max-Age=0", now).unwrap();
+        assert!(cookie.is_expired(now));
+        let cookie = Cookie::parse(&url, "id=1; Path=docs", now).unwrap();
+        assert_eq!(cookie.path, "/docs");
+
+        assert_eq!(Cookie::parse(&url, "id=1; Domain=other.com", now), None);
+        assert_eq!(
+            Cookie::parse(&url, "id=1; Domain=a.www.example.com", now),
+            None
+        );
+        assert_eq!(Cookie::parse(&url, "=1", now), None);
+        assert_eq!(Cookie::parse(&url, "id", now), None);
+    }
+
+    #[test]
+    fn matches_test() {
+        let now = UNIX_EPOCH;
+        let set_by =
+            |url: &str, header: &str| Cookie::parse(&url.parse().unwrap(), header, now).unwrap();
+        let host_only = set_by("http://example.com/", "id=1");
+        let domain = set_by("http://example.com/", "id=1; Domain=example.com");
+        let secure = set_by("https://example.com/docs/", "id=1; Secure");
+        let cases = [
+            (&host_only, "http://example.com/x", true),
+            (&host_only, "http://EXAMPLE.com:8080/", true),
+            (&host_only, "http://www.example.com/", false),
+            (&domain, "http://www.example.com/", true),
+            (&domain, "http://otherexample.com/", false),
+            (&secure, "https://example.com/docs/a", true),
+            (&secure, "https://example.com/other", false),
+            (&secure, "http://example.com/docs/a", false),
+        ];
+        for (cookie, url, matches) in cases.iter() {
+            let url: Uri = url.parse().unwrap();
+            assert_eq!(cookie.matches(&url), *matches, "{:?} {}", cookie, url);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A UTC date and time in the proleptic Gregorian calendar, for the dates sent in headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct DateTime {
+    pub(crate) year: i64,
+    /// 1 to 12
+    pub(crate) month: u32,
+    /// 1 to 31
+    pub(crate) day: u32,
+    pub(crate) hour: u32,
+    pub(crate) minute: u32,
+    pub(crate) second: u32,
+}
+
+impl DateTime {
+    /// Times before 1970 are taken as 1970-01-01
+    pub(crate) fn from_system_time(t: SystemTime) -> DateTime {
+        let secs = t.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let secs = (secs % 86400) as u32;
+        DateTime {
+            year,
+            month,
+            day,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+        }
+    }
+
+    /// Dates before 1970 are taken as 1970-01-01
+    pub(crate) fn to_system_time(self) -> SystemTime {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let secs = days * 86400 + i64::from(self.hour * 3600 + self.minute * 60 + self.second);
+        if secs < 0 {
+            return UNIX_EPOCH;
+        }
+        UNIX_EPOCH + Duration::from_secs(secs as u64)
+    }
+}
+
+/// Days from 1970-01-01 to a date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Date of a day counted from 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn date_time_test() {
+        let cases = [
+            (0, (1970, 1, 1, 0, 0, 0)),
+            (946_684_799, (1999, 12, 31, 23, 59, 59)),
+            (951_782_400, (2000, 2, 29, 0, 0, 0)),
+            (1_623_233_894, (2021, 6, 9, 10, 18, 14)),
+            (4_102_444_800, (2100, 1, 1, 0, 0, 0)),
+        ];
+        for (secs, (year, month, day, hour, minute, second)) in cases.iter() {
+            let t = UNIX_EPOCH + Duration::from_secs(*secs);
+            let date = DateTime {
+                year: *year,
+                month: *month,
+                day: *day,
+                hour: *hour,
+                minute: *minute,
+                second: *second,
+            };
+            assert_eq!(DateTime::from_system_time(t), date, "{}", secs);
+            assert_eq!(date.to_system_time(), t, "{:?}", date);
+        }
+        let before_epoch = DateTime {
+            year: 1969,
+            month: 12,
+            day: 31,
+            hour: 23,
+            minute: 59,
+            second: 59,
+        };
+        assert_eq!(before_epoch.to_system_time(), UNIX_EPOCH);
+    }
+}
@@ -1,13 +1,41 @@
 use crate::client::Metric;
+use crate::context;
+use crate::events::{self, Event};
 use crate::FutureResponse;
 use futures::prelude::*;
 use hyper::client::connect::dns::Name;
 use hyper::service::Service;
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs};
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
 use std::task::Context;
 use std::task::Poll;
+use thiserror::Error;
 use tracer_metrics::{CollectorHandle, Stopwatch};
 
+/// Failure to resolve a host name to any address
+#[derive(Debug, Error)]
+#[error("could not resolve {name}: {source}")]
+pub struct DnsError {
+    pub name: String,
+    #[source]
+    pub source: io::Error,
+}
+
+impl DnsError {
+    pub fn new<S: Into<String>>(name: S, source: io::Error) -> DnsError {
+        DnsError {
+            name: name.into(),
+            source,
+        }
+    }
+}
+
+impl From<DnsError> for io::Error {
+    fn from(e: DnsError) -> io::Error {
+        io::Error::new(e.source.kind(), e)
+    }
+}
+
 #[derive(Clone)]
 pub struct TracingResolver {
     collector: CollectorHandle<Metric>,
@@ -28,13 +56,12 @@ impl Service<Name> for TracingResolver {
             if let Some(addrs) = try_parse_ipaddr(&name) {
                 return Ok(addrs);
             }
-            tokio::task::spawn_blocking(move || {
-                let stopwatch = Stopwatch::new();
-                let ipaddrs = resolve(&name);
-                collector.send(stopwatch.elapsed(Metric::Dns));
-                ipaddrs
-            })
-            .await?
+            events::emit(Event::DnsResolutionStarted);
+            let stopwatch = Stopwatch::new();
+            let ipaddrs = tokio::task::spawn_blocking(move || resolve(&name)).await?;
+            context::send(&collector, stopwatch.elapsed(Metric::Dns));
+            events::emit(Event::DnsResolutionFinished);
+            ipaddrs
         }
         .boxed()
     }
@@ -59,23 +86,22 @@ impl TracingResolver {
 }
 
 fn try_parse_ipaddr(host: &Name) -> Option<IpAddrs> {
-    if let Ok(addr) = host.as_str().parse::<Ipv4Addr>() {
-        let addr = SocketAddrV4::new(addr, 0);
-        Some(IpAddrs {
-            inner: vec![SocketAddr::V4(addr)].into_iter(),
-        })
-    } else if let Ok(addr) = host.as_str().parse::<Ipv6Addr>() {
-        let addr = SocketAddrV6::new(addr, 0, 0, 0);
-        Some(IpAddrs {
-            inner: vec![SocketAddr::V6(addr)].into_iter(),
-        })
-    } else {
-        None
-    }
+    ip_literal(host.as_str()).map(|addr| IpAddrs {
+        inner: vec![SocketAddr::new(addr, 0)].into_iter(),
+    })
+}
+
+/// The address a URL host names directly, if it is an IP literal.  IPv6 hosts may be bracketed.
+pub(crate) fn ip_literal(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
 }
 
 fn resolve(name: &Name) -> Result<IpAddrs, std::io::Error> {
     (name.as_str(), 0)
         .to_socket_addrs()
         .map(|sockets| IpAddrs { inner: sockets })
+        .map_err(|e| DnsError::new(name.as_str(), e).into())
 }
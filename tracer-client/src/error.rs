@@ -0,0 +1,338 @@
+use crate::dns::DnsError;
+use crate::proxy::ProxyError;
+use crate::timeouts::TimeoutError;
+use hyper::Error as HyperError;
+use rustls::TLSError;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Broad classes of request failures, used as metric keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorKind {
+    Dns,
+    ConnectionRefused,
+    ConnectionReset,
+    TlsHandshake,
+    TlsVerification,
+    TlsClientAuth,
+    Proxy,
+    Protocol,
+    Timeout,
+    BodyTruncated,
+    Decompression,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn all() -> &'static [ErrorKind] {
+        &[
+            ErrorKind::Dns,
+            ErrorKind::ConnectionRefused,
+            ErrorKind::ConnectionReset,
+            ErrorKind::TlsHandshake,
+            ErrorKind::TlsVerification,
+            ErrorKind::TlsClientAuth,
+            ErrorKind::Proxy,
+            ErrorKind::Protocol,
+            ErrorKind::Timeout,
+            ErrorKind::BodyTruncated,
+            ErrorKind::Decompression,
+            ErrorKind::Other,
+        ]
+    }
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown failure class {0}")]
+pub struct ErrorKindParseError(String);
+
+/// Parses the kebab case names of failure classes, like `connection-refused`
+impl FromStr for ErrorKind {
+    type Err = ErrorKindParseError;
+
+    fn from_str(s: &str) -> Result<ErrorKind, ErrorKindParseError> {
+        match s {
+            "dns" => Ok(ErrorKind::Dns),
+            "connection-refused" => Ok(ErrorKind::ConnectionRefused),
+            "connection-reset" => Ok(ErrorKind::ConnectionReset),
+            "tls-handshake" => Ok(ErrorKind::TlsHandshake),
+            "tls-verification" => Ok(ErrorKind::TlsVerification),
+            "tls-client-auth" => Ok(ErrorKind::TlsClientAuth),
+            "proxy" => Ok(ErrorKind::Proxy),
+            "protocol" => Ok(ErrorKind::Protocol),
+            "timeout" => Ok(ErrorKind::Timeout),
+            "body-truncated" => Ok(ErrorKind::BodyTruncated),
+            "decompression" => Ok(ErrorKind::Decompression),
+            "other" => Ok(ErrorKind::Other),
+            _ => Err(ErrorKindParseError(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ErrorKind::Dns => "DNS failure",
+            ErrorKind::ConnectionRefused => "Connection refused",
+            ErrorKind::ConnectionReset => "Connection reset",
+            ErrorKind::TlsHandshake => "TLS handshake failure",
+            ErrorKind::TlsVerification => "TLS verification failure",
+            ErrorKind::TlsClientAuth => "TLS client authentication failure",
+            ErrorKind::Proxy => "Proxy failure",
+            ErrorKind::Protocol => "Protocol error",
+            ErrorKind::Timeout => "Timeout",
+            ErrorKind::BodyTruncated => "Body truncated",
+            ErrorKind::Decompression => "Decompression failure",
+            ErrorKind::Other => "Error",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ClientError {
+    #[error("{0}")]
+    Dns(HyperError),
+    #[error("{0}")]
+    ConnectionRefused(HyperError),
+    #[error("{0}")]
+    ConnectionReset(HyperError),
+    #[error("{0}")]
+    TlsHandshake(HyperError),
+    #[error("{0}")]
+    TlsVerification(HyperError),
+    #[error("{0}")]
+    TlsClientAuth(HyperError),
+    #[error("{0}")]
+    Proxy(HyperError),
+    #[error("{0}")]
+    Protocol(HyperError),
+    #[error(transparent)]
+    Timeout(#[from] TimeoutError),
+    #[error("{0}")]
+    BodyTruncated(HyperError),
+    /// The response body couldn't be decoded from its `Content-Encoding`
+    #[error("Couldn't decompress response body: {0}")]
+    Decompression(#[source] io::Error),
+    #[error(transparent)]
+    Other(HyperError),
+    /// Failure of a request sent over HTTP/3, which doesn't go through hyper
+    #[error("{1}")]
+    Quic(ErrorKind, #[source] Box<dyn Error + Send + Sync>),
+}
+
+impl ClientError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ClientError::Dns(_) => ErrorKind::Dns,
+            ClientError::ConnectionRefused(_) => ErrorKind::ConnectionRefused,
+            ClientError::ConnectionReset(_) => ErrorKind::ConnectionReset,
+            ClientError::TlsHandshake(_) => ErrorKind::TlsHandshake,
+            ClientError::TlsVerification(_) => ErrorKind::TlsVerification,
+            ClientError::TlsClientAuth(_) => ErrorKind::TlsClientAuth,
+            ClientError::Proxy(_) => ErrorKind::Proxy,
+            ClientError::Protocol(_) => ErrorKind::Protocol,
+            ClientError::Timeout(_) => ErrorKind::Timeout,
+            ClientError::BodyTruncated(_) => ErrorKind::BodyTruncated,
+            ClientError::Decompression(_) => ErrorKind::Decompression,
+            ClientError::Other(_) => ErrorKind::Other,
+            ClientError::Quic(kind, _) => *kind,
+        }
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        self.timeout().is_some()
+    }
+
+    pub fn timeout(&self) -> Option<&TimeoutError> {
+        match self {
+            ClientError::Timeout(t) => Some(t),
+            _ => None,
+        }
+    }
+
+    /// Errors while reading the response body mean it was cut short
+    pub(crate) fn from_body(e: HyperError) -> ClientError {
+        ClientError::BodyTruncated(e)
+    }
+
+    pub(crate) fn from_quic(e: Box<dyn Error + Send + Sync>) -> ClientError {
+        if let Some(t) = find_cause::<TimeoutError>(&*e) {
+            return ClientError::Timeout(*t);
+        }
+        ClientError::Quic(classify_quic(&*e), e)
+    }
+}
+
+impl From<HyperError> for ClientError {
+    fn from(e: HyperError) -> ClientError {
+        // Connector timeouts arrive wrapped in a hyper connect error
+        if let Some(t) = find_cause::<TimeoutError>(&e) {
+            return ClientError::Timeout(*t);
+        }
+        match classify(&e) {
+            ErrorKind::Dns => ClientError::Dns(e),
+            ErrorKind::ConnectionRefused => ClientError::ConnectionRefused(e),
+            ErrorKind::ConnectionReset => ClientError::ConnectionReset(e),
+            ErrorKind::TlsHandshake => ClientError::TlsHandshake(e),
+            ErrorKind::TlsVerification => ClientError::TlsVerification(e),
+            ErrorKind::TlsClientAuth => ClientError::TlsClientAuth(e),
+            ErrorKind::Proxy => ClientError::Proxy(e),
+            ErrorKind::Protocol => ClientError::Protocol(e),
+            ErrorKind::BodyTruncated => ClientError::BodyTruncated(e),
+            ErrorKind::Timeout | ErrorKind::Decompression | ErrorKind::Other => {
+                ClientError::Other(e)
+            }
+        }
+    }
+}
+
+/// Raised by `TracingHttpsConnector` when the TLS handshake doesn't complete
+#[derive(Debug, Error)]
+#[error("TLS handshake failed: {0}")]
+pub struct TlsHandshakeError(#[source] pub io::Error);
+
+/// Search the source chain of an error for a cause of type `T`.
+///
+/// `io::Error` hides the error it wraps from `source()`, so wrapped errors are unpacked as well.
+pub fn find_cause<'a, T: Error + 'static>(err: &'a (dyn Error + 'static)) -> Option<&'a T> {
+    let mut cur = Some(err);
+    while let Some(e) = cur {
+        if let Some(t) = e.downcast_ref::<T>() {
+            return Some(t);
+        }
+        if let Some(t) = e
+            .downcast_ref::<io::Error>()
+            .and_then(|io| io.get_ref())
+            .and_then(|inner| find_cause::<T>(inner))
+        {
+            return Some(t);
+        }
+        cur = e.source();
+    }
+    None
+}
+
+fn find_io_kind(err: &(dyn Error + 'static)) -> Option<io::ErrorKind> {
+    let mut cur = Some(err);
+    while let Some(e) = cur {
+        if let Some(io) = e.downcast_ref::<io::Error>() {
+            return Some(io.kind());
+        }
+        cur = e.source();
+    }
+    None
+}
+
+fn classify(err: &HyperError) -> ErrorKind {
+    if find_cause::<TimeoutError>(err).is_some() {
+        return ErrorKind::Timeout;
+    }
+    if find_cause::<DnsError>(err).is_some() {
+        return ErrorKind::Dns;
+    }
+    if let Some(tls) = find_cause::<TLSError>(err) {
+        return match tls {
+            TLSError::WebPKIError(_)
+            | TLSError::InvalidSCT(_)
+            | TLSError::NoCertificatesPresented => ErrorKind::TlsVerification,
+            TLSError::AlertReceived(alert) if is_client_auth_alert(alert.get_u8()) => {
+                ErrorKind::TlsClientAuth
+            }
+            _ => ErrorKind::TlsHandshake,
+        };
+    }
+    if find_cause::<TlsHandshakeError>(err).is_some() {
+        return ErrorKind::TlsHandshake;
+    }
+    if find_cause::<ProxyError>(err).is_some() {
+        return ErrorKind::Proxy;
+    }
+    match find_io_kind(err) {
+        Some(io::ErrorKind::ConnectionRefused) => return ErrorKind::ConnectionRefused,
+        Some(io::ErrorKind::ConnectionReset)
+        | Some(io::ErrorKind::ConnectionAborted)
+        | Some(io::ErrorKind::BrokenPipe)
+        | Some(io::ErrorKind::UnexpectedEof) => return ErrorKind::ConnectionReset,
+        _ => {}
+    }
+    if err.is_incomplete_message() {
+        ErrorKind::ConnectionReset
+    } else if err.is_parse() {
+        ErrorKind::Protocol
+    } else {
+        ErrorKind::Other
+    }
+}
+
+fn classify_quic(err: &(dyn Error + 'static)) -> ErrorKind {
+    if find_cause::<DnsError>(err).is_some() {
+        return ErrorKind::Dns;
+    }
+    if let Some(e) = find_cause::<quinn::ConnectionError>(err) {
+        return classify_quic_connection(e);
+    }
+    if let Some(e) = find_cause::<h3::error::StreamError>(err) {
+        return match e {
+            h3::error::StreamError::ConnectionError { 0: e, .. } => classify_h3_connection(e),
+            h3::error::StreamError::RemoteTerminate { .. } => ErrorKind::ConnectionReset,
+            _ => ErrorKind::Protocol,
+        };
+    }
+    if let Some(e) = find_cause::<h3::error::ConnectionError>(err) {
+        return classify_h3_connection(e);
+    }
+    ErrorKind::Other
+}
+
+fn classify_quic_connection(err: &quinn::ConnectionError) -> ErrorKind {
+    use quinn::ConnectionError::*;
+    match err {
+        TransportError(e) => match u64::from(e.code) {
+            // TLS alerts are carried as crypto errors, 0x100 + alert
+            // bad_certificate to certificate_unknown, and unknown_ca
+            0x12a..=0x12e | 0x130 => ErrorKind::TlsVerification,
+            0x100..=0x1ff => ErrorKind::TlsHandshake,
+            _ => ErrorKind::Protocol,
+        },
+        VersionMismatch => ErrorKind::Protocol,
+        // Alerts sent by the server arrive as the code it closed the connection with
+        ConnectionClosed(close) => match u64::from(close.error_code) {
+            code @ 0x100..=0x1ff if is_client_auth_alert((code - 0x100) as u8) => {
+                ErrorKind::TlsClientAuth
+            }
+            0x100..=0x1ff => ErrorKind::TlsHandshake,
+            _ => ErrorKind::ConnectionReset,
+        },
+        ApplicationClosed(_) | Reset => ErrorKind::ConnectionReset,
+        TimedOut => ErrorKind::Timeout,
+        _ => ErrorKind::Other,
+    }
+}
+
+/// Alerts a server sends when it rejects the client's certificate, or wanted one and got none:
+/// bad_certificate to certificate_unknown, unknown_ca, access_denied and certificate_required
+fn is_client_auth_alert(alert: u8) -> bool {
+    matches!(alert, 0x2a..=0x2e | 0x30 | 0x31 | 0x74)
+}
+
+fn classify_h3_connection(err: &h3::error::ConnectionError) -> ErrorKind {
+    use h3::error::ConnectionError::*;
+    use h3::quic::ConnectionErrorIncoming;
+    // h3's error variants are non-exhaustive, so they are matched as structs
+    match err {
+        Timeout { .. }
+        | Remote {
+            0: ConnectionErrorIncoming::Timeout,
+            ..
+        } => ErrorKind::Timeout,
+        Remote {
+            0: ConnectionErrorIncoming::ApplicationClose { .. },
+            ..
+        } => ErrorKind::ConnectionReset,
+        _ => ErrorKind::Protocol,
+    }
+}
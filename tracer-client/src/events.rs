@@ -1,7 +1,18 @@
+use crate::context;
 use crossbeam::queue::SegQueue;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
+/// Record `e` for the request being made by the current task, if any
+pub(crate) fn emit(e: Event) {
+    context::with_current(|c| c.events.add(e));
+}
+
+/// Record `e` as having happened at `t`, see `emit`
+pub(crate) fn emit_at(e: Event, t: Instant) {
+    context::with_current(|c| c.events.add_at(e, t));
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Event {
     Initiated,
@@ -11,9 +22,17 @@ pub enum Event {
     Connected,
     TlsNegotiationStarted,
     TlsNegotiated,
+    /// The last of the request was handed to the connection
+    RequestWritten,
+    /// The first byte of the response arrived
+    FirstByte,
     HeadersReceived,
+    /// The first chunk of the response body was read
+    FirstBodyByte,
     FullResponse,
     ConnectionError,
+    /// The request failed, for any reason including `ConnectionError`s
+    RequestFailed,
 }
 
 #[derive(Debug, Default)]
@@ -31,21 +50,29 @@ impl EventCollector {
     }
 
     pub fn add(&self, e: Event) {
+        self.add_at(e, Instant::now());
+    }
+
+    /// Add an event that happened earlier, such as one noticed by the connection's IO
+    pub fn add_at(&self, e: Event, t: Instant) {
         let collector = self.0.clone();
-        collector.push((e, Instant::now()));
+        collector.push((e, t));
     }
 
+    /// Take the events collected so far, in the order they happened
     pub fn drain_events(&self) -> EventSet {
         let collector = self.0.clone();
         let mut r = Vec::new();
-        while let Some((e, t)) = collector.try_pop() {
+        while let Ok((e, t)) = collector.pop() {
             r.push((e, t));
         }
+        r.sort_by_key(|&(_e, t)| t);
         EventSet(r)
     }
 }
 
-#[derive(Debug)]
+/// The timeline of a single request, attached to the responses `Client` returns
+#[derive(Debug, Clone, Default)]
 pub struct EventSet(Vec<(Event, Instant)>);
 
 impl IntoIterator for EventSet {
@@ -85,7 +112,7 @@ impl EventSet {
                     Some(None)
                 }
             })
-            .filter_map(|opt| opt) // Unwrap a layer from above, ignoring any `None` elements
+            .flatten() // Unwrap a layer from above, ignoring any `None` elements
     }
 
     pub fn initiated_at(&self) -> Option<Instant> {
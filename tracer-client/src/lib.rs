@@ -1,8 +1,23 @@
+pub mod auth;
+pub mod body;
 pub mod client;
+pub mod compression;
 pub mod connectors;
+pub mod context;
+pub mod cookies;
+mod date;
 pub mod dns;
+pub mod error;
+pub mod events;
+pub mod proxy;
+pub mod redirect;
+pub mod retry;
+pub mod socket;
+pub mod timeouts;
+pub mod tls;
 
-pub use crate::client::Client;
+pub use crate::client::{Client, ClientOptions, HttpVersion};
+pub use crate::error::{ClientError, ErrorKind};
 use std::future::Future;
 use std::pin::Pin;
 pub(crate) type FutureResponse<T, E> = Pin<Box<dyn Future<Output = Result<T, E>> + Send>>;
@@ -0,0 +1,314 @@
+use crate::auth::AWS_SIGNING_HEADERS;
+use crate::context::RequestMetrics;
+use crate::events::EventSet;
+use hyper::body::Bytes;
+use hyper::header::{
+    HeaderMap, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST, LOCATION,
+    TRANSFER_ENCODING,
+};
+use hyper::http::{Method, Request, StatusCode, Version};
+use hyper::{Body, Uri};
+use std::str::FromStr;
+use std::time::Duration;
+use thiserror::Error;
+
+/// How the method of a request changes when it is redirected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MethodRewrite {
+    /// As browsers do: POST becomes GET on 301 and 302, and anything but HEAD becomes GET on 303
+    #[default]
+    Browser,
+    /// Only 303 changes the method, to GET
+    Strict,
+    /// The method is never changed
+    Keep,
+}
+
+#[derive(Debug, Error)]
+#[error("Unknown redirect method rule {0}, expected browser, strict or keep")]
+pub struct MethodRewriteError(String);
+
+impl FromStr for MethodRewrite {
+    type Err = MethodRewriteError;
+
+    fn from_str(s: &str) -> Result<MethodRewrite, MethodRewriteError> {
+        match s {
+            "browser" => Ok(MethodRewrite::Browser),
+            "strict" => Ok(MethodRewrite::Strict),
+            "keep" => Ok(MethodRewrite::Keep),
+            _ => Err(MethodRewriteError(s.to_string())),
+        }
+    }
+}
+
+/// Which redirects `Client::request_fully` and `Client::request_streaming` follow
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RedirectOptions {
+    /// Most redirects followed for a request, none are followed when 0.  The last redirect
+    /// response is returned once there have been this many.
+    pub max_redirects: usize,
+    /// Send the request body again when the method is kept, as on 307 and 308.  Otherwise
+    /// such redirects aren't followed.
+    pub resend_body: bool,
+    /// Keep sending `Authorization`, `Cookie` and AWS signing headers when redirected to
+    /// another host
+    pub trusted: bool,
+    pub method: MethodRewrite,
+}
+
+impl Default for RedirectOptions {
+    fn default() -> RedirectOptions {
+        RedirectOptions {
+            max_redirects: 0,
+            resend_body: true,
+            trusted: false,
+            method: MethodRewrite::default(),
+        }
+    }
+}
+
+/// A response that redirected the request elsewhere
+#[derive(Debug, Clone)]
+pub struct RedirectHop {
+    pub url: Uri,
+    pub status: StatusCode,
+    pub metrics: RequestMetrics,
+    pub timeline: EventSet,
+}
+
+/// The redirects followed to get to a response, attached to it when there were any
+#[derive(Debug, Clone)]
+pub struct Redirects {
+    /// In the order they were followed
+    pub hops: Vec<RedirectHop>,
+    /// Where the response came from in the end
+    pub url: Uri,
+    /// Time from the start of the first request until the final response was read
+    pub total: Duration,
+}
+
+/// A request that can be sent again, with changes, for each redirect
+#[derive(Debug, Clone)]
+pub(crate) struct Hop {
+    pub(crate) method: Method,
+    pub(crate) uri: Uri,
+    pub(crate) version: Version,
+    pub(crate) headers: HeaderMap,
+    pub(crate) body: Bytes,
+}
+
+impl Hop {
+    pub(crate) async fn from_request(req: Request<Body>) -> Result<Hop, hyper::Error> {
+        let (parts, body) = req.into_parts();
+        Ok(Hop {
+            method: parts.method,
+            uri: parts.uri,
+            version: parts.version,
+            headers: parts.headers,
+            body: hyper::body::to_bytes(body).await?,
+        })
+    }
+
+    pub(crate) fn request(&self) -> Request<Body> {
+        let mut req = Request::new(Body::from(self.body.clone()));
+        *req.method_mut() = self.method.clone();
+        *req.uri_mut() = self.uri.clone();
+        *req.version_mut() = self.version;
+        *req.headers_mut() = self.headers.clone();
+        req
+    }
+
+    /// The request to send after a response with `status` and `headers`, if it redirects and
+    /// `options` allow following it
+    pub(crate) fn redirect(
+        &self,
+        options: &RedirectOptions,
+        status: StatusCode,
+        headers: &HeaderMap,
+    ) -> Option<Hop> {
+        let rewrite = match (status.as_u16(), options.method) {
+            (301, MethodRewrite::Browser) | (302, MethodRewrite::Browser) => {
+                self.method == Method::POST
+            }
+            (301, _) | (302, _) | (307, _) | (308, _) => false,
+            (303, MethodRewrite::Keep) => false,
+            (303, _) => self.method != Method::HEAD,
+            _ => return None,
+        };
+        let location = headers.get(LOCATION)?.to_str().ok()?;
+        let uri = resolve(&self.uri, location)?;
+        let mut next = Hop {
+            uri,
+            ..self.clone()
+        };
+        if rewrite {
+            next.method = Method::GET;
+            next.body = Bytes::new();
+            for name in &[CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING] {
+                next.headers.remove(name);
+            }
+        } else if !next.body.is_empty() && !options.resend_body {
+            return None;
+        }
+        if !same_origin(&self.uri, &next.uri) {
+            next.headers.remove(HOST);
+            if !options.trusted {
+                next.headers.remove(AUTHORIZATION);
+                next.headers.remove(COOKIE);
+                for name in &AWS_SIGNING_HEADERS {
+                    next.headers.remove(*name);
+                }
+            }
+        }
+        Some(next)
+    }
+}
+
+pub(crate) fn same_origin(a: &Uri, b: &Uri) -> bool {
+    a.scheme() == b.scheme()
+        && a.host() == b.host()
+        && a.port_u16().or_else(|| default_port(a)) == b.port_u16().or_else(|| default_port(b))
+}
+
+pub(crate) fn default_port(uri: &Uri) -> Option<u16> {
+    match uri.scheme_str() {
+        Some("http") => Some(80),
+        Some("https") => Some(443),
+        _ => None,
+    }
+}
+
+/// Resolve a `Location` header against the URL it was received from, as in RFC 3986 §5.2
+fn resolve(base: &Uri, location: &str) -> Option<Uri> {
+    // Fragments aren't sent to servers
+    let location = location.split('#').next()?;
+    let (reference, query) = location.split_at(location.find('?').unwrap_or(location.len()));
+    if let Ok(uri) = reference.parse::<Uri>() {
+        if let (Some(scheme), Some(authority)) = (uri.scheme_str(), uri.authority()) {
+            return join(scheme, authority.as_str(), uri.path(), query);
+        }
+    }
+    let scheme = base.scheme_str()?;
+    if let Some(rest) = reference.strip_prefix("//") {
+        let (authority, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        return join(scheme, authority, path, query);
+    }
+    let authority = base.authority()?.as_str();
+    if reference.is_empty() {
+        // The same path, with a new query or the current one
+        let query = match (query, base.query()) {
+            ("", Some(q)) => format!("?{}", q),
+            _ => query.to_string(),
+        };
+        return join(scheme, authority, base.path(), &query);
+    }
+    if reference.starts_with('/') {
+        return join(scheme, authority, reference, query);
+    }
+    // Relative to the directory of the current path
+    let path = base.path();
+    let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+    join(scheme, authority, &format!("{}{}", dir, reference), query)
+}
+
+fn join(scheme: &str, authority: &str, path: &str, query: &str) -> Option<Uri> {
+    format!(
+        "{}://{}{}{}",
+        scheme,
+        authority,
+        remove_dot_segments(path),
+        query
+    )
+    .parse()
+    .ok()
+}
+
+/// Drop the `.` and `..` segments from a path, as in RFC 3986 §5.2.4.  A `..` never goes
+/// above the root, and a path ending in either keeps its trailing slash.
+fn remove_dot_segments(path: &str) -> String {
+    let mut output: Vec<&str> = Vec::new();
+    let mut segments = path.split('/').peekable();
+    while let Some(segment) = segments.next() {
+        match segment {
+            "." | ".." => {
+                // The leading empty segment of an absolute path is the root
+                if segment == ".." && output.len() > 1 {
+                    output.pop();
+                }
+                if segments.peek().is_none() {
+                    output.push("");
+                }
+            }
+            _ => output.push(segment),
+        }
+    }
+    output.join("/")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn resolve_test() {
+        // The examples from RFC 3986 §5.4, less those only differing in fragments
+        let base: Uri = "http://a/b/c/d;p?q".parse().unwrap();
+        let cases = [
+            ("https://g/x", "https://g/x"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g/"),
+            ("//g:8080/x", "http://g:8080/x"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q"),
+            ("g?y#s", "http://a/b/c/g?y"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../g", "http://a/g"),
+            ("../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("./g/.", "http://a/b/c/g/"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/./y", "http://a/b/c/g;x=1/y"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+            ("g?y/./x", "http://a/b/c/g?y/./x"),
+            ("http://x/a/../b/./c", "http://x/b/c"),
+        ];
+        for (location, expected) in cases.iter() {
+            let expected = expected.parse::<Uri>().unwrap();
+            assert_eq!(resolve(&base, location), Some(expected), "{}", location);
+        }
+    }
+
+    #[test]
+    fn same_origin_test() {
+        let cases = [
+            ("http://a/x", "http://a/y?z", true),
+            ("http://a/", "http://a:80/", true),
+            ("https://a:443/", "https://a/", true),
+            ("http://a/", "https://a/", false),
+            ("http://a/", "http://b/", false),
+            ("http://a/", "http://a:8080/", false),
+            ("http://a:8080/", "http://a:8081/", false),
+            ("https://a/", "https://a:80/", false),
+        ];
+        for (a, b, same) in cases.iter() {
+            let (a, b) = (a.parse().unwrap(), b.parse().unwrap());
+            assert_eq!(same_origin(&a, &b), *same, "{} {}", a, b);
+            assert_eq!(same_origin(&b, &a), *same, "{} {}", b, a);
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use crate::context::RequestMetrics;
+use crate::error::{ClientError, ErrorKind};
+use crate::events::EventSet;
+use hyper::http::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+/// When `Client::request_fully` and `Client::request_streaming` send a request again
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetryOptions {
+    /// Most times a request is sent, including the first.  Requests aren't retried when this
+    /// is 1 or less.
+    pub max_attempts: usize,
+    /// Failures worth another attempt.  Failures after part of the response body was passed
+    /// on aren't retried.
+    pub errors: Vec<ErrorKind>,
+    /// Response statuses worth another attempt.  The response is returned as it is once
+    /// there are no attempts left.
+    pub statuses: Vec<StatusCode>,
+    /// Wait before the first retry, doubled for each retry after it
+    pub backoff: Duration,
+    /// Longest wait before a retry
+    pub max_backoff: Duration,
+    /// Wait a random time between half and all of the backoff, so clients that failed
+    /// together don't retry together
+    pub jitter: bool,
+}
+
+impl Default for RetryOptions {
+    fn default() -> RetryOptions {
+        RetryOptions {
+            max_attempts: 1,
+            errors: vec![
+                ErrorKind::ConnectionRefused,
+                ErrorKind::ConnectionReset,
+                ErrorKind::Timeout,
+            ],
+            statuses: vec![
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+        }
+    }
+}
+
+impl RetryOptions {
+    pub fn enabled(&self) -> bool {
+        self.max_attempts > 1
+    }
+
+    pub(crate) fn retries_error(&self, e: &ClientError) -> bool {
+        self.errors.contains(&e.kind())
+    }
+
+    pub(crate) fn retries_status(&self, status: StatusCode) -> bool {
+        self.statuses.contains(&status)
+    }
+
+    /// Wait before retry number `retry`, counting from 1
+    pub fn backoff(&self, retry: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(retry.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let delay = self
+            .backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |d| d.min(self.max_backoff));
+        if self.jitter {
+            delay / 2 + delay.mul_f64(fastrand::f64() / 2.0)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Why an attempt was retried
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetryReason {
+    /// The attempt failed, with this error message
+    Error(ErrorKind, String),
+    Status(StatusCode),
+}
+
+impl fmt::Display for RetryReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RetryReason::Error(kind, message) => write!(f, "{} {}", kind, message),
+            RetryReason::Status(status) => write!(f, "{}", status),
+        }
+    }
+}
+
+/// An attempt at a request that was retried
+#[derive(Debug, Clone)]
+pub struct RetryAttempt {
+    pub reason: RetryReason,
+    pub metrics: RequestMetrics,
+    pub timeline: EventSet,
+    /// Wait after this attempt before the next one was sent
+    pub backoff: Duration,
+}
+
+/// The attempts retried before a response, attached to it when there were any
+#[derive(Debug, Clone)]
+pub struct Retries {
+    /// In the order they were sent
+    pub attempts: Vec<RetryAttempt>,
+    /// Time from the start of the first attempt until the final response was read
+    pub total: Duration,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff_test() {
+        let options = RetryOptions {
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(1),
+            jitter: false,
+            ..RetryOptions::default()
+        };
+        let delays: Vec<u64> = (1..=6)
+            .map(|retry| options.backoff(retry).as_millis() as u64)
+            .collect();
+        assert_eq!(vec![100, 200, 400, 800, 1000, 1000], delays);
+        assert_eq!(Duration::from_millis(100), options.backoff(0));
+        // Doubling past what a Duration holds stays at the longest wait
+        assert_eq!(Duration::from_secs(1), options.backoff(40));
+        assert_eq!(Duration::from_secs(1), options.backoff(u32::MAX));
+
+        let jittered = RetryOptions {
+            jitter: true,
+            ..options.clone()
+        };
+        for retry in 1..=6 {
+            let full = options.backoff(retry);
+            let delay = jittered.backoff(retry);
+            assert!(delay >= full / 2 && delay <= full, "{} {:?}", retry, delay);
+        }
+    }
+
+    #[test]
+    fn retries_test() {
+        let options = RetryOptions::default();
+        assert!(!options.enabled());
+        assert!(RetryOptions {
+            max_attempts: 2,
+            ..RetryOptions::default()
+        }
+        .enabled());
+        assert!(options.retries_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(!options.retries_status(StatusCode::INTERNAL_SERVER_ERROR));
+        assert!(!options.retries_status(StatusCode::OK));
+        let error = |kind| ClientError::Quic(kind, "failed".into());
+        assert!(options.retries_error(&error(ErrorKind::ConnectionRefused)));
+        assert!(options.retries_error(&error(ErrorKind::Timeout)));
+        assert!(!options.retries_error(&error(ErrorKind::TlsVerification)));
+        assert!(!options.retries_error(&error(ErrorKind::BodyTruncated)));
+    }
+
+    #[test]
+    fn reason_test() {
+        let status = RetryReason::Status(StatusCode::BAD_GATEWAY);
+        assert_eq!("502 Bad Gateway", status.to_string());
+        let error = RetryReason::Error(ErrorKind::ConnectionReset, "reset by peer".to_string());
+        assert_eq!(
+            format!("{} reset by peer", ErrorKind::ConnectionReset),
+            error.to_string()
+        );
+    }
+}
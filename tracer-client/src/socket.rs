@@ -0,0 +1,318 @@
+use socket2::{Domain, Protocol, SockAddr, Socket, TcpKeepalive, Type};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+/// How TCP connections are set up.  Only `local_address` and `local_port` apply to QUIC.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketOptions {
+    /// Source address to connect from, which also picks the address family of the destination
+    pub local_address: Option<IpAddr>,
+    /// Source port to connect from, any free one when `None`
+    pub local_port: Option<u16>,
+    /// Network interface to send through, by name.  Linux only.
+    pub interface: Option<String>,
+    /// Send small writes immediately instead of waiting to fill a packet (Nagle's algorithm)
+    pub nodelay: bool,
+    /// Idle time before TCP keepalive probes are sent, keepalive is off when `None`
+    pub keepalive: Option<Duration>,
+    pub send_buffer_size: Option<usize>,
+    pub recv_buffer_size: Option<usize>,
+    /// IP time to live, or hop limit for IPv6
+    pub ttl: Option<u32>,
+    /// Differentiated services code point to mark packets with, 0 to 63
+    pub dscp: Option<u8>,
+}
+
+impl Default for SocketOptions {
+    fn default() -> SocketOptions {
+        SocketOptions {
+            local_address: None,
+            local_port: None,
+            interface: None,
+            nodelay: true,
+            keepalive: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+            ttl: None,
+            dscp: None,
+        }
+    }
+}
+
+impl SocketOptions {
+    /// Whether connections to `addr` can be made from the configured source address
+    pub(crate) fn can_reach(&self, addr: &SocketAddr) -> bool {
+        match self.local_address {
+            Some(local) => local.is_ipv4() == addr.is_ipv4(),
+            None => true,
+        }
+    }
+
+    /// The address to bind to before connecting to `addr`, if one is configured
+    pub(crate) fn local_bind(&self, addr: &SocketAddr) -> io::Result<Option<SocketAddr>> {
+        if !self.can_reach(addr) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "local address {} can't connect to {}",
+                    self.local_address.unwrap(),
+                    addr
+                ),
+            ));
+        }
+        let ip = match (self.local_address, self.local_port) {
+            (None, None) => return Ok(None),
+            (Some(ip), _) => ip,
+            (None, Some(_)) if addr.is_ipv4() => IpAddr::from([0, 0, 0, 0]),
+            (None, Some(_)) => IpAddr::from([0u16; 8]),
+        };
+        Ok(Some(SocketAddr::new(ip, self.local_port.unwrap_or(0))))
+    }
+
+    /// Open a TCP connection to `addr` with these options
+    pub(crate) async fn connect(&self, addr: &SocketAddr) -> io::Result<TcpStream> {
+        let socket = Socket::new(
+            Domain::for_address(*addr),
+            Type::STREAM,
+            Some(Protocol::TCP),
+        )?;
+        if let Some(local) = self.local_bind(addr)? {
+            // A fixed source port is reused by every repetition, despite TIME_WAIT
+            socket.set_reuse_address(true)?;
+            socket.bind(&SockAddr::from(local))?;
+        }
+        if let Some(ref interface) = self.interface {
+            bind_device(&socket, interface)?;
+        }
+        socket.set_tcp_nodelay(self.nodelay)?;
+        if let Some(time) = self.keepalive {
+            socket.set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        match (self.ttl, addr) {
+            (Some(ttl), SocketAddr::V4(_)) => socket.set_ttl_v4(ttl)?,
+            (Some(ttl), SocketAddr::V6(_)) => socket.set_unicast_hops_v6(ttl)?,
+            (None, _) => {}
+        }
+        if let Some(dscp) = self.dscp {
+            set_dscp(&socket, addr, dscp)?;
+        }
+        TcpStream::connect_std(socket.into(), addr).await
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: &Socket, _interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "binding to an interface is only supported on Linux",
+    ))
+}
+
+/// The DSCP is the top six bits of the IPv4 TOS or IPv6 traffic class byte
+#[cfg(target_os = "linux")]
+fn set_dscp(socket: &Socket, addr: &SocketAddr, dscp: u8) -> io::Result<()> {
+    let value = u32::from(dscp) << 2;
+    match addr {
+        SocketAddr::V4(_) => socket.set_tos_v4(value),
+        SocketAddr::V6(_) => socket.set_tclass_v6(value),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_dscp(socket: &Socket, addr: &SocketAddr, dscp: u8) -> io::Result<()> {
+    match addr {
+        SocketAddr::V4(_) => socket.set_tos_v4(u32::from(dscp) << 2),
+        SocketAddr::V6(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "DSCP for IPv6 is only supported on Linux",
+        )),
+    }
+}
+
+/// Kernel statistics for a TCP connection, read with `TCP_INFO`.  Linux only.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TcpInfo {
+    /// Smoothed round trip time
+    pub rtt: Duration,
+    pub rtt_var: Duration,
+    /// Segments retransmitted over the life of the connection
+    pub retransmits: u32,
+    /// Congestion window, in segments
+    pub cwnd: u32,
+    /// `None` on kernels older than 4.1, which don't report it
+    pub bytes_acked: Option<u64>,
+}
+
+/// The start of `struct tcp_info` from linux/tcp.h, up to the fields read here.  The libc
+/// crate's glibc version stops before `tcpi_bytes_acked`.
+#[cfg(target_os = "linux")]
+#[repr(C)]
+#[derive(Default)]
+struct RawTcpInfo {
+    state: u8,
+    ca_state: u8,
+    retransmits: u8,
+    probes: u8,
+    backoff: u8,
+    options: u8,
+    wscale: u8,
+    flags: u8,
+    rto: u32,
+    ato: u32,
+    snd_mss: u32,
+    rcv_mss: u32,
+    unacked: u32,
+    sacked: u32,
+    lost: u32,
+    retrans: u32,
+    fackets: u32,
+    last_data_sent: u32,
+    last_ack_sent: u32,
+    last_data_recv: u32,
+    last_ack_recv: u32,
+    pmtu: u32,
+    rcv_ssthresh: u32,
+    rtt: u32,
+    rttvar: u32,
+    snd_ssthresh: u32,
+    snd_cwnd: u32,
+    advmss: u32,
+    reordering: u32,
+    rcv_rtt: u32,
+    rcv_space: u32,
+    total_retrans: u32,
+    pacing_rate: u64,
+    max_pacing_rate: u64,
+    bytes_acked: u64,
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn tcp_info(fd: std::os::unix::io::RawFd) -> io::Result<TcpInfo> {
+    use std::mem::size_of;
+    let mut raw = RawTcpInfo::default();
+    let mut len = size_of::<RawTcpInfo>() as libc::socklen_t;
+    // Safety: the kernel writes at most `len` bytes to `raw`, and reports how many it wrote
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_INFO,
+            &mut raw as *mut RawTcpInfo as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(TcpInfo {
+        rtt: Duration::from_micros(raw.rtt.into()),
+        rtt_var: Duration::from_micros(raw.rttvar.into()),
+        retransmits: raw.total_retrans,
+        cwnd: raw.snd_cwnd,
+        bytes_acked: if len as usize >= size_of::<RawTcpInfo>() {
+            Some(raw.bytes_acked)
+        } else {
+            None
+        },
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn local_bind_test() {
+        let v4: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        let v6: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        let options = |address: Option<&str>, port: Option<u16>| SocketOptions {
+            local_address: address.map(|a| a.parse().unwrap()),
+            local_port: port,
+            ..SocketOptions::default()
+        };
+        let cases = [
+            (options(None, None), v4, Some(None)),
+            (options(None, None), v6, Some(None)),
+            (
+                options(Some("10.0.0.1"), None),
+                v4,
+                Some(Some("10.0.0.1:0")),
+            ),
+            (
+                options(Some("10.0.0.1"), Some(5000)),
+                v4,
+                Some(Some("10.0.0.1:5000")),
+            ),
+            // Any address of the destination's family, from a fixed port
+            (options(None, Some(5000)), v4, Some(Some("0.0.0.0:5000"))),
+            (options(None, Some(5000)), v6, Some(Some("[::]:5000"))),
+            (options(Some("::1"), None), v6, Some(Some("[::1]:0"))),
+            (options(Some("10.0.0.1"), None), v6, None),
+            (options(Some("::1"), Some(5000)), v4, None),
+        ];
+        for (options, addr, expected) in cases.iter() {
+            let expected = expected.map(|e| e.map(|e| e.parse::<SocketAddr>().unwrap()));
+            let bind = options.local_bind(addr).ok();
+            assert_eq!(bind, expected, "{:?} {}", options.local_address, addr);
+            assert_eq!(options.can_reach(addr), expected.is_some());
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn connect_test() {
+        use socket2::SockRef;
+        use std::os::unix::io::{AsRawFd, BorrowedFd};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = SocketOptions {
+            nodelay: false,
+            keepalive: Some(Duration::from_secs(30)),
+            recv_buffer_size: Some(64 * 1024),
+            ttl: Some(16),
+            dscp: Some(46),
+            ..SocketOptions::default()
+        };
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let stream = rt.block_on(options.connect(&addr)).unwrap();
+        let (_accepted, from) = listener.accept().unwrap();
+        assert_eq!(from, stream.local_addr().unwrap());
+        // Safety: the stream outlives the borrow
+        let fd = unsafe { BorrowedFd::borrow_raw(stream.as_raw_fd()) };
+        let socket = SockRef::from(&fd);
+        assert!(!socket.tcp_nodelay().unwrap());
+        assert!(socket.keepalive().unwrap());
+        assert_eq!(
+            Duration::from_secs(30),
+            socket.tcp_keepalive_time().unwrap()
+        );
+        // The kernel doubles the size asked for, to allow for its own bookkeeping
+        assert!(socket.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert_eq!(16, socket.ttl_v4().unwrap());
+        assert_eq!(46 << 2, socket.tos_v4().unwrap());
+
+        let info = tcp_info(stream.as_raw_fd()).unwrap();
+        assert!(info.cwnd > 0);
+        assert!(info.bytes_acked.is_some());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn raw_tcp_info_test() {
+        // tcpi_bytes_acked is the last 8 of the first 128 bytes of struct tcp_info
+        assert_eq!(128, std::mem::size_of::<RawTcpInfo>());
+    }
+}
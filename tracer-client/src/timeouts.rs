@@ -0,0 +1,98 @@
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Phase of a request that a timeout can be applied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Phase {
+    Dns,
+    Connect,
+    Tls,
+    Headers,
+    BodyRead,
+    Total,
+}
+
+impl Phase {
+    pub fn all() -> &'static [Phase] {
+        &[
+            Phase::Dns,
+            Phase::Connect,
+            Phase::Tls,
+            Phase::Headers,
+            Phase::BodyRead,
+            Phase::Total,
+        ]
+    }
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Phase::Dns => "DNS resolution",
+            Phase::Connect => "connect",
+            Phase::Tls => "TLS handshake",
+            Phase::Headers => "response headers",
+            Phase::BodyRead => "body read",
+            Phase::Total => "request",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Limits for the individual phases of a request.  `None` waits indefinitely.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    pub dns: Option<Duration>,
+    pub connect: Option<Duration>,
+    pub tls: Option<Duration>,
+    /// Time from the start of the request until the response headers are received.  Includes
+    /// setting up a new connection and writing the request, on top of their own limits
+    pub headers: Option<Duration>,
+    /// Longest allowed gap between two chunks of the response body
+    pub body_read: Option<Duration>,
+    /// Time for the whole request, including reading the body
+    pub total: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn get(&self, phase: Phase) -> Option<Duration> {
+        match phase {
+            Phase::Dns => self.dns,
+            Phase::Connect => self.connect,
+            Phase::Tls => self.tls,
+            Phase::Headers => self.headers,
+            Phase::BodyRead => self.body_read,
+            Phase::Total => self.total,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Error)]
+#[error("{phase} timed out after {limit:?}")]
+pub struct TimeoutError {
+    pub phase: Phase,
+    pub limit: Duration,
+}
+
+impl From<TimeoutError> for io::Error {
+    fn from(e: TimeoutError) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, e)
+    }
+}
+
+/// Run a future, failing with a `TimeoutError` for the given phase if it doesn't complete in time.
+pub async fn timeout<F: Future>(
+    phase: Phase,
+    limit: Option<Duration>,
+    fut: F,
+) -> Result<F::Output, TimeoutError> {
+    match limit {
+        Some(limit) => tokio::time::timeout(limit, fut)
+            .await
+            .map_err(|_| TimeoutError { phase, limit }),
+        None => Ok(fut.await),
+    }
+}
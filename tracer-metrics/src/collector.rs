@@ -131,9 +131,9 @@ where
     pub fn snapshot(&self, key: &T) -> Snapshot<T> {
         Snapshot::new(
             key.clone(),
-            self.counters.read().unwrap().get(&key),
-            self.gauges.read().unwrap().get(&key),
-            self.latency_histograms.read().unwrap().get(&key),
+            self.counters.read().unwrap().get(key),
+            self.gauges.read().unwrap().get(key),
+            self.latency_histograms.read().unwrap().get(key),
             self.percentiles.clone(),
         )
     }
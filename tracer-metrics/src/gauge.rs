@@ -32,13 +32,13 @@ where
 
     /// Check if the given interest has been registered with this object
     pub fn interested(&self, key: &T) -> bool {
-        self.data.contains_key(&key)
+        self.data.contains_key(key)
     }
 
     /// Set the gauge with the given key to the given value
     /// If the key hasn't been registered, this is ignored and the `Gauges` object will not be updated.
     pub fn set(&mut self, key: &T, val: u64) {
-        if let Some(v) = self.data.get_mut(&key) {
+        if let Some(v) = self.data.get_mut(key) {
             *v = val;
         }
     }
@@ -46,18 +46,18 @@ where
     /// Set the gauge with the given key to 0
     /// If the key hasn't been registered, this is ignored and the `Gauges` object will not be updated.
     pub fn clear(&mut self, key: &T) {
-        if let Some(v) = self.data.get_mut(&key) {
+        if let Some(v) = self.data.get_mut(key) {
             *v = 0;
         }
     }
 
     /// Get the value of the gauge with the given key, if that key exists
     pub fn get(&self, key: &T) -> Option<u64> {
-        self.data.get(&key).copied()
+        self.data.get(key).copied()
     }
 
     /// Remove (and unregister) the given key
     pub fn remove(&mut self, key: &T) {
-        self.data.remove(&key);
+        self.data.remove(key);
     }
 }
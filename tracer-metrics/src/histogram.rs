@@ -36,7 +36,7 @@ where
 
     /// Check if the given interest has been registered with this object
     pub fn interested(&self, key: &T) -> bool {
-        self.data.contains_key(&key)
+        self.data.contains_key(key)
     }
 
     /// Record a value in the histogram with the given key.
@@ -48,7 +48,7 @@ where
     /// Record multiple occurrences of a value in the histogram with the given key.
     /// If the key hasn't been registered, this is ignored and the `Histograms` object will not be updated.
     pub fn record_multiple(&mut self, key: &T, value: u64, count: u64) {
-        if let Some(h) = self.data.get_mut(&key) {
+        if let Some(h) = self.data.get_mut(key) {
             h.saturating_record_n(value, count);
         }
     }
@@ -56,23 +56,23 @@ where
     /// Clear the histogram with the given key
     /// If the key hasn't been registered, this is ignored and the `Histograms` object will not be updated.
     pub fn clear(&mut self, key: &T) {
-        if let Some(h) = self.data.get_mut(&key) {
+        if let Some(h) = self.data.get_mut(key) {
             h.clear();
         }
     }
 
     /// Get the histogram for the given key, if it exists.
     pub fn get(&self, key: &T) -> Option<Histogram<u64>> {
-        self.data.get(&key).cloned()
+        self.data.get(key).cloned()
     }
 
     /// Get the value at the given **quantile** for the given key, if it exists.
     pub fn quantile(&self, key: &T, q: f64) -> Option<u64> {
-        self.data.get(&key).map(|h| h.value_at_quantile(q))
+        self.data.get(key).map(|h| h.value_at_quantile(q))
     }
 
     /// Remove (and unregister) the histogram with the given key
     pub fn remove(&mut self, key: &T) {
-        self.data.remove(&key);
+        self.data.remove(key);
     }
 }